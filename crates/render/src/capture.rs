@@ -0,0 +1,175 @@
+use std::num::NonZeroU32;
+
+/// A frame read back from the GPU, always stored as tightly packed RGBA8.
+///
+/// The bytes are the values that were presented on screen: sRGB surfaces
+/// store encoded values and non-sRGB surfaces are displayed as if they were,
+/// so the data can be written to an image file without any colour conversion.
+pub struct CapturedFrame {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// Copy of a colour texture into a mappable buffer, recorded in the frame's
+/// command encoder and read once the commands have been submitted.
+pub(crate) struct FrameReadback {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+    bgra: bool,
+}
+
+impl FrameReadback {
+    const BYTES_PER_PIXEL: u32 = 4;
+
+    pub(crate) fn record(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Option<Self> {
+        let bgra = match format {
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            _ => {
+                log::warn!("Frame capture is not supported for {:?} surfaces", format);
+                return None;
+            }
+        };
+
+        // Rows copied into a buffer must be aligned on COPY_BYTES_PER_ROW_ALIGNMENT
+        let unpadded_bytes_per_row = width * Self::BYTES_PER_PIXEL;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (unpadded_bytes_per_row + align - 1) / align * align;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame Capture Buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: NonZeroU32::new(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        Some(Self {
+            buffer,
+            width,
+            height,
+            padded_bytes_per_row,
+            bgra,
+        })
+    }
+
+    /// Blocks until the copy is done. Must be called after the encoder has been submitted.
+    pub(crate) fn read(self, device: &wgpu::Device) -> Option<CapturedFrame> {
+        let slice = self.buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+
+        match receiver.recv() {
+            Ok(Ok(())) => {}
+            _ => {
+                log::error!("Failed to map the frame capture buffer");
+                return None;
+            }
+        }
+
+        let data = unpad_rows(
+            &slice.get_mapped_range(),
+            self.width,
+            self.height,
+            self.padded_bytes_per_row,
+            self.bgra,
+        );
+        self.buffer.unmap();
+
+        Some(CapturedFrame {
+            width: self.width,
+            height: self.height,
+            data,
+        })
+    }
+}
+
+/// Drops the padding at the end of each row of a copied texture and swaps
+/// BGRA pixels to RGBA
+fn unpad_rows(
+    padded: &[u8],
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+    bgra: bool,
+) -> Vec<u8> {
+    let unpadded_bytes_per_row = (width * FrameReadback::BYTES_PER_PIXEL) as usize;
+    let mut data = Vec::with_capacity(unpadded_bytes_per_row * height as usize);
+    for row in padded
+        .chunks(padded_bytes_per_row as usize)
+        .take(height as usize)
+    {
+        data.extend_from_slice(&row[..unpadded_bytes_per_row]);
+    }
+
+    if bgra {
+        for pixel in data.chunks_exact_mut(FrameReadback::BYTES_PER_PIXEL as usize) {
+            pixel.swap(0, 2);
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padded_bgra_rows_are_unpacked() {
+        // 10 pixels make 40 bytes, padded to 256 per row
+        let (width, height) = (10, 2);
+        let padded_bytes_per_row = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let mut padded = vec![0xee; (padded_bytes_per_row * height) as usize];
+        for y in 0..height {
+            for x in 0..width {
+                let i = (y * padded_bytes_per_row + x * 4) as usize;
+                // Blue, green, red, alpha
+                padded[i..i + 4].copy_from_slice(&[x as u8, y as u8, 200, 255]);
+            }
+        }
+
+        let data = unpad_rows(&padded, width, height, padded_bytes_per_row, true);
+        assert_eq!(data.len(), (width * height * 4) as usize);
+        assert_eq!(data[..4], [200, 0, 0, 255]);
+        // Last pixel of the second row, right before the padding
+        assert_eq!(data[data.len() - 4..], [200, 1, 9, 255]);
+        assert!(!data.contains(&0xee));
+
+        let rgba = unpad_rows(&padded, width, height, padded_bytes_per_row, false);
+        assert_eq!(rgba[..4], [0, 0, 200, 255]);
+    }
+}
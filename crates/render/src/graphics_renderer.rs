@@ -3,12 +3,17 @@ use std::iter;
 use wgpu::{CommandEncoder, TextureView};
 use winit::window::Window;
 
+use crate::capture::{CapturedFrame, FrameReadback};
+
 pub struct GraphicsRenderer {
     pub surface: wgpu::Surface,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub size: winit::dpi::PhysicalSize<u32>,
     pub config: wgpu::SurfaceConfiguration,
+    can_capture: bool,
+    capture_requested: bool,
+    captured_frame: Option<CapturedFrame>,
 }
 
 impl GraphicsRenderer {
@@ -47,8 +52,16 @@ impl GraphicsRenderer {
 
         let caps = surface.get_capabilities(&adapter);
         let format = caps.formats[0];
+        // Reading the surface back is needed for frame captures, but WebGL
+        // doesn't allow copying from the canvas texture.
+        let can_capture = !cfg!(target_arch = "wasm32");
+        let usage = if can_capture {
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC
+        } else {
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+        };
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage,
             format: format,
             width: size.width,
             height: size.height,
@@ -67,6 +80,9 @@ impl GraphicsRenderer {
             queue,
             config,
             size,
+            can_capture,
+            capture_requested: false,
+            captured_frame: None,
         }
     }
 
@@ -95,9 +111,43 @@ impl GraphicsRenderer {
             });
 
         render(&view, &mut encoder)?;
+
+        let readback = if self.capture_requested {
+            self.capture_requested = false;
+            FrameReadback::record(
+                &self.device,
+                &mut encoder,
+                &output.texture,
+                self.config.format,
+                self.config.width,
+                self.config.height,
+            )
+        } else {
+            None
+        };
+
         self.queue.submit(iter::once(encoder.finish()));
         output.present();
 
+        if let Some(readback) = readback {
+            self.captured_frame = readback.read(&self.device);
+        }
+
         Ok(())
     }
+
+    /// Asks for the next rendered frame to be copied back to the CPU.
+    /// The result is available with [`GraphicsRenderer::take_captured_frame`]
+    /// once that frame has been rendered.
+    pub fn request_capture(&mut self) {
+        if self.can_capture {
+            self.capture_requested = true;
+        } else {
+            log::warn!("The surface doesn't support frame capture");
+        }
+    }
+
+    pub fn take_captured_frame(&mut self) -> Option<CapturedFrame> {
+        self.captured_frame.take()
+    }
 }
@@ -1,3 +1,4 @@
+pub mod capture;
 pub mod graphics_renderer;
 
 #[cfg(test)]
@@ -0,0 +1,205 @@
+use cgmath::{Quaternion, Vector3, VectorSpace};
+
+use super::{nlerp, Pose};
+
+/// How values are interpolated between two keyframes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Holds each keyframe until the next one
+    Step,
+    Linear,
+}
+
+/// Keyframe values of a channel, one per keyframe time
+#[derive(Debug, Clone, PartialEq)]
+pub enum Keyframes {
+    Translation(Vec<Vector3<f32>>),
+    Rotation(Vec<Quaternion<f32>>),
+    Scale(Vec<Vector3<f32>>),
+}
+
+impl Keyframes {
+    fn len(&self) -> usize {
+        match self {
+            Keyframes::Translation(values) => values.len(),
+            Keyframes::Rotation(values) => values.len(),
+            Keyframes::Scale(values) => values.len(),
+        }
+    }
+}
+
+/// Animates one property of one joint
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    pub joint: usize,
+    /// Increasing keyframe times in seconds
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+    pub interpolation: Interpolation,
+}
+
+impl Channel {
+    /// Keyframes surrounding `time` and how far `time` is between them
+    fn locate(&self, time: f32) -> (usize, usize, f32) {
+        let next = self.times.partition_point(|&t| t <= time);
+        if next == 0 {
+            return (0, 0, 0.0);
+        }
+        if next == self.times.len() {
+            return (next - 1, next - 1, 0.0);
+        }
+        let previous = next - 1;
+        let span = self.times[next] - self.times[previous];
+        let t = match self.interpolation {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear if span > 0.0 => (time - self.times[previous]) / span,
+            Interpolation::Linear => 0.0,
+        };
+        (previous, next, t)
+    }
+}
+
+/// Named marker fired when playback passes its time, e.g. a footstep
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationEvent {
+    pub time: f32,
+    pub name: String,
+}
+
+/// Keyframed joint animation, played back by sampling it into a `Pose`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationClip {
+    pub name: String,
+    /// Time of the last keyframe of every channel
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+    /// Sorted by time
+    pub events: Vec<AnimationEvent>,
+}
+
+impl AnimationClip {
+    /// Channels with fewer values than keyframe times are dropped
+    pub fn new(name: &str, channels: Vec<Channel>) -> Self {
+        let channels: Vec<_> = channels
+            .into_iter()
+            .filter(|channel| {
+                let valid =
+                    !channel.times.is_empty() && channel.keyframes.len() >= channel.times.len();
+                if !valid {
+                    log::warn!(
+                        "Dropping invalid channel of joint {} in {}",
+                        channel.joint,
+                        name
+                    );
+                }
+                valid
+            })
+            .collect();
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        Self {
+            name: name.to_string(),
+            duration,
+            channels,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn with_event(mut self, time: f32, name: &str) -> Self {
+        let index = self.events.partition_point(|event| event.time <= time);
+        self.events.insert(
+            index,
+            AnimationEvent {
+                time,
+                name: name.to_string(),
+            },
+        );
+        self
+    }
+
+    /// Events after `start` up to and including `end`
+    pub(super) fn events_between(
+        &self,
+        start: f32,
+        end: f32,
+    ) -> impl Iterator<Item = &AnimationEvent> {
+        self.events
+            .iter()
+            .filter(move |event| event.time > start && event.time <= end)
+    }
+
+    /// Overwrites the animated properties of `pose` with their value at
+    /// `time`, clamped to the clip. Properties without a channel keep their
+    /// current value.
+    pub fn sample(&self, time: f32, pose: &mut Pose) {
+        let time = time.clamp(0.0, self.duration);
+        for channel in &self.channels {
+            let Some(local) = pose.local.get_mut(channel.joint) else {
+                continue;
+            };
+            let (a, b, t) = channel.locate(time);
+            match &channel.keyframes {
+                Keyframes::Translation(values) => {
+                    local.translation = values[a].lerp(values[b], t);
+                }
+                Keyframes::Rotation(values) => {
+                    local.rotation = nlerp(values[a], values[b], t);
+                }
+                Keyframes::Scale(values) => {
+                    local.scale = values[a].lerp(values[b], t);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::Transform;
+
+    fn clip(interpolation: Interpolation) -> AnimationClip {
+        AnimationClip::new(
+            "move",
+            vec![Channel {
+                joint: 0,
+                times: vec![0.0, 1.0, 3.0],
+                keyframes: Keyframes::Translation(vec![
+                    Vector3::new(0.0, 0.0, 0.0),
+                    Vector3::new(2.0, 0.0, 0.0),
+                    Vector3::new(2.0, 4.0, 0.0),
+                ]),
+                interpolation,
+            }],
+        )
+    }
+
+    fn sample(clip: &AnimationClip, time: f32) -> Vector3<f32> {
+        let mut pose = Pose {
+            local: vec![Transform::IDENTITY],
+        };
+        clip.sample(time, &mut pose);
+        pose.local[0].translation
+    }
+
+    #[test]
+    fn linear_keyframes_are_interpolated() {
+        let clip = clip(Interpolation::Linear);
+        assert_eq!(clip.duration, 3.0);
+        assert_eq!(sample(&clip, 0.5), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(sample(&clip, 2.0), Vector3::new(2.0, 2.0, 0.0));
+        // Clamped past either end
+        assert_eq!(sample(&clip, -1.0), Vector3::new(0.0, 0.0, 0.0));
+        assert_eq!(sample(&clip, 5.0), Vector3::new(2.0, 4.0, 0.0));
+    }
+
+    #[test]
+    fn step_keyframes_hold_until_the_next() {
+        let clip = clip(Interpolation::Step);
+        assert_eq!(sample(&clip, 0.99), Vector3::new(0.0, 0.0, 0.0));
+        assert_eq!(sample(&clip, 1.0), Vector3::new(2.0, 0.0, 0.0));
+        assert_eq!(sample(&clip, 2.9), Vector3::new(2.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+
+use super::{AnimationClip, AnimationState, Pose, Skeleton, StateMachine};
+
+/// Playback position within a state
+#[derive(Debug, Clone, Copy)]
+struct Playback {
+    state: usize,
+    time: f32,
+}
+
+/// State being faded out after a transition
+#[derive(Debug, Clone, Copy)]
+struct Fade {
+    from: Playback,
+    elapsed: f32,
+    duration: f32,
+}
+
+/// State machine blended over the layers below it. Only the joints animated
+/// by its clips are affected, so a layer can override part of the body.
+#[derive(Debug, Clone)]
+pub struct AnimationLayer {
+    /// How much the layer replaces the layers below, from 0 to 1
+    pub weight: f32,
+    machine: StateMachine,
+    current: Playback,
+    fade: Option<Fade>,
+}
+
+impl AnimationLayer {
+    pub fn new(machine: StateMachine, weight: f32) -> Self {
+        Self {
+            weight,
+            machine,
+            current: Playback {
+                state: 0,
+                time: 0.0,
+            },
+            fade: None,
+        }
+    }
+
+    pub fn current_state(&self) -> &AnimationState {
+        &self.machine.states()[self.current.state]
+    }
+
+    /// Cross-fade progress into the current state, 1 once it fully plays
+    pub fn fade_progress(&self) -> f32 {
+        self.fade
+            .map_or(1.0, |fade| (fade.elapsed / fade.duration).min(1.0))
+    }
+
+    /// Advances playback by `dt` seconds, pushing the events passed by the
+    /// current state. Returns whether a non looping state has finished.
+    fn advance(
+        &self,
+        playback: &mut Playback,
+        dt: f32,
+        clips: &[AnimationClip],
+        events: Option<&mut Vec<String>>,
+    ) -> bool {
+        let state = &self.machine.states()[playback.state];
+        let Some(clip) = clips.get(state.clip) else {
+            return true;
+        };
+        let start = playback.time;
+        let end = start + dt * state.speed;
+        let mut fired = Vec::new();
+        let finished = if clip.duration <= 0.0 {
+            playback.time = 0.0;
+            !state.looping
+        } else if state.looping {
+            if end >= clip.duration {
+                fired.extend(clip.events_between(start, clip.duration));
+                playback.time = end.rem_euclid(clip.duration);
+                fired.extend(clip.events_between(f32::NEG_INFINITY, playback.time));
+            } else {
+                fired.extend(clip.events_between(start, end));
+                playback.time = end;
+            }
+            false
+        } else {
+            playback.time = end.min(clip.duration);
+            fired.extend(clip.events_between(start, playback.time));
+            playback.time >= clip.duration
+        };
+        if let Some(events) = events {
+            events.extend(fired.into_iter().map(|event| event.name.clone()));
+        }
+        finished
+    }
+
+    fn update(
+        &mut self,
+        dt: f32,
+        clips: &[AnimationClip],
+        parameters: &HashMap<String, f32>,
+        events: &mut Vec<String>,
+    ) {
+        let mut current = self.current;
+        let finished = self.advance(&mut current, dt, clips, Some(events));
+        self.current = current;
+
+        if let Some(mut fade) = self.fade {
+            self.advance(&mut fade.from, dt, clips, None);
+            fade.elapsed += dt;
+            self.fade = (fade.elapsed < fade.duration).then_some(fade);
+        }
+
+        if let Some(transition) = self.machine.next(self.current.state, parameters, finished) {
+            self.fade = (transition.duration > 0.0).then_some(Fade {
+                from: self.current,
+                elapsed: 0.0,
+                duration: transition.duration,
+            });
+            self.current = Playback {
+                state: transition.to,
+                time: 0.0,
+            };
+        }
+    }
+
+    fn sample_playback(&self, playback: &Playback, clips: &[AnimationClip], pose: &mut Pose) {
+        let state = &self.machine.states()[playback.state];
+        if let Some(clip) = clips.get(state.clip) {
+            clip.sample(playback.time, pose);
+        }
+    }
+
+    /// Samples the layer over `pose`, cross-fading from the previous state
+    fn sample(&self, clips: &[AnimationClip], pose: &mut Pose) {
+        let Some(fade) = &self.fade else {
+            self.sample_playback(&self.current, clips, pose);
+            return;
+        };
+        let mut from = pose.clone();
+        self.sample_playback(&fade.from, clips, &mut from);
+        self.sample_playback(&self.current, clips, pose);
+        from.blend(pose, self.fade_progress());
+        *pose = from;
+    }
+}
+
+/// Drives the animation of one entity: named parameters feed the state
+/// machine of every layer, and the layers are blended bottom to top into a
+/// single pose. Clips are referred to by index into the model animations.
+#[derive(Debug, Clone)]
+pub struct AnimationController {
+    layers: Vec<AnimationLayer>,
+    parameters: HashMap<String, f32>,
+}
+
+impl AnimationController {
+    pub fn new(layers: Vec<AnimationLayer>) -> Self {
+        Self {
+            layers,
+            parameters: HashMap::new(),
+        }
+    }
+
+    pub fn layers(&self) -> &[AnimationLayer] {
+        &self.layers
+    }
+
+    pub fn layers_mut(&mut self) -> &mut [AnimationLayer] {
+        &mut self.layers
+    }
+
+    pub fn set_parameter(&mut self, name: &str, value: f32) {
+        self.parameters.insert(name.to_string(), value);
+    }
+
+    /// Advances every layer by `dt` seconds and takes the transitions whose
+    /// condition holds. Returns the events fired along the way, in order.
+    pub fn update(&mut self, dt: f32, clips: &[AnimationClip]) -> Vec<String> {
+        let mut events = Vec::new();
+        for layer in &mut self.layers {
+            layer.update(dt, clips, &self.parameters, &mut events);
+        }
+        events
+    }
+
+    /// Blended pose of every layer over the rest pose of `skeleton`
+    pub fn pose(&self, skeleton: &Skeleton, clips: &[AnimationClip]) -> Pose {
+        let mut pose = skeleton.rest_pose();
+        for layer in &self.layers {
+            let weight = layer.weight.clamp(0.0, 1.0);
+            if weight <= 0.0 {
+                continue;
+            }
+            let mut layer_pose = pose.clone();
+            layer.sample(clips, &mut layer_pose);
+            pose.blend(&layer_pose, weight);
+        }
+        pose
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use super::*;
+    use crate::animation::{Channel, Condition, Interpolation, Keyframes, Transform, Transition};
+
+    /// Clip holding joint `joint` at `x` for one second
+    fn hold(name: &str, joint: usize, x: f32) -> AnimationClip {
+        AnimationClip::new(
+            name,
+            vec![Channel {
+                joint,
+                times: vec![0.0, 1.0],
+                keyframes: Keyframes::Translation(vec![Vector3::new(x, 0.0, 0.0); 2]),
+                interpolation: Interpolation::Linear,
+            }],
+        )
+    }
+
+    fn state(name: &str, clip: usize) -> AnimationState {
+        AnimationState {
+            name: name.to_string(),
+            clip,
+            speed: 1.0,
+            looping: true,
+        }
+    }
+
+    fn skeleton() -> Skeleton {
+        Skeleton::from_rest_pose([
+            ("root".to_string(), None, Transform::IDENTITY),
+            ("arm".to_string(), Some(0), Transform::IDENTITY),
+        ])
+        .unwrap()
+    }
+
+    fn locomotion() -> StateMachine {
+        StateMachine::new(
+            vec![state("idle", 0), state("run", 1)],
+            vec![Transition {
+                from: Some(0),
+                to: 1,
+                condition: Condition::Greater("speed".to_string(), 1.0),
+                duration: 0.5,
+            }],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parameters_cross_fade_between_states() {
+        let clips = [hold("idle", 0, 0.0), hold("run", 0, 4.0)];
+        let skeleton = skeleton();
+        let mut controller = AnimationController::new(vec![AnimationLayer::new(locomotion(), 1.0)]);
+
+        controller.update(0.1, &clips);
+        assert_eq!(controller.layers()[0].current_state().name, "idle");
+
+        controller.set_parameter("speed", 2.0);
+        controller.update(0.1, &clips);
+        assert_eq!(controller.layers()[0].current_state().name, "run");
+
+        // Half way through the fade the root is half way between the clips
+        controller.update(0.25, &clips);
+        let x = controller.pose(&skeleton, &clips).local[0].translation.x;
+        assert!((x - 2.0).abs() < 1e-5);
+
+        controller.update(0.5, &clips);
+        assert_eq!(controller.layers()[0].fade_progress(), 1.0);
+        assert_eq!(
+            controller.pose(&skeleton, &clips).local[0].translation.x,
+            4.0
+        );
+    }
+
+    #[test]
+    fn layers_only_override_their_joints() {
+        let clips = [hold("idle", 0, 2.0), hold("wave", 1, 6.0)];
+        let wave = StateMachine::new(vec![state("wave", 1)], Vec::new()).unwrap();
+        let controller = AnimationController::new(vec![
+            AnimationLayer::new(locomotion(), 1.0),
+            AnimationLayer::new(wave, 0.5),
+        ]);
+
+        let pose = controller.pose(&skeleton(), &clips);
+        assert_eq!(pose.local[0].translation.x, 2.0);
+        assert_eq!(pose.local[1].translation.x, 3.0);
+    }
+
+    #[test]
+    fn events_fire_when_passed() {
+        let clips = [hold("idle", 0, 0.0)
+            .with_event(0.75, "step")
+            .with_event(0.25, "lift")];
+        let machine = StateMachine::new(vec![state("idle", 0)], Vec::new()).unwrap();
+        let mut controller = AnimationController::new(vec![AnimationLayer::new(machine, 1.0)]);
+
+        assert_eq!(controller.update(0.5, &clips), ["lift"]);
+        assert!(controller.update(0.1, &clips).is_empty());
+        // Looping past the end fires the rest of this loop and the next
+        assert_eq!(controller.update(0.7, &clips), ["step", "lift"]);
+    }
+
+    #[test]
+    fn finished_states_move_on() {
+        let clips = [hold("attack", 0, 0.0), hold("idle", 0, 0.0)];
+        let machine = StateMachine::new(
+            vec![
+                AnimationState {
+                    looping: false,
+                    ..state("attack", 0)
+                },
+                state("idle", 1),
+            ],
+            vec![Transition {
+                from: None,
+                to: 1,
+                condition: Condition::Finished,
+                duration: 0.0,
+            }],
+        )
+        .unwrap();
+        let mut controller = AnimationController::new(vec![AnimationLayer::new(machine, 1.0)]);
+
+        controller.update(0.9, &clips);
+        assert_eq!(controller.layers()[0].current_state().name, "attack");
+        controller.update(0.2, &clips);
+        assert_eq!(controller.layers()[0].current_state().name, "idle");
+        assert_eq!(controller.layers()[0].fade_progress(), 1.0);
+    }
+}
@@ -0,0 +1,70 @@
+mod clip;
+mod controller;
+mod palette;
+mod skeleton;
+mod state_machine;
+
+pub use clip::{AnimationClip, Channel, Interpolation, Keyframes};
+pub use controller::{AnimationController, AnimationLayer};
+pub use palette::JointPalettes;
+pub use skeleton::{Pose, Skeleton};
+pub use state_machine::{AnimationState, Condition, StateMachine, Transition};
+
+use cgmath::{InnerSpace, Matrix4, One, Quaternion, Vector3, VectorSpace, Zero};
+
+/// Translation, rotation and scale of a joint relative to its parent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        translation: Vector3::new(0.0, 0.0, 0.0),
+        rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        scale: Vector3::new(1.0, 1.0, 1.0),
+    };
+
+    pub fn from_translation(translation: Vector3<f32>) -> Self {
+        Self {
+            translation,
+            ..Self::IDENTITY
+        }
+    }
+
+    /// Blends translation and scale linearly and rotation along the
+    /// shortest path
+    pub fn lerp(self, other: Self, t: f32) -> Self {
+        Self {
+            translation: self.translation.lerp(other.translation, t),
+            rotation: nlerp(self.rotation, other.rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+
+    pub fn to_matrix(self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Normalised linear interpolation between rotations, flipping `b` when
+/// needed so the rotation never goes the long way round
+fn nlerp(a: Quaternion<f32>, b: Quaternion<f32>, t: f32) -> Quaternion<f32> {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    let blended = a * (1.0 - t) + b * t;
+    if blended.magnitude2().is_zero() {
+        Quaternion::one()
+    } else {
+        blended.normalize()
+    }
+}
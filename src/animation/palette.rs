@@ -0,0 +1,130 @@
+use cgmath::Matrix4;
+
+use crate::{
+    cluster,
+    render::{self, MAX_JOINTS},
+};
+
+/// Skinning matrices of every skinned instance, each instance reading its
+/// own palette through a dynamic offset into a single uniform buffer. The
+/// bind group also holds the clustered lights, which skinned models are lit
+/// by.
+pub struct JointPalettes {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// Bytes between two palettes, honouring the offset alignment
+    stride: u64,
+    capacity: usize,
+    count: usize,
+}
+
+impl JointPalettes {
+    const PALETTE_SIZE: u64 = (MAX_JOINTS * std::mem::size_of::<[[f32; 4]; 4]>()) as u64;
+    const INITIAL_CAPACITY: usize = 4;
+
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &render::Pipelines,
+        clustered_lights: &cluster::ClusteredLights,
+    ) -> Self {
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let stride = Self::PALETTE_SIZE.div_ceil(alignment) * alignment;
+        let (buffer, bind_group) = Self::create_buffer(
+            device,
+            pipelines,
+            clustered_lights,
+            stride,
+            Self::INITIAL_CAPACITY,
+        );
+
+        Self {
+            buffer,
+            bind_group,
+            stride,
+            capacity: Self::INITIAL_CAPACITY,
+            count: 0,
+        }
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        pipelines: &render::Pipelines,
+        clustered_lights: &cluster::ClusteredLights,
+        stride: u64,
+        capacity: usize,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Joint Palette Buffer"),
+            size: stride * capacity as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let [clusters, ranges, indices] = clustered_lights.bind_group_entries();
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipelines.get_joints_bind_layout(),
+            entries: &[
+                clusters,
+                ranges,
+                indices,
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(Self::PALETTE_SIZE),
+                    }),
+                },
+            ],
+            label: Some("joint_palette_bind_group"),
+        });
+        (buffer, bind_group)
+    }
+
+    /// Uploads one palette per instance, as returned by
+    /// `Skeleton::skinning_matrices`. Joints past `MAX_JOINTS` are ignored.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipelines: &render::Pipelines,
+        clustered_lights: &cluster::ClusteredLights,
+        palettes: &[Vec<Matrix4<f32>>],
+    ) {
+        if palettes.len() > self.capacity {
+            self.capacity = palettes.len().next_power_of_two();
+            (self.buffer, self.bind_group) = Self::create_buffer(
+                device,
+                pipelines,
+                clustered_lights,
+                self.stride,
+                self.capacity,
+            );
+        }
+
+        let mut data = vec![0u8; self.stride as usize * palettes.len()];
+        for (palette, chunk) in palettes.iter().zip(data.chunks_mut(self.stride as usize)) {
+            let matrices: Vec<[[f32; 4]; 4]> = palette
+                .iter()
+                .take(MAX_JOINTS)
+                .map(|&matrix| matrix.into())
+                .collect();
+            let bytes: &[u8] = bytemuck::cast_slice(&matrices);
+            chunk[..bytes.len()].copy_from_slice(bytes);
+        }
+        if !data.is_empty() {
+            queue.write_buffer(&self.buffer, 0, &data);
+        }
+        self.count = palettes.len();
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Binds the palette of `instance` and the clusters for the skinning
+    /// pipeline
+    pub fn bind<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, instance: usize) {
+        let offset = (self.stride * instance as u64) as wgpu::DynamicOffset;
+        render_pass.set_bind_group(3, &self.bind_group, &[offset]);
+    }
+}
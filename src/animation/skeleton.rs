@@ -0,0 +1,162 @@
+use anyhow::bail;
+use cgmath::{Matrix4, SquareMatrix};
+
+use super::Transform;
+use crate::render::MAX_JOINTS;
+
+#[derive(Debug, Clone)]
+pub struct Joint {
+    pub name: String,
+    /// Always comes before the joint in the skeleton
+    pub parent: Option<usize>,
+    /// Local transform when no animation plays
+    pub rest: Transform,
+    /// Moves mesh vertices from model space into the joint space
+    pub inverse_bind: Matrix4<f32>,
+}
+
+/// Hierarchy of joints deforming a skinned mesh
+#[derive(Debug, Clone)]
+pub struct Skeleton {
+    joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    pub fn new(joints: Vec<Joint>) -> anyhow::Result<Self> {
+        if joints.len() > MAX_JOINTS {
+            bail!(
+                "Skeleton has {} joints, at most {} are supported",
+                joints.len(),
+                MAX_JOINTS
+            );
+        }
+        for (i, joint) in joints.iter().enumerate() {
+            if joint.parent.is_some_and(|parent| parent >= i) {
+                bail!("Joint {} is listed before its parent", joint.name);
+            }
+        }
+        Ok(Self { joints })
+    }
+
+    /// Skeleton whose bind pose is its rest pose, built from the name,
+    /// parent and rest transform of every joint
+    pub fn from_rest_pose(
+        joints: impl IntoIterator<Item = (String, Option<usize>, Transform)>,
+    ) -> anyhow::Result<Self> {
+        let mut skeleton = Self::new(
+            joints
+                .into_iter()
+                .map(|(name, parent, rest)| Joint {
+                    name,
+                    parent,
+                    rest,
+                    inverse_bind: Matrix4::identity(),
+                })
+                .collect(),
+        )?;
+        let globals = skeleton.global_matrices(&skeleton.rest_pose());
+        for (joint, global) in skeleton.joints.iter_mut().zip(globals) {
+            joint.inverse_bind = global.invert().unwrap_or(Matrix4::identity());
+        }
+        Ok(skeleton)
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    pub fn rest_pose(&self) -> Pose {
+        Pose {
+            local: self.joints.iter().map(|joint| joint.rest).collect(),
+        }
+    }
+
+    /// Model space transform of every joint
+    pub fn global_matrices(&self, pose: &Pose) -> Vec<Matrix4<f32>> {
+        let mut globals: Vec<Matrix4<f32>> = Vec::with_capacity(self.joints.len());
+        for (joint, local) in self.joints.iter().zip(&pose.local) {
+            let local = local.to_matrix();
+            let global = match joint.parent {
+                Some(parent) => globals[parent] * local,
+                None => local,
+            };
+            globals.push(global);
+        }
+        globals
+    }
+
+    /// Matrices moving vertices from their bind pose to `pose`, in the
+    /// order expected by the skinning shader
+    pub fn skinning_matrices(&self, pose: &Pose) -> Vec<Matrix4<f32>> {
+        self.global_matrices(pose)
+            .into_iter()
+            .zip(&self.joints)
+            .map(|(global, joint)| global * joint.inverse_bind)
+            .collect()
+    }
+}
+
+/// Local transform of every joint of a skeleton
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pose {
+    pub local: Vec<Transform>,
+}
+
+impl Pose {
+    /// Moves every joint `weight` of the way towards `other`
+    pub fn blend(&mut self, other: &Pose, weight: f32) {
+        for (local, other) in self.local.iter_mut().zip(&other.local) {
+            *local = local.lerp(*other, weight);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Quaternion, Rotation3, Transform as _, Vector3};
+
+    use super::*;
+
+    fn chain() -> Skeleton {
+        Skeleton::from_rest_pose([
+            ("root".to_string(), None, Transform::IDENTITY),
+            (
+                "tip".to_string(),
+                Some(0),
+                Transform::from_translation(Vector3::unit_y()),
+            ),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn rest_pose_leaves_vertices_in_place() {
+        let skeleton = chain();
+        for matrix in skeleton.skinning_matrices(&skeleton.rest_pose()) {
+            assert_eq!(matrix, Matrix4::identity());
+        }
+    }
+
+    #[test]
+    fn children_follow_their_parent() {
+        let skeleton = chain();
+        let mut pose = skeleton.rest_pose();
+        pose.local[0].rotation = Quaternion::from_angle_z(Deg(90.0));
+
+        // A vertex bound at the tip swings from +Y to -X with the root
+        let tip = skeleton.skinning_matrices(&pose)[1];
+        let moved = tip.transform_point((0.0, 1.0, 0.0).into());
+        assert!((moved.x + 1.0).abs() < 1e-5 && moved.y.abs() < 1e-5);
+    }
+
+    #[test]
+    fn parents_must_come_first() {
+        let joint = |parent| Joint {
+            name: String::new(),
+            parent,
+            rest: Transform::IDENTITY,
+            inverse_bind: Matrix4::identity(),
+        };
+        assert!(Skeleton::new(vec![joint(Some(1)), joint(None)]).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+
+/// Clip played while a state machine is in this state
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationState {
+    pub name: String,
+    /// Index of the clip in the animations of the model
+    pub clip: usize,
+    /// Playback rate, 1 being the speed the clip was authored at
+    pub speed: f32,
+    /// Loops the clip instead of holding its last frame
+    pub looping: bool,
+}
+
+/// When a transition is taken
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// Parameter above the value, parameters never set being 0
+    Greater(String, f32),
+    /// Parameter below the value, parameters never set being 0
+    Less(String, f32),
+    /// Non looping clip of the state reached its end
+    Finished,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    /// State the transition leaves, any state when `None`
+    pub from: Option<usize>,
+    pub to: usize,
+    pub condition: Condition,
+    /// Seconds spent cross-fading into the new state
+    pub duration: f32,
+}
+
+/// States of an animated entity and the parameter conditions moving it from
+/// one to the other. The first state is where playback starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateMachine {
+    states: Vec<AnimationState>,
+    transitions: Vec<Transition>,
+}
+
+impl StateMachine {
+    pub fn new(states: Vec<AnimationState>, transitions: Vec<Transition>) -> anyhow::Result<Self> {
+        if states.is_empty() {
+            bail!("State machine needs at least one state");
+        }
+        for transition in &transitions {
+            let from_valid = transition.from.is_none_or(|from| from < states.len());
+            if !from_valid || transition.to >= states.len() {
+                bail!(
+                    "Transition {:?} -> {} refers to a missing state",
+                    transition.from,
+                    transition.to
+                );
+            }
+        }
+        Ok(Self {
+            states,
+            transitions,
+        })
+    }
+
+    pub fn states(&self) -> &[AnimationState] {
+        &self.states
+    }
+
+    /// First transition out of `current` whose condition holds. Transitions
+    /// into the current state are never taken.
+    pub(super) fn next(
+        &self,
+        current: usize,
+        parameters: &HashMap<String, f32>,
+        finished: bool,
+    ) -> Option<&Transition> {
+        let parameter = |name: &String| parameters.get(name).copied().unwrap_or(0.0);
+        self.transitions.iter().find(|transition| {
+            transition.from.is_none_or(|from| from == current)
+                && transition.to != current
+                && match &transition.condition {
+                    Condition::Greater(name, value) => parameter(name) > *value,
+                    Condition::Less(name, value) => parameter(name) < *value,
+                    Condition::Finished => finished,
+                }
+        })
+    }
+}
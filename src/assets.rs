@@ -0,0 +1,826 @@
+//! Textures, materials and models loaded once and shared by everything using
+//! them
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt,
+    future::Future,
+    hash::Hash,
+    ops::Deref,
+    sync::{mpsc, Arc, Weak},
+};
+
+use crate::{cooked, model, resources, texture};
+
+/// Shared reference to a loaded asset. The asset, with its GPU resources, is
+/// freed when its last handle is dropped.
+pub struct Handle<T>(Arc<T>);
+
+impl<T> Handle<T> {
+    /// Handle to an asset that isn't cached, like one built in code
+    pub fn new(asset: T) -> Self {
+        Self(Arc::new(asset))
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Deref for Handle<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadState {
+    Loading,
+    Loaded,
+    Failed(String),
+}
+
+impl fmt::Display for LoadState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadState::Loading => write!(f, "loading"),
+            LoadState::Loaded => write!(f, "loaded"),
+            LoadState::Failed(error) => write!(f, "failed: {}", error),
+        }
+    }
+}
+
+type LoadResult<T> = Result<Handle<T>, String>;
+
+type LoadCallback<T> = Box<dyn FnOnce(&Streamed<T>)>;
+
+/// Asset loading in the background, a placeholder standing in for it until
+/// it is ready. The placeholder stays when loading fails, and so does the
+/// previous asset when reloading it does.
+pub struct Streamed<T> {
+    asset: Handle<T>,
+    state: LoadState,
+    /// `None` once the server is gone
+    receiver: Option<mpsc::Receiver<LoadResult<T>>>,
+    callbacks: Vec<LoadCallback<T>>,
+}
+
+impl<T> Streamed<T> {
+    fn loaded(asset: Handle<T>, receiver: mpsc::Receiver<LoadResult<T>>) -> Self {
+        Self {
+            asset,
+            state: LoadState::Loaded,
+            receiver: Some(receiver),
+            callbacks: Vec::new(),
+        }
+    }
+
+    fn loading(placeholder: Handle<T>, receiver: mpsc::Receiver<LoadResult<T>>) -> Self {
+        Self {
+            asset: placeholder,
+            state: LoadState::Loading,
+            receiver: Some(receiver),
+            callbacks: Vec::new(),
+        }
+    }
+
+    pub fn state(&self) -> &LoadState {
+        &self.state
+    }
+
+    /// Calls `callback` once loading is over, right away if it already is
+    pub fn on_loaded(&mut self, callback: impl FnOnce(&Streamed<T>) + 'static) {
+        if self.state == LoadState::Loading {
+            self.callbacks.push(Box::new(callback));
+        } else {
+            callback(self);
+        }
+    }
+
+    /// Swaps the asset in once the server has uploaded it, and again each
+    /// time it is reloaded, returning whether the state or the asset changed
+    pub fn update(&mut self) -> bool {
+        let Some(receiver) = &self.receiver else {
+            return false;
+        };
+        let mut latest = None;
+        let disconnected = loop {
+            match receiver.try_recv() {
+                Ok(result) => latest = Some(result),
+                Err(mpsc::TryRecvError::Empty) => break false,
+                Err(mpsc::TryRecvError::Disconnected) => break true,
+            }
+        };
+        if disconnected {
+            self.receiver = None;
+            if latest.is_none() && self.state == LoadState::Loading {
+                latest = Some(Err("The asset server is gone".to_string()));
+            }
+        }
+        let Some(result) = latest else {
+            return false;
+        };
+
+        let first_load = self.state == LoadState::Loading;
+        match result {
+            Ok(asset) => {
+                self.asset = asset;
+                self.state = LoadState::Loaded;
+            }
+            Err(error) => self.state = LoadState::Failed(error),
+        }
+        if first_load {
+            for callback in std::mem::take(&mut self.callbacks) {
+                callback(self);
+            }
+        }
+        true
+    }
+}
+
+impl<T> Deref for Streamed<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.asset
+    }
+}
+
+/// Assets by key, held weakly so that the cache doesn't keep them alive
+struct Cache<K, T> {
+    entries: HashMap<K, Weak<T>>,
+}
+
+impl<K: Hash + Eq, T> Cache<K, T> {
+    fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<Handle<T>> {
+        self.entries.get(key)?.upgrade().map(Handle)
+    }
+
+    fn insert(&mut self, key: K, asset: T) -> Handle<T> {
+        self.entries.retain(|_, asset| asset.strong_count() > 0);
+        let handle = Handle::new(asset);
+        self.entries.insert(key, Arc::downgrade(&handle.0));
+        handle
+    }
+
+    /// Stops handing out the assets whose key matches `predicate`, which
+    /// stay alive as long as their handles
+    fn forget(&mut self, mut predicate: impl FnMut(&K) -> bool) {
+        self.entries.retain(|key, _| !predicate(key));
+    }
+
+    /// Assets still in use
+    fn live(&self) -> usize {
+        self.entries
+            .values()
+            .filter(|asset| asset.strong_count() > 0)
+            .count()
+    }
+}
+
+/// Identifies the image of a texture, normal maps being uploaded in another
+/// format than colours
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum TextureKey {
+    File {
+        path: String,
+        is_normal_map: bool,
+    },
+    /// Image embedded in a model
+    Embedded {
+        model: String,
+        image: usize,
+        is_normal_map: bool,
+    },
+}
+
+/// Materials are shared when they have the same name, parameters and maps
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct MaterialKey {
+    name: String,
+    /// Debug output of the parameters, which is exact for floats
+    params: String,
+    diffuse: Option<TextureKey>,
+    normal: Option<TextureKey>,
+}
+
+impl MaterialKey {
+    fn uses(&self, texture: &TextureKey) -> bool {
+        self.diffuse.as_ref() == Some(texture) || self.normal.as_ref() == Some(texture)
+    }
+}
+
+/// Number of assets of each kind in use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AssetStats {
+    pub textures: usize,
+    pub materials: usize,
+    pub models: usize,
+    /// Models still being read and decoded
+    pub loading: usize,
+}
+
+impl fmt::Display for AssetStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} textures, {} materials, {} models, {} loading",
+            self.textures, self.materials, self.models, self.loading
+        )
+    }
+}
+
+/// Model read and its images decoded off the render thread, ready to upload
+struct DecodedModel {
+    data: cooked::ModelData,
+    /// One per image of the model
+    images: Vec<anyhow::Result<image::DynamicImage>>,
+}
+
+/// Work done on the worker threads, by file
+enum Decoded {
+    Model(String, anyhow::Result<DecodedModel>),
+    /// Image of a texture being reloaded
+    Image(String, anyhow::Result<image::DynamicImage>),
+}
+
+/// Files a loaded model was made from, which reload it when they change
+struct ModelSources {
+    files: Vec<String>,
+    /// Image files of its maps
+    images: Vec<String>,
+}
+
+/// Loads assets from the resources, handing out the already loaded ones
+/// when the same file is requested again. Materials defined the same way,
+/// like those of two models sharing an MTL file, are shared as well.
+///
+/// Models are streamed: they are read and decoded on worker threads, then
+/// uploaded by [`AssetServer::poll`]. They are streamed again when the files
+/// they were made from are reloaded.
+pub struct AssetServer {
+    textures: Cache<TextureKey, texture::Texture>,
+    materials: Cache<MaterialKey, model::Material>,
+    models: Cache<String, model::Model>,
+    default_textures: texture::DefaultTextures,
+    /// Checkered cube standing in for the models being loaded
+    placeholder: Handle<model::Model>,
+    /// Streams of each model requested, which get it again when it is
+    /// reloaded
+    streams: HashMap<String, Vec<mpsc::Sender<LoadResult<model::Model>>>>,
+    /// Models being read and decoded
+    loading: HashSet<String>,
+    /// Models reloaded while being decoded, from files that may have
+    /// changed after they were read
+    reload_pending: HashSet<String>,
+    sources: HashMap<String, ModelSources>,
+    decoded_sender: mpsc::Sender<Decoded>,
+    decoded_receiver: mpsc::Receiver<Decoded>,
+    options: resources::LoadOptions,
+    /// Whether cooked models are checked against their sources, which
+    /// change during development
+    check_cooked_sources: bool,
+}
+
+impl AssetServer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        options: resources::LoadOptions,
+        check_cooked_sources: bool,
+    ) -> anyhow::Result<Self> {
+        let default_textures = texture::DefaultTextures::new(device, queue)?;
+        let placeholder = Handle::new(model::Model {
+            meshes: vec![resources::upload_mesh(
+                "placeholder",
+                resources::placeholder_mesh(&options),
+                device,
+            )],
+            materials: vec![Handle::new(model::Material::new(
+                device,
+                "placeholder",
+                default_textures.checker.clone(),
+                default_textures.flat_normal.clone(),
+                model::MaterialParams::default(),
+                layout,
+            ))],
+            skeleton: None,
+            animations: Vec::new(),
+            vertex_format: options.vertex_format,
+            nodes: Vec::new(),
+        });
+        let (decoded_sender, decoded_receiver) = mpsc::channel();
+        Ok(Self {
+            textures: Cache::new(),
+            materials: Cache::new(),
+            models: Cache::new(),
+            default_textures,
+            placeholder,
+            streams: HashMap::new(),
+            loading: HashSet::new(),
+            reload_pending: HashSet::new(),
+            sources: HashMap::new(),
+            decoded_sender,
+            decoded_receiver,
+            options,
+            check_cooked_sources,
+        })
+    }
+
+    pub fn stats(&self) -> AssetStats {
+        AssetStats {
+            textures: self.textures.live(),
+            materials: self.materials.live(),
+            models: self.models.live(),
+            loading: self.loading.len(),
+        }
+    }
+
+    pub async fn load_texture(
+        &mut self,
+        file_name: &str,
+        is_normal_map: bool,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<Handle<texture::Texture>> {
+        let key = TextureKey::File {
+            path: file_name.to_string(),
+            is_normal_map,
+        };
+        if let Some(texture) = self.textures.get(&key) {
+            return Ok(texture);
+        }
+        let texture = resources::load_texture(file_name, is_normal_map, device, queue).await?;
+        Ok(self.textures.insert(key, texture))
+    }
+
+    /// Starts loading a model, from its cooked file when it is up to date,
+    /// with the options the server was created with. The placeholder stands
+    /// in for it until [`AssetServer::poll`] has uploaded it.
+    pub fn stream_model(&mut self, file_name: &str) -> Streamed<model::Model> {
+        let (sender, receiver) = mpsc::channel();
+        self.streams
+            .entry(file_name.to_string())
+            .or_default()
+            .push(sender);
+        if let Some(model) = self.models.get(&file_name.to_string()) {
+            return Streamed::loaded(model, receiver);
+        }
+        self.decode_model(file_name);
+        Streamed::loading(self.placeholder.clone(), receiver)
+    }
+
+    /// Reloads the assets made from `files`, which changed. Textures are
+    /// rewritten in place, so that the materials using them stay as they
+    /// are, and models are streamed again.
+    pub fn reload(&mut self, files: &[String]) {
+        let mut models = BTreeSet::new();
+        for file_name in files {
+            let is_texture = [false, true].into_iter().any(|is_normal_map| {
+                let key = TextureKey::File {
+                    path: file_name.clone(),
+                    is_normal_map,
+                };
+                self.textures.get(&key).is_some()
+            });
+            if is_texture {
+                let file_name = file_name.clone();
+                let decoded_sender = self.decoded_sender.clone();
+                spawn(move || async move {
+                    let image = decode_image(&file_name).await;
+                    let _ = decoded_sender.send(Decoded::Image(file_name, image));
+                });
+            }
+            // Models that failed to load have no known sources but their file
+            if self.streams.contains_key(file_name) {
+                models.insert(file_name.clone());
+            }
+            for (model, sources) in &self.sources {
+                if sources.files.contains(file_name) {
+                    models.insert(model.clone());
+                }
+            }
+        }
+        for model in models {
+            self.reload_model(&model);
+        }
+    }
+
+    /// Uploads the models decoded since the last call and hands them to
+    /// their streams, and writes the reloaded images over their textures
+    pub fn poll(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) {
+        while let Ok(decoded) = self.decoded_receiver.try_recv() {
+            match decoded {
+                Decoded::Model(file_name, decoded) => {
+                    self.upload_decoded_model(file_name, decoded, device, queue, layout)
+                }
+                Decoded::Image(file_name, image) => match image {
+                    Ok(image) => self.rewrite_texture(&file_name, &image, queue),
+                    Err(e) => log::warn!("Failed to reload {}: {:#}", file_name, e),
+                },
+            }
+        }
+    }
+
+    /// Decodes a model again, once the decode under way is done if there is
+    /// one, as it may have read the files before they changed
+    fn reload_model(&mut self, file_name: &str) {
+        if self.loading.contains(file_name) {
+            self.reload_pending.insert(file_name.to_string());
+        } else {
+            self.decode_model(file_name);
+        }
+    }
+
+    /// Reads and decodes a model on a worker thread, unless it already is
+    fn decode_model(&mut self, file_name: &str) {
+        if !self.loading.insert(file_name.to_string()) {
+            return;
+        }
+        let file_name = file_name.to_string();
+        let options = self.options;
+        let check_sources = self.check_cooked_sources;
+        let decoded_sender = self.decoded_sender.clone();
+        spawn(move || async move {
+            let decoded = decode_model(&file_name, &options, check_sources).await;
+            // The server may have been dropped in the meantime
+            let _ = decoded_sender.send(Decoded::Model(file_name, decoded));
+        });
+    }
+
+    fn upload_decoded_model(
+        &mut self,
+        file_name: String,
+        decoded: anyhow::Result<DecodedModel>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) {
+        self.loading.remove(&file_name);
+        if self.reload_pending.remove(&file_name) {
+            // Stale, its streams get the reloaded one instead
+            self.decode_model(&file_name);
+            return;
+        }
+        let result = match decoded {
+            Ok(decoded) => {
+                let sources = ModelSources {
+                    files: decoded
+                        .data
+                        .key
+                        .dependencies
+                        .iter()
+                        .map(|dependency| dependency.path.clone())
+                        .collect(),
+                    images: decoded
+                        .data
+                        .images
+                        .iter()
+                        .filter_map(|image| match image {
+                            cooked::ImageData::File(path) => Some(path.clone()),
+                            cooked::ImageData::Bytes(_) => None,
+                        })
+                        .collect(),
+                };
+                if self.sources.insert(file_name.clone(), sources).is_some() {
+                    log::info!("Reloaded {}", file_name);
+                }
+                let model = self.upload_model(&file_name, decoded, device, queue, layout);
+                Ok(self.models.insert(file_name.clone(), model))
+            }
+            Err(e) => {
+                log::error!("Failed to load {}: {:#}", file_name, e);
+                Err(format!("{:#}", e))
+            }
+        };
+        if let Some(streams) = self.streams.get_mut(&file_name) {
+            // Dropped streams don't want it anymore
+            streams.retain(|stream| stream.send(result.clone()).is_ok());
+        }
+    }
+
+    /// Writes a reloaded image over the textures made from it. Textures it
+    /// doesn't fit are dropped from the cache along with their materials,
+    /// and the models using them are reloaded to make new ones.
+    fn rewrite_texture(
+        &mut self,
+        file_name: &str,
+        image: &image::DynamicImage,
+        queue: &wgpu::Queue,
+    ) {
+        let mut resized = false;
+        for is_normal_map in [false, true] {
+            let key = TextureKey::File {
+                path: file_name.to_string(),
+                is_normal_map,
+            };
+            let Some(texture) = self.textures.get(&key) else {
+                continue;
+            };
+            if !texture.write_image(queue, image) {
+                self.textures.forget(|texture| *texture == key);
+                self.materials.forget(|material| material.uses(&key));
+                resized = true;
+            }
+        }
+        if !resized {
+            log::info!("Reloaded {}", file_name);
+            return;
+        }
+        log::warn!(
+            "{} changed size, materials made in code keep the old one until restarting",
+            file_name
+        );
+        let models = self
+            .sources
+            .iter()
+            .filter(|(_, sources)| sources.images.iter().any(|image| image == file_name))
+            .map(|(model, _)| model.clone())
+            .collect::<Vec<_>>();
+        for model in models {
+            self.reload_model(&model);
+        }
+    }
+
+    fn default_texture(&self, is_normal_map: bool) -> Handle<texture::Texture> {
+        if is_normal_map {
+            self.default_textures.flat_normal.clone()
+        } else {
+            self.default_textures.white.clone()
+        }
+    }
+
+    /// Texture of a material map, the default one when the material has no
+    /// such map or it fails to load
+    fn material_texture(
+        &mut self,
+        file_name: &str,
+        model: &DecodedModel,
+        image: Option<usize>,
+        is_normal_map: bool,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> (Option<TextureKey>, Handle<texture::Texture>) {
+        let Some(image) = image else {
+            return (None, self.default_texture(is_normal_map));
+        };
+        let (key, label) = match &model.data.images[image] {
+            cooked::ImageData::File(path) => (
+                TextureKey::File {
+                    path: path.clone(),
+                    is_normal_map,
+                },
+                path.clone(),
+            ),
+            cooked::ImageData::Bytes(_) => (
+                TextureKey::Embedded {
+                    model: file_name.to_string(),
+                    image,
+                    is_normal_map,
+                },
+                format!("{} image {}", file_name, image),
+            ),
+        };
+        if let Some(texture) = self.textures.get(&key) {
+            return (Some(key), texture);
+        }
+        let texture = match &model.images[image] {
+            Ok(decoded) => {
+                texture::Texture::from_image(device, queue, decoded, Some(&label), is_normal_map)
+            }
+            Err(e) => Err(anyhow::anyhow!("{:#}", e)),
+        };
+        match texture {
+            Ok(texture) => (Some(key.clone()), self.textures.insert(key, texture)),
+            Err(e) => {
+                log::warn!("Failed to load {}, using a default texture: {}", label, e);
+                (None, self.default_texture(is_normal_map))
+            }
+        }
+    }
+
+    /// Uploads the meshes of a model and shares its materials and maps with
+    /// the assets already loaded
+    fn upload_model(
+        &mut self,
+        file_name: &str,
+        model: DecodedModel,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) -> model::Model {
+        let mut materials = Vec::with_capacity(model.data.materials.len());
+        for material in &model.data.materials {
+            let (diffuse_key, diffuse_texture) = self.material_texture(
+                file_name,
+                &model,
+                material.diffuse_image,
+                false,
+                device,
+                queue,
+            );
+            let (normal_key, normal_texture) = self.material_texture(
+                file_name,
+                &model,
+                material.normal_image,
+                true,
+                device,
+                queue,
+            );
+            let key = MaterialKey {
+                name: material.name.clone(),
+                params: format!("{:?}", material.params),
+                diffuse: diffuse_key,
+                normal: normal_key,
+            };
+            let material = match self.materials.get(&key) {
+                Some(material) => material,
+                None => {
+                    let material = model::Material::new(
+                        device,
+                        &material.name,
+                        diffuse_texture,
+                        normal_texture,
+                        material.params,
+                        layout,
+                    );
+                    self.materials.insert(key, material)
+                }
+            };
+            materials.push(material);
+        }
+
+        model::Model {
+            meshes: model
+                .data
+                .meshes
+                .into_iter()
+                .map(|mesh| resources::upload_mesh(file_name, mesh, device))
+                .collect(),
+            materials,
+            skeleton: None,
+            animations: Vec::new(),
+            vertex_format: model.data.vertex_format,
+            nodes: model.data.nodes,
+        }
+    }
+}
+
+/// Reads a model and decodes its images, everything short of the upload
+async fn decode_model(
+    file_name: &str,
+    options: &resources::LoadOptions,
+    check_sources: bool,
+) -> anyhow::Result<DecodedModel> {
+    let data = match resources::load_cooked_model(file_name, options, check_sources).await {
+        Some(data) => data,
+        None => {
+            resources::load_model_data(file_name, resources::AssetSource::Resources, options)
+                .await?
+        }
+    };
+    Ok(decode_images(data).await)
+}
+
+/// Decodes the images of a model. Those failing to decode only fail
+/// themselves, their materials getting default textures instead.
+async fn decode_images(data: cooked::ModelData) -> DecodedModel {
+    // Images already loaded by other models are decoded again, only to be
+    // dropped when uploading
+    let mut images = Vec::with_capacity(data.images.len());
+    for image in &data.images {
+        let decoded = match image {
+            cooked::ImageData::File(path) => decode_image(path).await,
+            cooked::ImageData::Bytes(bytes) => image::load_from_memory(bytes).map_err(Into::into),
+        };
+        images.push(decoded);
+    }
+    DecodedModel { data, images }
+}
+
+async fn decode_image(file_name: &str) -> anyhow::Result<image::DynamicImage> {
+    let bytes = resources::load_binary(file_name).await?;
+    Ok(image::load_from_memory(&bytes)?)
+}
+
+/// Runs the future made by `task` on the rayon thread pool
+#[cfg(not(target_arch = "wasm32"))]
+fn spawn<F: Future<Output = ()> + 'static>(task: impl FnOnce() -> F + Send + 'static) {
+    rayon::spawn(move || async_std::task::block_on(task()));
+}
+
+/// Runs the future made by `task` on the browser's event loop, there being
+/// no threads to decode on
+#[cfg(target_arch = "wasm32")]
+fn spawn<F: Future<Output = ()> + 'static>(task: impl FnOnce() -> F + Send + 'static) {
+    wasm_bindgen_futures::spawn_local(task());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_assets_are_freed_with_their_last_handle() {
+        let mut cache = Cache::new();
+        let first = cache.insert("a", vec![1u8; 16]);
+        let second = cache.get(&"a").unwrap();
+        assert!(Arc::ptr_eq(&first.0, &second.0));
+        assert_eq!(cache.live(), 1);
+
+        drop(first);
+        assert_eq!(cache.live(), 1);
+        drop(second);
+        assert_eq!(cache.live(), 0);
+        assert!(cache.get(&"a").is_none());
+
+        // Dead entries are dropped as new assets come in
+        cache.insert("b", vec![]);
+        assert_eq!(cache.entries.len(), 1);
+    }
+
+    #[test]
+    fn streamed_assets_are_swapped_in_when_ready() {
+        let (sender, receiver) = mpsc::channel();
+        let mut streamed = Streamed::loading(Handle::new(0), receiver);
+        let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = calls.clone();
+        streamed.on_loaded(move |streamed| {
+            assert_eq!(**streamed, 1);
+            counter.set(counter.get() + 1);
+        });
+        assert!(!streamed.update());
+        assert_eq!(*streamed, 0);
+
+        sender.send(Ok(Handle::new(1))).unwrap();
+        assert!(streamed.update());
+        assert_eq!(*streamed.state(), LoadState::Loaded);
+        assert_eq!(calls.get(), 1);
+        assert!(!streamed.update());
+
+        // Reloads are swapped in too, keeping the last one
+        sender.send(Ok(Handle::new(2))).unwrap();
+        sender.send(Ok(Handle::new(3))).unwrap();
+        assert!(streamed.update());
+        assert_eq!(*streamed, 3);
+        sender.send(Err("Broken".to_string())).unwrap();
+        assert!(streamed.update());
+        assert_eq!(*streamed.state(), LoadState::Failed("Broken".to_string()));
+        assert_eq!(*streamed, 3);
+        assert_eq!(calls.get(), 1);
+        drop(sender);
+        assert!(!streamed.update());
+
+        let (sender, receiver) = mpsc::channel();
+        let mut failed = Streamed::loading(Handle::new(0), receiver);
+        drop(sender);
+        assert!(failed.update());
+        assert!(matches!(failed.state(), LoadState::Failed(_)));
+        assert_eq!(*failed, 0);
+    }
+
+    #[test]
+    fn bad_embedded_images_only_fail_themselves() {
+        let mut png = Vec::new();
+        image::DynamicImage::new_rgba8(2, 2)
+            .write_to(
+                &mut std::io::Cursor::new(&mut png),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+        let data = cooked::ModelData {
+            key: cooked::CookKey {
+                options: 0,
+                dependencies: Vec::new(),
+            },
+            vertex_format: model::VertexFormat::Full,
+            images: vec![
+                cooked::ImageData::Bytes(png),
+                cooked::ImageData::Bytes(vec![1, 2, 3]),
+            ],
+            materials: Vec::new(),
+            nodes: Vec::new(),
+            meshes: Vec::new(),
+        };
+        let decoded = async_std::task::block_on(decode_images(data));
+        assert_eq!(decoded.images.len(), 2);
+        assert_eq!(decoded.images[0].as_ref().unwrap().width(), 2);
+        assert!(decoded.images[1].is_err());
+    }
+}
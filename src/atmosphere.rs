@@ -0,0 +1,295 @@
+use cgmath::{Matrix4, Rad, SquareMatrix, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::render;
+
+/// How fog thickens between the camera and what it looks at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FogMode {
+    Off,
+    /// No fog before `start`, fully fogged past `end`
+    Linear {
+        start: f32,
+        end: f32,
+    },
+    /// Constant density, the fog thickens exponentially with distance
+    Exponential {
+        density: f32,
+    },
+    /// Density decreasing exponentially with the altitude above `base`, so
+    /// fog pools in valleys and thins out looking up
+    Height {
+        density: f32,
+        falloff: f32,
+        base: f32,
+    },
+}
+
+impl FogMode {
+    fn id(&self) -> u32 {
+        match self {
+            FogMode::Off => 0,
+            FogMode::Linear { .. } => 1,
+            FogMode::Exponential { .. } => 2,
+            FogMode::Height { .. } => 3,
+        }
+    }
+
+    fn params(&self) -> [f32; 4] {
+        match *self {
+            FogMode::Off => [0.0; 4],
+            FogMode::Linear { start, end } => [start, end, 0.0, 0.0],
+            FogMode::Exponential { density } => [density, 0.0, 0.0, 0.0],
+            FogMode::Height {
+                density,
+                falloff,
+                base,
+            } => [density, falloff, base, 0.0],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fog {
+    pub mode: FogMode,
+    /// Colour of the fog, replaced by the sky horizon when a sky is used
+    pub color: [f32; 3],
+    /// How much the fog takes the sun colour when looking towards the sun
+    pub sun_scattering: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            mode: FogMode::Off,
+            color: [0.5, 0.6, 0.7],
+            sun_scattering: 0.5,
+        }
+    }
+}
+
+/// Fog parameters as uploaded with the camera, so that every pipeline
+/// drawing the world can apply the same fog
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FogUniform {
+    color: [f32; 3],
+    mode: u32,
+    sun_color: [f32; 3],
+    sun_scattering: f32,
+    sun_direction: [f32; 3],
+    _padding: u32,
+    params: [f32; 4],
+}
+
+impl Fog {
+    /// Fog as seen under `sky`, which drives the fog colours when given
+    pub fn uniform(&self, sky: Option<&Sky>) -> FogUniform {
+        let (color, sun_color, sun_direction) = match sky {
+            Some(sky) => (
+                sky.horizon_color(),
+                sky.sun_color(),
+                sky.sun_direction().into(),
+            ),
+            None => (self.color, self.color, [0.0, 1.0, 0.0]),
+        };
+        FogUniform {
+            color,
+            mode: self.mode.id(),
+            sun_color,
+            sun_scattering: self.sun_scattering,
+            sun_direction,
+            _padding: 0,
+            params: self.mode.params(),
+        }
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+/// Analytic sky lit by a single sun. Colours only depend on the sun
+/// elevation, the sky shader blends them by view direction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sky {
+    /// Angle of the sun above the horizon
+    pub sun_elevation: Rad<f32>,
+    /// Angle of the sun around the Y axis, from the X axis towards Z
+    pub sun_azimuth: Rad<f32>,
+}
+
+impl Sky {
+    /// Extinction of sunlight per air mass, blue scatters the most
+    const EXTINCTION: [f32; 3] = [0.02, 0.05, 0.12];
+    const DAY_ZENITH: [f32; 3] = [0.18, 0.35, 0.75];
+    const DAY_HORIZON: [f32; 3] = [0.65, 0.75, 0.9];
+    const SUNSET_HORIZON: [f32; 3] = [0.9, 0.45, 0.2];
+    const NIGHT: [f32; 3] = [0.01, 0.01, 0.03];
+
+    pub fn sun_direction(&self) -> Vector3<f32> {
+        let (sin_elevation, cos_elevation) = self.sun_elevation.0.sin_cos();
+        let (sin_azimuth, cos_azimuth) = self.sun_azimuth.0.sin_cos();
+        Vector3::new(
+            cos_elevation * cos_azimuth,
+            sin_elevation,
+            cos_elevation * sin_azimuth,
+        )
+    }
+
+    /// Share of the full daylight, fading out as the sun sets
+    fn daylight(&self) -> f32 {
+        smoothstep(-0.2, 0.3, self.sun_elevation.0.sin())
+    }
+
+    /// Sunlight left after crossing the atmosphere. The light crosses more
+    /// air near the horizon and turns red.
+    pub fn sun_color(&self) -> [f32; 3] {
+        let elevation = self.sun_elevation.0.max(0.0);
+        // Kasten and Young's approximation of the relative air mass
+        let air_mass =
+            1.0 / (elevation.sin() + 0.15 * (elevation.to_degrees() + 3.885).powf(-1.253));
+        let visible = smoothstep(-0.1, 0.02, self.sun_elevation.0.sin());
+        Self::EXTINCTION.map(|extinction| (-extinction * air_mass).exp() * visible)
+    }
+
+    pub fn zenith_color(&self) -> [f32; 3] {
+        lerp(Self::NIGHT, Self::DAY_ZENITH, self.daylight())
+    }
+
+    pub fn horizon_color(&self) -> [f32; 3] {
+        let sunset = 1.0 - smoothstep(0.0, 0.35, self.sun_elevation.0.sin());
+        let day = lerp(Self::DAY_HORIZON, Self::SUNSET_HORIZON, sunset);
+        lerp(Self::NIGHT, day, self.daylight())
+    }
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Self {
+            sun_elevation: Rad(0.6),
+            sun_azimuth: Rad(-1.0),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    /// Turns screen positions back into view directions
+    inv_view_proj: [[f32; 4]; 4],
+    sun_direction: [f32; 3],
+    _padding0: u32,
+    sun_color: [f32; 3],
+    _padding1: u32,
+    zenith_color: [f32; 3],
+    _padding2: u32,
+    horizon_color: [f32; 3],
+    _padding3: u32,
+}
+
+/// Draws a `Sky` behind the scene
+pub struct SkyRenderer {
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl SkyRenderer {
+    pub fn new(device: &wgpu::Device, pipelines: &render::Pipelines) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sky Buffer"),
+            contents: bytemuck::cast_slice(&[SkyUniform {
+                inv_view_proj: Matrix4::identity().into(),
+                ..bytemuck::Zeroable::zeroed()
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipelines.get_sky_bind_layout(),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("sky_bind_group"),
+        });
+
+        Self {
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    pub fn prepare(&self, queue: &wgpu::Queue, sky: &Sky, view_proj: Matrix4<f32>) {
+        let uniform = SkyUniform {
+            inv_view_proj: view_proj.invert().unwrap_or(Matrix4::identity()).into(),
+            sun_direction: sky.sun_direction().into(),
+            sun_color: sky.sun_color(),
+            zenith_color: sky.zenith_color(),
+            horizon_color: sky.horizon_color(),
+            ..bytemuck::Zeroable::zeroed()
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Must be drawn after the opaque geometry, with its depth buffer bound
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a render::Pipelines,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(pipelines.get_sky_pipeline());
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sun_reddens_towards_the_horizon() {
+        let sky = |elevation| Sky {
+            sun_elevation: Rad(elevation),
+            ..Default::default()
+        };
+
+        let noon = sky(1.5).sun_color();
+        assert!(noon.iter().all(|&c| c > 0.85));
+
+        let sunset = sky(0.0).sun_color();
+        assert!(sunset[0] > sunset[1] && sunset[1] > sunset[2]);
+        assert!(sunset[0] < noon[0]);
+
+        let night = sky(-0.5);
+        assert_eq!(night.sun_color(), [0.0; 3]);
+        assert_eq!(night.horizon_color(), Sky::NIGHT);
+    }
+
+    #[test]
+    fn sky_drives_fog_colour() {
+        let fog = Fog {
+            mode: FogMode::Linear {
+                start: 10.0,
+                end: 50.0,
+            },
+            ..Default::default()
+        };
+        let sky = Sky::default();
+
+        let uniform = fog.uniform(Some(&sky));
+        assert_eq!(uniform.color, sky.horizon_color());
+        assert_eq!(uniform.sun_color, sky.sun_color());
+        assert_eq!(uniform.mode, 1);
+        assert_eq!(uniform.params, [10.0, 50.0, 0.0, 0.0]);
+
+        assert_eq!(fog.uniform(None).color, fog.color);
+    }
+}
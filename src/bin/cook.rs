@@ -0,0 +1,36 @@
+//! Cooks the models of a resource folder into the engine's binary format,
+//! so that they load without being parsed. Models already cooked from the
+//! same sources and options are skipped.
+//!
+//! `cargo run --bin cook -- [--compact-vertices] [--optimize-meshes] [res]`
+//!
+//! Use the flags the engine is run with: cooked models made with other
+//! options are ignored when loading.
+
+use std::path::PathBuf;
+
+use tuto1::{cook_models, LoadOptions, OptimizeOptions, VertexFormat};
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    let mut options = LoadOptions::default();
+    let mut res_dir = PathBuf::from("res");
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--compact-vertices" => options.vertex_format = VertexFormat::Compact,
+            "--optimize-meshes" => options.optimize = Some(OptimizeOptions::default()),
+            _ => res_dir = PathBuf::from(arg),
+        }
+    }
+
+    let report = async_std::task::block_on(cook_models(&res_dir, &options))?;
+    for name in &report.cooked {
+        println!("Cooked {}", name);
+    }
+    println!(
+        "{} cooked, {} up to date",
+        report.cooked.len(),
+        report.up_to_date.len()
+    );
+    Ok(())
+}
@@ -67,11 +67,64 @@ impl Projection {
         self.aspect = width as f32 / height as f32;
     }
 
+    pub fn aspect(&self) -> f32 {
+        self.aspect
+    }
+
+    pub fn fovy(&self) -> Rad<f32> {
+        self.fovy
+    }
+
+    /// Distances of the near and far clipping planes
+    pub fn depth_range(&self) -> (f32, f32) {
+        (self.znear, self.zfar)
+    }
+
     pub fn calc_matrix(&self) -> Matrix4<f32> {
         OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
     }
 }
 
+/// 2D camera looking down the Z axis, with Y pointing up. At a zoom of 1,
+/// one world unit covers one pixel.
+#[derive(Debug)]
+pub struct OrthographicCamera {
+    pub position: Point2<f32>,
+    pub zoom: f32,
+    width: f32,
+    height: f32,
+}
+
+impl OrthographicCamera {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            position: Point2::new(0.0, 0.0),
+            zoom: 1.0,
+            width: width as f32,
+            height: height as f32,
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width as f32;
+        self.height = height as f32;
+    }
+
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
+        let half_width = self.width * 0.5 / self.zoom;
+        let half_height = self.height * 0.5 / self.zoom;
+        OPENGL_TO_WGPU_MATRIX
+            * ortho(
+                self.position.x - half_width,
+                self.position.x + half_width,
+                self.position.y - half_height,
+                self.position.y + half_height,
+                -1.0,
+                1.0,
+            )
+    }
+}
+
 #[derive(Debug)]
 pub struct CameraController {
     amount_left: f32,
@@ -83,8 +136,8 @@ pub struct CameraController {
     rotate_horizontal: f32,
     rotate_vertical: f32,
     scroll: f32,
-    speed: f32,
-    sensitivity: f32,
+    pub speed: f32,
+    pub sensitivity: f32,
 }
 
 impl CameraController {
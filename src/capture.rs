@@ -0,0 +1,248 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use ::render::capture::CapturedFrame;
+use winit::event::VirtualKeyCode;
+
+pub struct CaptureConfig {
+    /// Key requesting a screenshot of the next frame
+    pub screenshot_key: VirtualKeyCode,
+    /// Directory screenshots and recordings are written to, created if missing
+    pub output_dir: PathBuf,
+    /// Key starting and stopping the recording of every frame
+    pub record_key: VirtualKeyCode,
+    pub record_format: RecordFormat,
+    /// Simulated frame time used while recording instead of the wall-clock
+    /// delta, so that two recordings of the same scene match frame by frame
+    pub record_fixed_dt: Option<instant::Duration>,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            screenshot_key: VirtualKeyCode::F12,
+            output_dir: PathBuf::from("screenshots"),
+            record_key: VirtualKeyCode::F11,
+            record_format: RecordFormat::PngSequence,
+            record_fixed_dt: Some(instant::Duration::from_secs_f64(1.0 / 60.0)),
+        }
+    }
+}
+
+impl CaptureConfig {
+    pub fn screenshot_path(&self) -> PathBuf {
+        self.output_dir
+            .join(format!("screenshot-{}.png", timestamp()))
+    }
+}
+
+fn timestamp() -> u128 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+pub fn save_png(frame: &CapturedFrame, path: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    image::save_buffer_with_format(
+        path,
+        &frame.data,
+        frame.width,
+        frame.height,
+        image::ColorType::Rgba8,
+        image::ImageFormat::Png,
+    )?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// One `frame-00000.png` file per frame
+    PngSequence,
+    /// A single raw YUV4MPEG2 (4:4:4) stream, readable by ffmpeg and most players
+    Y4m,
+}
+
+/// Writes every captured frame of a recording to disk.
+pub struct Recorder {
+    format: RecordFormat,
+    directory: PathBuf,
+    frame_rate: u32,
+    frame_count: u32,
+    size: Option<(u32, u32)>,
+    y4m: Option<BufWriter<File>>,
+}
+
+impl Recorder {
+    pub fn start(config: &CaptureConfig) -> anyhow::Result<Self> {
+        let directory = config.output_dir.join(format!("recording-{}", timestamp()));
+        std::fs::create_dir_all(&directory)?;
+
+        let frame_rate = config
+            .record_fixed_dt
+            .map(|dt| (1.0 / dt.as_secs_f64()).round() as u32)
+            .unwrap_or(60)
+            .max(1);
+
+        Ok(Self {
+            format: config.record_format,
+            directory,
+            frame_rate,
+            frame_count: 0,
+            size: None,
+            y4m: None,
+        })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.frame_count
+    }
+
+    /// Neither format can change resolution midway through a recording
+    fn check_size(&mut self, width: u32, height: u32) -> anyhow::Result<()> {
+        match self.size {
+            Some(size) if size != (width, height) => {
+                anyhow::bail!(
+                    "frame size changed from {}x{} to {}x{} during recording",
+                    size.0,
+                    size.1,
+                    width,
+                    height
+                );
+            }
+            Some(_) => {}
+            None => self.size = Some((width, height)),
+        }
+        Ok(())
+    }
+
+    pub fn write_frame(&mut self, frame: &CapturedFrame) -> anyhow::Result<()> {
+        self.check_size(frame.width, frame.height)?;
+
+        match self.format {
+            RecordFormat::PngSequence => {
+                let path = self
+                    .directory
+                    .join(format!("frame-{:05}.png", self.frame_count));
+                save_png(frame, &path)?;
+            }
+            RecordFormat::Y4m => {
+                if self.y4m.is_none() {
+                    let file = File::create(self.directory.join("recording.y4m"))?;
+                    let mut writer = BufWriter::new(file);
+                    write_y4m_header(&mut writer, frame.width, frame.height, self.frame_rate)?;
+                    self.y4m = Some(writer);
+                }
+                write_y4m_frame(self.y4m.as_mut().unwrap(), frame)?;
+            }
+        }
+
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        if let Some(writer) = self.y4m.as_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn write_y4m_header(
+    writer: &mut impl Write,
+    width: u32,
+    height: u32,
+    frame_rate: u32,
+) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+        width, height, frame_rate
+    )
+}
+
+fn write_y4m_frame(writer: &mut impl Write, frame: &CapturedFrame) -> std::io::Result<()> {
+    writer.write_all(b"FRAME\n")?;
+    writer.write_all(&rgba_to_yuv444(&frame.data))
+}
+
+/// Converts RGBA8 pixels to planar BT.601 limited range Y, U and V planes.
+fn rgba_to_yuv444(rgba: &[u8]) -> Vec<u8> {
+    let pixel_count = rgba.len() / 4;
+    let mut planes = vec![0u8; pixel_count * 3];
+    let (y_plane, chroma) = planes.split_at_mut(pixel_count);
+    let (u_plane, v_plane) = chroma.split_at_mut(pixel_count);
+
+    for (i, pixel) in rgba.chunks_exact(4).enumerate() {
+        let r = pixel[0] as f32;
+        let g = pixel[1] as f32;
+        let b = pixel[2] as f32;
+        y_plane[i] = (16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8;
+        u_plane[i] = (128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8;
+        v_plane[i] = (128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8;
+    }
+
+    planes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32, pixel: [u8; 4]) -> CapturedFrame {
+        CapturedFrame {
+            width,
+            height,
+            data: pixel.repeat((width * height) as usize),
+        }
+    }
+
+    #[test]
+    fn colors_are_converted_to_limited_range_yuv() {
+        let rgba = [[255, 255, 255, 255], [0, 0, 0, 255], [255, 0, 0, 255]].concat();
+        let planes = rgba_to_yuv444(&rgba);
+        // Y plane, then U, then V
+        assert_eq!(planes, [235, 16, 82, 128, 128, 90, 128, 128, 240]);
+    }
+
+    #[test]
+    fn y4m_streams_are_written() {
+        let mut data = Vec::new();
+        write_y4m_header(&mut data, 2, 1, 30).unwrap();
+        assert_eq!(data, b"YUV4MPEG2 W2 H1 F30:1 Ip A1:1 C444\n");
+
+        data.clear();
+        write_y4m_frame(&mut data, &frame(2, 1, [0, 0, 0, 255])).unwrap();
+        assert_eq!(data, b"FRAME\n\x10\x10\x80\x80\x80\x80");
+    }
+
+    #[test]
+    fn recordings_keep_their_frame_size() {
+        let mut recorder = Recorder {
+            format: RecordFormat::Y4m,
+            directory: PathBuf::new(),
+            frame_rate: 60,
+            frame_count: 0,
+            size: None,
+            y4m: None,
+        };
+        recorder.check_size(640, 480).unwrap();
+        recorder.check_size(640, 480).unwrap();
+        let error = recorder.check_size(800, 600).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "frame size changed from 640x480 to 800x600 during recording"
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use cgmath::{Matrix4, Point3, Transform};
+use wgpu::util::DeviceExt;
+
+use crate::{camera, deferred::PointLight, render};
+
+/// Clusters along X and Y in screen space, and along the view depth
+pub const CLUSTER_GRID: [u32; 3] = [16, 9, 16];
+/// Point lights past this count are ignored by clustered shading
+pub const MAX_CLUSTERED_LIGHTS: usize = 256;
+/// Light references shared by all the clusters, sized so every binding
+/// fits in the 16 KiB uniform buffers available on WebGL
+const MAX_LIGHT_INDICES: usize = 8192;
+const CLUSTER_COUNT: usize = (CLUSTER_GRID[0] * CLUSTER_GRID[1] * CLUSTER_GRID[2]) as usize;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClustersUniform {
+    /// Clusters along each axis, then the number of lights
+    grid: [u32; 4],
+    screen_size: [f32; 2],
+    z_near: f32,
+    z_far: f32,
+    lights: [PointLight; MAX_CLUSTERED_LIGHTS],
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Aabb {
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+impl Aabb {
+    fn intersects_sphere(&self, center: Point3<f32>, radius: f32) -> bool {
+        let center: [f32; 3] = center.into();
+        let distance_squared: f32 = (0..3)
+            .map(|i| {
+                let closest = center[i].clamp(self.min[i], self.max[i]);
+                (center[i] - closest).powi(2)
+            })
+            .sum();
+        distance_squared <= radius * radius
+    }
+}
+
+/// View depth where each depth slice of clusters starts. Slices grow
+/// exponentially so that clusters stay roughly cubic.
+fn slice_depth(slice: u32, z_near: f32, z_far: f32) -> f32 {
+    z_near * (z_far / z_near).powf(slice as f32 / CLUSTER_GRID[2] as f32)
+}
+
+/// View space bounds of every cluster, X first then Y from the top of the
+/// screen, then depth
+fn cluster_bounds(projection: &camera::Projection) -> Vec<Aabb> {
+    let (z_near, z_far) = projection.depth_range();
+    let half_height = (projection.fovy().0 * 0.5).tan();
+    let half_width = half_height * projection.aspect();
+
+    let mut bounds = Vec::with_capacity(CLUSTER_COUNT);
+    for z in 0..CLUSTER_GRID[2] {
+        let near = slice_depth(z, z_near, z_far);
+        let far = slice_depth(z + 1, z_near, z_far);
+        for y in 0..CLUSTER_GRID[1] {
+            let top = 1.0 - 2.0 * y as f32 / CLUSTER_GRID[1] as f32;
+            let bottom = 1.0 - 2.0 * (y + 1) as f32 / CLUSTER_GRID[1] as f32;
+            for x in 0..CLUSTER_GRID[0] {
+                let left = -1.0 + 2.0 * x as f32 / CLUSTER_GRID[0] as f32;
+                let right = -1.0 + 2.0 * (x + 1) as f32 / CLUSTER_GRID[0] as f32;
+                // The tile widens with depth, its extremes are on the near
+                // or far plane
+                let xs =
+                    [left * near, right * near, left * far, right * far].map(|x| x * half_width);
+                let ys =
+                    [bottom * near, top * near, bottom * far, top * far].map(|y| y * half_height);
+                bounds.push(Aabb {
+                    min: [
+                        xs.iter().copied().fold(f32::MAX, f32::min),
+                        ys.iter().copied().fold(f32::MAX, f32::min),
+                        -far,
+                    ],
+                    max: [
+                        xs.iter().copied().fold(f32::MIN, f32::max),
+                        ys.iter().copied().fold(f32::MIN, f32::max),
+                        -near,
+                    ],
+                });
+            }
+        }
+    }
+    bounds
+}
+
+/// Lists the lights reaching each cluster. Returns, per cluster, the offset
+/// of its first light index packed with the light count in the high 16 bits,
+/// and the light indices themselves, as `cluster.wgsl` unpacks them.
+fn assign_lights(
+    view: Matrix4<f32>,
+    projection: &camera::Projection,
+    lights: &[PointLight],
+) -> (Vec<u32>, Vec<u16>) {
+    let (z_near, z_far) = projection.depth_range();
+    let bounds = cluster_bounds(projection);
+    let mut cluster_lights = vec![Vec::new(); CLUSTER_COUNT];
+
+    let slices_per_plane = (CLUSTER_GRID[0] * CLUSTER_GRID[1]) as usize;
+    for (i, light) in lights.iter().enumerate().take(MAX_CLUSTERED_LIGHTS) {
+        let center = view.transform_point(light.position.into());
+        let radius = light.radius;
+        if -center.z + radius < z_near || -center.z - radius > z_far {
+            continue;
+        }
+
+        // Only the slices overlapping the light depth need testing
+        let first = (0..CLUSTER_GRID[2])
+            .find(|&z| slice_depth(z + 1, z_near, z_far) >= -center.z - radius)
+            .unwrap_or(0);
+        let last = (0..CLUSTER_GRID[2])
+            .rev()
+            .find(|&z| slice_depth(z, z_near, z_far) <= -center.z + radius)
+            .unwrap_or(CLUSTER_GRID[2] - 1);
+        for z in first as usize..=last as usize {
+            let slice = z * slices_per_plane..(z + 1) * slices_per_plane;
+            for cluster in slice {
+                if bounds[cluster].intersects_sphere(center, radius) {
+                    cluster_lights[cluster].push(i as u16);
+                }
+            }
+        }
+    }
+
+    let mut ranges = Vec::with_capacity(CLUSTER_COUNT);
+    let mut indices = Vec::new();
+    let mut truncated = false;
+    for lights in cluster_lights {
+        let count = lights.len().min(MAX_LIGHT_INDICES - indices.len());
+        truncated |= count < lights.len();
+        ranges.push(indices.len() as u32 | (count as u32) << 16);
+        indices.extend_from_slice(&lights[..count]);
+    }
+    if truncated {
+        log::warn!("Too many lights overlap the view, some clusters miss lights");
+    }
+
+    (ranges, indices)
+}
+
+/// Assigns point lights to clusters of the view frustum, so that forward
+/// shaders only evaluate the lights close to each fragment. The assignment
+/// doesn't depend on the depth buffer, transparent surfaces are lit too.
+pub struct ClusteredLights {
+    clusters_buffer: wgpu::Buffer,
+    ranges_buffer: wgpu::Buffer,
+    indices_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ClusteredLights {
+    pub fn new(device: &wgpu::Device, global_bind_layout: &render::GlobalBindLayout) -> Self {
+        let clusters_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Clusters Buffer"),
+            contents: bytemuck::bytes_of(&ClustersUniform {
+                grid: [CLUSTER_GRID[0], CLUSTER_GRID[1], CLUSTER_GRID[2], 0],
+                ..bytemuck::Zeroable::zeroed()
+            }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let ranges_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cluster Ranges Buffer"),
+            contents: bytemuck::cast_slice(&[0u32; CLUSTER_COUNT]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let indices_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cluster Indices Buffer"),
+            contents: bytemuck::cast_slice(&[0u16; MAX_LIGHT_INDICES]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: global_bind_layout.get_cluster_bind_layout(),
+            entries: &bind_group_entries(&clusters_buffer, &ranges_buffer, &indices_buffer),
+            label: Some("cluster_bind_group"),
+        });
+
+        Self {
+            clusters_buffer,
+            ranges_buffer,
+            indices_buffer,
+            bind_group,
+        }
+    }
+
+    pub fn prepare(
+        &self,
+        queue: &wgpu::Queue,
+        camera: &camera::Camera,
+        projection: &camera::Projection,
+        screen_size: winit::dpi::PhysicalSize<u32>,
+        lights: &[PointLight],
+    ) {
+        let lights = &lights[..lights.len().min(MAX_CLUSTERED_LIGHTS)];
+        let (ranges, mut indices) = assign_lights(camera.calc_matrix(), projection, lights);
+        // Uniform buffers are read four bytes at a time
+        if indices.len() % 2 != 0 {
+            indices.push(0);
+        }
+
+        let (z_near, z_far) = projection.depth_range();
+        let mut uniform = ClustersUniform {
+            grid: [
+                CLUSTER_GRID[0],
+                CLUSTER_GRID[1],
+                CLUSTER_GRID[2],
+                lights.len() as u32,
+            ],
+            screen_size: [screen_size.width as f32, screen_size.height as f32],
+            z_near,
+            z_far,
+            ..bytemuck::Zeroable::zeroed()
+        };
+        uniform.lights[..lights.len()].copy_from_slice(lights);
+
+        queue.write_buffer(&self.clusters_buffer, 0, bytemuck::bytes_of(&uniform));
+        queue.write_buffer(&self.ranges_buffer, 0, bytemuck::cast_slice(&ranges));
+        if !indices.is_empty() {
+            queue.write_buffer(&self.indices_buffer, 0, bytemuck::cast_slice(&indices));
+        }
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Bindings of the clusters, for the bind groups of the pipelines which
+    /// add their own to them
+    pub fn bind_group_entries(&self) -> [wgpu::BindGroupEntry<'_>; 3] {
+        bind_group_entries(
+            &self.clusters_buffer,
+            &self.ranges_buffer,
+            &self.indices_buffer,
+        )
+    }
+}
+
+fn bind_group_entries<'a>(
+    clusters_buffer: &'a wgpu::Buffer,
+    ranges_buffer: &'a wgpu::Buffer,
+    indices_buffer: &'a wgpu::Buffer,
+) -> [wgpu::BindGroupEntry<'a>; 3] {
+    let entry = |binding, buffer: &'a wgpu::Buffer| wgpu::BindGroupEntry {
+        binding,
+        resource: buffer.as_entire_binding(),
+    };
+    [
+        entry(0, clusters_buffer),
+        entry(1, ranges_buffer),
+        entry(2, indices_buffer),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lights_only_reach_nearby_clusters() {
+        let projection = camera::Projection::new(1600, 900, cgmath::Deg(90.0), 0.1, 100.0);
+        let light = |position, radius| PointLight {
+            position,
+            radius,
+            color: [1.0; 3],
+            intensity: 1.0,
+        };
+        // Straight ahead of the camera, and behind it
+        let lights = [light([0.0, 0.0, -10.0], 1.0), light([0.0, 0.0, 5.0], 1.0)];
+
+        let (ranges, indices) = assign_lights(Matrix4::from_scale(1.0), &projection, &lights);
+
+        let count = |cluster: usize| (ranges[cluster] >> 16) as usize;
+        let plane = (CLUSTER_GRID[0] * CLUSTER_GRID[1]) as usize;
+        let slice = (0..CLUSTER_GRID[2])
+            .find(|&z| slice_depth(z + 1, 0.1, 100.0) > 10.0)
+            .unwrap() as usize;
+        // Tiles in the middle of the screen
+        let center = slice * plane + 4 * CLUSTER_GRID[0] as usize + 7;
+        assert_eq!(count(center), 1);
+        assert_eq!(count(center + 1), 1);
+        // Screen corner at the same depth
+        assert_eq!(count(slice * plane), 0);
+        // Right next to the camera
+        assert_eq!(count(4 * CLUSTER_GRID[0] as usize + 7), 0);
+
+        assert!(indices.iter().all(|&i| i == 0));
+        assert_eq!(indices.len(), (0..CLUSTER_COUNT).map(count).sum::<usize>());
+    }
+}
@@ -0,0 +1,687 @@
+//! Engine-native binary format for models, written by the `cook` tool so
+//! that loading skips parsing and mesh processing.
+//!
+//! Everything is little endian. Vertex and index data are stored in their
+//! GPU layout, 4-byte aligned from the start of the file, so they can be
+//! uploaded as they are read or mapped.
+
+use std::{ops::Range, path::Path, sync::Arc};
+
+use anyhow::{bail, Context};
+use cgmath::Matrix4;
+
+use crate::{model, resources};
+
+const MAGIC: &[u8; 4] = b"TMDL";
+/// Bumped whenever the layout or the processing of meshes changes, so that
+/// older files get cooked again
+const VERSION: u32 = 1;
+
+/// Cooked files of a model are read from `COOKED_DIR/<model file>.mesh`,
+/// relative to the resources
+pub const COOKED_DIR: &str = "cooked";
+
+pub fn cooked_path(file_name: &str) -> String {
+    format!("{}/{}.mesh", COOKED_DIR, file_name)
+}
+
+/// 64-bit FNV-1a, stable across builds and platforms unlike the standard
+/// library hasher
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Hash of the dependencies that failed to read, like the MTL file of an
+/// OBJ model that has none yet, so that creating them makes the model stale
+pub const MISSING_HASH: u64 = 0;
+
+/// File read to produce a model, with the hash of its contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub path: String,
+    pub hash: u64,
+}
+
+/// What a cooked model was made from. The model is stale when any of it
+/// changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CookKey {
+    /// Hash of the load options, see `options_hash`
+    pub options: u64,
+    pub dependencies: Vec<Dependency>,
+}
+
+impl CookKey {
+    /// Reads the key at the start of a cooked file, without the rest
+    pub fn read(data: &[u8]) -> anyhow::Result<Self> {
+        Reader::new(data)?.key()
+    }
+}
+
+pub fn options_hash(options: &resources::LoadOptions) -> u64 {
+    // The debug output holds every option and its value
+    content_hash(format!("{:?}", options).as_bytes())
+}
+
+/// Image of a material map, decoded when the model is uploaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageData {
+    /// Path relative to the resources
+    File(String),
+    /// Encoded image embedded in the model
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialData {
+    pub name: String,
+    pub params: model::MaterialParams,
+    /// Indices into `ModelData::images`, the default textures being used
+    /// without
+    pub diffuse_image: Option<usize>,
+    pub normal_image: Option<usize>,
+}
+
+/// Contents of a vertex or index buffer, either made when loading the model
+/// or a slice of the cooked file it was read from, shared by all of its
+/// meshes rather than copied
+#[derive(Clone)]
+pub struct BufferData {
+    data: Arc<Vec<u8>>,
+    range: Range<usize>,
+}
+
+impl From<Vec<u8>> for BufferData {
+    fn from(data: Vec<u8>) -> Self {
+        let range = 0..data.len();
+        Self {
+            data: Arc::new(data),
+            range,
+        }
+    }
+}
+
+impl std::ops::Deref for BufferData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data[self.range.clone()]
+    }
+}
+
+impl PartialEq for BufferData {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl std::fmt::Debug for BufferData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bytes", self.len())
+    }
+}
+
+/// Mesh ready to upload, in the vertex format of its model
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshData {
+    pub name: String,
+    pub material: usize,
+    pub index_format: wgpu::IndexFormat,
+    pub index_count: u32,
+    pub vertices: BufferData,
+    pub indices: BufferData,
+}
+
+/// Model whose meshes are processed but not uploaded yet
+#[derive(Debug, Clone)]
+pub struct ModelData {
+    pub key: CookKey,
+    pub vertex_format: model::VertexFormat,
+    /// Shared by the materials using the same image
+    pub images: Vec<ImageData>,
+    pub materials: Vec<MaterialData>,
+    pub nodes: Vec<model::Node>,
+    pub meshes: Vec<MeshData>,
+}
+
+impl ModelData {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer(Vec::new());
+        writer.0.extend_from_slice(MAGIC);
+        writer.u32(VERSION);
+        writer.u64(self.key.options);
+        writer.u32(self.key.dependencies.len() as u32);
+        for dependency in &self.key.dependencies {
+            writer.str(&dependency.path);
+            writer.u64(dependency.hash);
+        }
+
+        writer.u8(match self.vertex_format {
+            model::VertexFormat::Full => 0,
+            model::VertexFormat::Compact => 1,
+        });
+        writer.u32(self.images.len() as u32);
+        for image in &self.images {
+            match image {
+                ImageData::File(path) => {
+                    writer.u8(0);
+                    writer.str(path);
+                }
+                ImageData::Bytes(bytes) => {
+                    writer.u8(1);
+                    writer.bytes(bytes);
+                }
+            }
+        }
+
+        writer.u32(self.materials.len() as u32);
+        for material in &self.materials {
+            let params = &material.params;
+            writer.str(&material.name);
+            for c in [params.ambient, params.diffuse, params.specular]
+                .iter()
+                .flatten()
+            {
+                writer.f32(*c);
+            }
+            writer.f32(params.shininess);
+            writer.f32(params.opacity);
+            writer.u32(params.illumination);
+            match params.alpha_mode {
+                model::AlphaMode::Opaque => writer.u8(0),
+                model::AlphaMode::Mask(cutoff) => {
+                    writer.u8(1);
+                    writer.f32(cutoff);
+                }
+                model::AlphaMode::Blend => writer.u8(2),
+            }
+            writer.index(material.diffuse_image);
+            writer.index(material.normal_image);
+        }
+
+        writer.u32(self.nodes.len() as u32);
+        for node in &self.nodes {
+            writer.str(&node.name);
+            writer.index(node.parent);
+            let columns: [[f32; 4]; 4] = node.transform.into();
+            columns.iter().flatten().for_each(|&x| writer.f32(x));
+            writer.u32(node.meshes.len() as u32);
+            for &mesh in &node.meshes {
+                writer.u32(mesh as u32);
+            }
+        }
+
+        writer.u32(self.meshes.len() as u32);
+        for mesh in &self.meshes {
+            writer.str(&mesh.name);
+            writer.u32(mesh.material as u32);
+            writer.u8(match mesh.index_format {
+                wgpu::IndexFormat::Uint16 => 0,
+                wgpu::IndexFormat::Uint32 => 1,
+            });
+            writer.u32(mesh.index_count);
+            writer.bytes(&mesh.vertices);
+            writer.bytes(&mesh.indices);
+        }
+        writer.0
+    }
+
+    /// Reads a cooked model, whose meshes keep slices of `data` instead of
+    /// copying them
+    pub fn from_bytes(data: Vec<u8>) -> anyhow::Result<Self> {
+        let data = Arc::new(data);
+        let mut reader = Reader::new(&data)?;
+        let key = reader.key()?;
+        let vertex_format = match reader.u8()? {
+            0 => model::VertexFormat::Full,
+            1 => model::VertexFormat::Compact,
+            format => bail!("Unknown vertex format {}", format),
+        };
+
+        let images = (0..reader.u32()?)
+            .map(|_| {
+                Ok(match reader.u8()? {
+                    0 => ImageData::File(reader.str()?),
+                    1 => ImageData::Bytes(reader.bytes()?.to_vec()),
+                    kind => bail!("Unknown image kind {}", kind),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let materials = (0..reader.u32()?)
+            .map(|_| {
+                let name = reader.str()?;
+                let mut color = || -> anyhow::Result<[f32; 3]> {
+                    Ok([reader.f32()?, reader.f32()?, reader.f32()?])
+                };
+                let (ambient, diffuse, specular) = (color()?, color()?, color()?);
+                let params = model::MaterialParams {
+                    ambient,
+                    diffuse,
+                    specular,
+                    shininess: reader.f32()?,
+                    opacity: reader.f32()?,
+                    illumination: reader.u32()?,
+                    alpha_mode: match reader.u8()? {
+                        0 => model::AlphaMode::Opaque,
+                        1 => model::AlphaMode::Mask(reader.f32()?),
+                        2 => model::AlphaMode::Blend,
+                        mode => bail!("Unknown alpha mode {}", mode),
+                    },
+                };
+                Ok(MaterialData {
+                    name,
+                    params,
+                    diffuse_image: reader.index(images.len())?,
+                    normal_image: reader.index(images.len())?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let node_count = reader.u32()? as usize;
+        let mut nodes = Vec::with_capacity(node_count.min(data.len()));
+        for index in 0..node_count {
+            let name = reader.str()?;
+            // Parents come first
+            let parent = reader.index(index)?;
+            let mut columns = [[0.0; 4]; 4];
+            for x in columns.iter_mut().flatten() {
+                *x = reader.f32()?;
+            }
+            let meshes = (0..reader.u32()?)
+                .map(|_| reader.u32().map(|mesh| mesh as usize))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            nodes.push(model::Node {
+                name,
+                parent,
+                transform: Matrix4::from(columns),
+                meshes,
+            });
+        }
+
+        let meshes = (0..reader.u32()?)
+            .map(|_| {
+                let name = reader.str()?;
+                let material = reader.u32()? as usize;
+                if material >= materials.len() {
+                    bail!("Mesh {} has no material {}", name, material);
+                }
+                let index_format = match reader.u8()? {
+                    0 => wgpu::IndexFormat::Uint16,
+                    1 => wgpu::IndexFormat::Uint32,
+                    format => bail!("Unknown index format {}", format),
+                };
+                let index_count = reader.u32()?;
+                let vertices = BufferData {
+                    data: data.clone(),
+                    range: reader.buffer()?,
+                };
+                let indices = BufferData {
+                    data: data.clone(),
+                    range: reader.buffer()?,
+                };
+                // Drawing past the end of the buffers is a validation error
+                let index_size = match index_format {
+                    wgpu::IndexFormat::Uint16 => 2,
+                    wgpu::IndexFormat::Uint32 => 4,
+                };
+                if !indices.len().is_multiple_of(index_size)
+                    || indices.len() / index_size != index_count as usize
+                {
+                    bail!(
+                        "Mesh {} has {} bytes of indices for {} of them",
+                        name,
+                        indices.len(),
+                        index_count
+                    );
+                }
+                if !vertices.len().is_multiple_of(vertex_format.size()) {
+                    bail!(
+                        "Mesh {} has {} bytes of vertices of {} bytes",
+                        name,
+                        vertices.len(),
+                        vertex_format.size()
+                    );
+                }
+                let vertex_count = vertices.len() / vertex_format.size();
+                if let Some(index) = max_index(&indices, index_format) {
+                    if index as usize >= vertex_count {
+                        bail!("Mesh {} uses vertex {} of {}", name, index, vertex_count);
+                    }
+                }
+                Ok(MeshData {
+                    name,
+                    material,
+                    index_format,
+                    index_count,
+                    vertices,
+                    indices,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if nodes
+            .iter()
+            .flat_map(|node| &node.meshes)
+            .any(|&mesh| mesh >= meshes.len())
+        {
+            bail!("A node uses a missing mesh");
+        }
+
+        Ok(Self {
+            key,
+            vertex_format,
+            images,
+            materials,
+            nodes,
+            meshes,
+        })
+    }
+}
+
+/// Largest index of an index buffer, `None` when it is empty
+fn max_index(indices: &[u8], format: wgpu::IndexFormat) -> Option<u32> {
+    match format {
+        wgpu::IndexFormat::Uint16 => indices
+            .chunks_exact(2)
+            .map(|index| u16::from_le_bytes([index[0], index[1]]) as u32)
+            .max(),
+        wgpu::IndexFormat::Uint32 => indices
+            .chunks_exact(4)
+            .map(|index| u32::from_le_bytes(index.try_into().unwrap()))
+            .max(),
+    }
+}
+
+/// Models cooked or already up to date
+#[derive(Debug, Default)]
+pub struct CookReport {
+    pub cooked: Vec<String>,
+    pub up_to_date: Vec<String>,
+}
+
+/// Cooks every OBJ and glTF model under `res_dir` into
+/// `res_dir/COOKED_DIR`, skipping the ones whose cooked file was made from
+/// the same sources and options
+pub async fn cook_models(
+    res_dir: &Path,
+    options: &resources::LoadOptions,
+) -> anyhow::Result<CookReport> {
+    let mut models = Vec::new();
+    let mut directories = vec![res_dir.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(&directory)
+            .with_context(|| format!("Failed to list {}", directory.display()))?
+        {
+            let path = entry?.path();
+            if path.is_dir() {
+                if path != res_dir.join(COOKED_DIR) {
+                    directories.push(path);
+                }
+                continue;
+            }
+            let extension = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .map(str::to_ascii_lowercase);
+            if matches!(extension.as_deref(), Some("obj" | "gltf" | "glb")) {
+                // Named like the runtime loads them, relative with slashes
+                let relative = path.strip_prefix(res_dir)?;
+                let name = relative
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                models.push(name);
+            }
+        }
+    }
+    models.sort();
+
+    let source = resources::AssetSource::Directory(res_dir);
+    let mut report = CookReport::default();
+    for name in models {
+        let output = res_dir.join(cooked_path(&name));
+        if let Ok(data) = std::fs::read(&output) {
+            if let Ok(key) = CookKey::read(&data) {
+                if resources::is_up_to_date(&key, options, source).await {
+                    report.up_to_date.push(name);
+                    continue;
+                }
+            }
+        }
+
+        let model = resources::load_model_data(&name, source, options)
+            .await
+            .with_context(|| format!("Failed to cook {}", name))?;
+        if let Some(parent) = output.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&output, model.to_bytes())
+            .with_context(|| format!("Failed to write {}", output.display()))?;
+        report.cooked.push(name);
+    }
+    Ok(report)
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// `u32::MAX` for `None`
+    fn index(&mut self, index: Option<usize>) {
+        self.u32(index.map_or(u32::MAX, |index| index as u32));
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value.as_bytes());
+    }
+
+    /// Length, then the bytes starting on a 4-byte boundary
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.0.resize(self.0.len().next_multiple_of(4), 0);
+        self.0.extend_from_slice(bytes);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> anyhow::Result<Self> {
+        if !data.starts_with(MAGIC) {
+            bail!("Not a cooked model");
+        }
+        let mut reader = Self { data, position: 4 };
+        let version = reader.u32()?;
+        if version != VERSION {
+            bail!("Cooked model version {} is not {}", version, VERSION);
+        }
+        Ok(reader)
+    }
+
+    fn take(&mut self, length: usize) -> anyhow::Result<&'a [u8]> {
+        let bytes = self
+            .position
+            .checked_add(length)
+            .and_then(|end| self.data.get(self.position..end))
+            .context("Truncated cooked model")?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn f32(&mut self) -> anyhow::Result<f32> {
+        self.array().map(f32::from_le_bytes)
+    }
+
+    /// Index below `count`, or `None`
+    fn index(&mut self, count: usize) -> anyhow::Result<Option<usize>> {
+        match self.u32()? {
+            u32::MAX => Ok(None),
+            index if (index as usize) < count => Ok(Some(index as usize)),
+            index => bail!("Index {} is out of bounds of {} items", index, count),
+        }
+    }
+
+    fn str(&mut self) -> anyhow::Result<String> {
+        let length = self.u32()? as usize;
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec()).context("Invalid UTF-8 in cooked model")
+    }
+
+    fn bytes(&mut self) -> anyhow::Result<&'a [u8]> {
+        let range = self.buffer()?;
+        Ok(&self.data[range])
+    }
+
+    /// Range of the aligned bytes that follow
+    fn buffer(&mut self) -> anyhow::Result<Range<usize>> {
+        let length = self.u32()? as usize;
+        self.position = self.position.next_multiple_of(4);
+        let start = self.position;
+        self.take(length)?;
+        Ok(start..self.position)
+    }
+
+    fn key(&mut self) -> anyhow::Result<CookKey> {
+        let options = self.u64()?;
+        let dependencies = (0..self.u32()?)
+            .map(|_| {
+                Ok(Dependency {
+                    path: self.str()?,
+                    hash: self.u64()?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(CookKey {
+            options,
+            dependencies,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model() -> ModelData {
+        ModelData {
+            key: CookKey {
+                options: 42,
+                dependencies: vec![Dependency {
+                    path: "crate.obj".to_string(),
+                    hash: content_hash(b"v 0 0 0"),
+                }],
+            },
+            vertex_format: model::VertexFormat::Compact,
+            images: vec![
+                ImageData::File("crate.png".to_string()),
+                ImageData::Bytes(vec![1, 2, 3]),
+            ],
+            materials: vec![MaterialData {
+                name: "wood".to_string(),
+                params: model::MaterialParams {
+                    alpha_mode: model::AlphaMode::Mask(0.25),
+                    ..Default::default()
+                },
+                diffuse_image: Some(1),
+                normal_image: None,
+            }],
+            nodes: vec![model::Node {
+                name: "root".to_string(),
+                parent: None,
+                transform: Matrix4::from_translation([1.0, 2.0, 3.0].into()),
+                meshes: vec![0],
+            }],
+            meshes: vec![MeshData {
+                name: "crate".to_string(),
+                material: 0,
+                index_format: wgpu::IndexFormat::Uint16,
+                index_count: 3,
+                vertices: vec![7; 28 * 3].into(),
+                indices: vec![0, 0, 1, 0, 2, 0].into(),
+            }],
+        }
+    }
+
+    #[test]
+    fn models_survive_a_round_trip() {
+        let model = model();
+        let bytes = model.to_bytes();
+        assert_eq!(CookKey::read(&bytes).unwrap(), model.key);
+
+        let read = ModelData::from_bytes(bytes.clone()).unwrap();
+        assert_eq!(read.vertex_format, model.vertex_format);
+        assert_eq!(read.images, model.images);
+        assert_eq!(read.materials, model.materials);
+        assert_eq!(read.nodes[0].transform, model.nodes[0].transform);
+        assert_eq!(read.meshes, model.meshes);
+        // Meshes are slices of the file rather than copies
+        let mesh = &read.meshes[0];
+        assert!(Arc::ptr_eq(&mesh.vertices.data, &mesh.indices.data));
+        assert_eq!(mesh.vertices.data.len(), bytes.len());
+
+        // Vertex data starts aligned, ready to be uploaded in place
+        let offset = bytes.windows(3).position(|w| w == [7, 7, 7]).unwrap();
+        assert!(offset.is_multiple_of(4));
+    }
+
+    #[test]
+    fn truncated_models_are_errors() {
+        let bytes = model().to_bytes();
+        for length in [0, 4, 16, bytes.len() - 1] {
+            assert!(ModelData::from_bytes(bytes[..length].to_vec()).is_err());
+        }
+    }
+
+    #[test]
+    fn inconsistent_meshes_are_errors() {
+        let breaks: [fn(&mut MeshData); 4] = [
+            |mesh| mesh.index_count = 4,
+            |mesh| mesh.indices = vec![0, 0, 1, 0, 2, 0, 0].into(),
+            |mesh| mesh.vertices = vec![7; 28 * 3 - 1].into(),
+            |mesh| mesh.indices = vec![0, 0, 1, 0, 3, 0].into(),
+        ];
+        for break_mesh in breaks {
+            let mut model = model();
+            break_mesh(&mut model.meshes[0]);
+            assert!(ModelData::from_bytes(model.to_bytes()).is_err());
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use crate::{model, render, texture};
+
+/// Surface attributes of every pixel, written by the geometry pass and read
+/// by the lighting passes. Depth comes from the regular depth texture.
+pub struct GBuffer {
+    albedo: texture::Texture,
+    normal: texture::Texture,
+    material: texture::Texture,
+}
+
+impl GBuffer {
+    pub const ALBEDO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+    /// World space normals
+    pub const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    /// Specular strength in red, shininess divided by 256 in green
+    pub const MATERIAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+    pub const FORMATS: [wgpu::TextureFormat; 3] = [
+        Self::ALBEDO_FORMAT,
+        Self::NORMAL_FORMAT,
+        Self::MATERIAL_FORMAT,
+    ];
+
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let create = |format, label| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: config.width,
+                    height: config.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[format],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            // Lighting passes load exact texels, the sampler is never used
+            let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+            texture::Texture {
+                texture,
+                view,
+                sampler,
+            }
+        };
+
+        Self {
+            albedo: create(Self::ALBEDO_FORMAT, "gbuffer_albedo"),
+            normal: create(Self::NORMAL_FORMAT, "gbuffer_normal"),
+            material: create(Self::MATERIAL_FORMAT, "gbuffer_material"),
+        }
+    }
+
+    fn views(&self) -> [&wgpu::TextureView; 3] {
+        [&self.albedo.view, &self.normal.view, &self.material.view]
+    }
+}
+
+/// Light shining in every direction up to `radius`, drawn as light volumes
+/// by the deferred renderer and through `cluster::ClusteredLights` when
+/// forward rendering
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointLight {
+    pub position: [f32; 3],
+    pub radius: f32,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl model::Vertex for PointLight {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<PointLight>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DeferredUniform {
+    /// Turns depth buffer coordinates back into world positions
+    inv_view_proj: [[f32; 4]; 4],
+}
+
+/// Owns the G-buffer and the per-frame data of the lighting passes
+pub struct DeferredRenderer {
+    gbuffer: GBuffer,
+    uniform_buffer: wgpu::Buffer,
+    gbuffer_bind_group: wgpu::BindGroup,
+    light_buffer: wgpu::Buffer,
+    light_capacity: usize,
+    light_count: u32,
+}
+
+impl DeferredRenderer {
+    const INITIAL_LIGHT_CAPACITY: usize = 64;
+
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        pipelines: &render::DeferredPipelines,
+        depth_texture: &texture::Texture,
+    ) -> Self {
+        let gbuffer = GBuffer::new(device, config);
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Deferred Buffer"),
+            contents: bytemuck::cast_slice(&[DeferredUniform {
+                inv_view_proj: Matrix4::identity().into(),
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let gbuffer_bind_group = Self::create_gbuffer_bind_group(
+            device,
+            pipelines,
+            &gbuffer,
+            depth_texture,
+            &uniform_buffer,
+        );
+
+        Self {
+            gbuffer,
+            uniform_buffer,
+            gbuffer_bind_group,
+            light_buffer: Self::create_light_buffer(device, Self::INITIAL_LIGHT_CAPACITY),
+            light_capacity: Self::INITIAL_LIGHT_CAPACITY,
+            light_count: 0,
+        }
+    }
+
+    fn create_gbuffer_bind_group(
+        device: &wgpu::Device,
+        pipelines: &render::DeferredPipelines,
+        gbuffer: &GBuffer,
+        depth_texture: &texture::Texture,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        let [albedo, normal, material] = gbuffer.views();
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipelines.get_gbuffer_bind_layout(),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(albedo),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(normal),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(material),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("gbuffer_bind_group"),
+        })
+    }
+
+    fn create_light_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Point Light Buffer"),
+            size: (capacity * std::mem::size_of::<PointLight>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Recreates the G-buffer at the new surface size. `depth_texture` must
+    /// be the depth texture recreated for the same size.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        pipelines: &render::DeferredPipelines,
+        depth_texture: &texture::Texture,
+    ) {
+        self.gbuffer = GBuffer::new(device, config);
+        self.gbuffer_bind_group = Self::create_gbuffer_bind_group(
+            device,
+            pipelines,
+            &self.gbuffer,
+            depth_texture,
+            &self.uniform_buffer,
+        );
+    }
+
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        view_proj: Matrix4<f32>,
+        lights: &[PointLight],
+    ) {
+        let uniform = DeferredUniform {
+            inv_view_proj: view_proj.invert().unwrap_or(Matrix4::identity()).into(),
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        if lights.len() > self.light_capacity {
+            self.light_capacity = lights.len().next_power_of_two();
+            self.light_buffer = Self::create_light_buffer(device, self.light_capacity);
+        }
+        if !lights.is_empty() {
+            queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(lights));
+        }
+        self.light_count = lights.len() as u32;
+    }
+
+    /// Starts the pass drawing opaque geometry into the G-buffer
+    pub fn begin_geometry_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        depth_view: &'a wgpu::TextureView,
+    ) -> wgpu::RenderPass<'a> {
+        let attachments = self.gbuffer.views().map(|view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })
+        });
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Geometry Pass"),
+            color_attachments: &attachments,
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        })
+    }
+
+    /// Shades the G-buffer into `view`, pixels without geometry are cleared
+    /// to `clear_color`
+    pub fn render_lighting(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        clear_color: wgpu::Color,
+        pipelines: &render::DeferredPipelines,
+        camera_bind_group: &wgpu::BindGroup,
+        light_bind_group: &wgpu::BindGroup,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Lighting Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear_color),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, light_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.gbuffer_bind_group, &[]);
+
+        render_pass.set_pipeline(pipelines.get_light_pipeline());
+        render_pass.draw(0..3, 0..1);
+
+        if self.light_count > 0 {
+            render_pass.set_pipeline(pipelines.get_point_light_pipeline());
+            render_pass.set_vertex_buffer(0, self.light_buffer.slice(..));
+            render_pass.draw(0..36, 0..self.light_count);
+        }
+    }
+}
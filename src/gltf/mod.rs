@@ -0,0 +1,577 @@
+//! glTF 2.0 documents, read from `.gltf` JSON files or `.glb` containers.
+//!
+//! Only the parts the renderer can draw are read: triangle primitives, their
+//! metallic-roughness materials and the node hierarchy placing them.
+//! Skins, animations and cameras are ignored. Parsing and validation are
+//! left to the `gltf` crate, files are read by the caller.
+
+use anyhow::{bail, Context};
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, SquareMatrix, Vector3};
+use gltf::accessor::{DataType, Dimensions};
+
+use crate::model;
+
+/// Where the bytes of a buffer or an image are
+#[derive(Debug, PartialEq, Eq)]
+pub enum Source {
+    Bytes(Vec<u8>),
+    /// Path relative to the document
+    File(String),
+}
+
+/// Material of a primitive, with its maps as indices of document images
+pub struct Material {
+    pub name: String,
+    pub params: model::MaterialParams,
+    pub base_color_image: Option<usize>,
+    pub normal_image: Option<usize>,
+}
+
+/// Triangles placed in model space by the transforms of their node
+pub struct Primitive {
+    pub name: String,
+    pub vertices: Vec<model::ModelVertex>,
+    pub indices: Vec<u32>,
+    pub material: Option<usize>,
+    pub has_normals: bool,
+    pub has_tex_coords: bool,
+    /// Bitangents are only set when the document has tangents
+    pub has_tangents: bool,
+}
+
+pub struct Scene {
+    /// Nodes reachable from the scene, parents first, whose `meshes` index
+    /// `primitives`
+    pub nodes: Vec<model::Node>,
+    pub primitives: Vec<Primitive>,
+}
+
+pub struct Document {
+    gltf: gltf::Document,
+    /// Binary chunk of a `.glb`, until `buffer_sources` hands it out
+    binary: Option<Vec<u8>>,
+}
+
+impl Document {
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let gltf::Gltf { document, blob } =
+            gltf::Gltf::from_slice_without_validation(data).context("Invalid glTF")?;
+
+        let root = document.into_json();
+        if !root.asset.version.starts_with("2.") {
+            bail!("glTF {} is not supported, only 2.x is", root.asset.version);
+        }
+        if !root.extensions_required.is_empty() {
+            bail!(
+                "Required extensions are not supported: {}",
+                root.extensions_required.join(", ")
+            );
+        }
+        let gltf = gltf::Document::from_json(root).context("Invalid glTF")?;
+        Ok(Self { gltf, binary: blob })
+    }
+
+    /// Sources of the buffers, the binary chunk of a `.glb` being the first
+    /// buffer without a URI
+    pub fn buffer_sources(&mut self) -> anyhow::Result<Vec<Source>> {
+        let mut binary = self.binary.take();
+        self.gltf
+            .buffers()
+            .map(|buffer| match buffer.source() {
+                gltf::buffer::Source::Uri(uri) => uri_source(uri),
+                gltf::buffer::Source::Bin => binary
+                    .take()
+                    .map(Source::Bytes)
+                    .with_context(|| format!("Buffer {} has no data", buffer.index())),
+            })
+            .collect()
+    }
+
+    pub fn image_source(&self, index: usize, buffers: &[Vec<u8>]) -> anyhow::Result<Source> {
+        let image = self
+            .gltf
+            .images()
+            .nth(index)
+            .with_context(|| format!("Missing image {}", index))?;
+        match image.source() {
+            gltf::image::Source::Uri { uri, .. } => uri_source(uri),
+            gltf::image::Source::View { view, .. } => {
+                buffer_view(&view, buffers).map(|bytes| Source::Bytes(bytes.to_vec()))
+            }
+        }
+    }
+
+    pub fn materials(&self) -> Vec<Material> {
+        self.gltf
+            .materials()
+            .enumerate()
+            .map(|(index, material)| {
+                let pbr = material.pbr_metallic_roughness();
+                let alpha_mode = match material.alpha_mode() {
+                    gltf::material::AlphaMode::Mask => {
+                        model::AlphaMode::Mask(material.alpha_cutoff().unwrap_or(0.5))
+                    }
+                    gltf::material::AlphaMode::Blend => model::AlphaMode::Blend,
+                    gltf::material::AlphaMode::Opaque => model::AlphaMode::Opaque,
+                };
+                Material {
+                    name: material
+                        .name()
+                        .map_or_else(|| format!("material {}", index), str::to_string),
+                    params: model::MaterialParams::from_metallic_roughness(
+                        pbr.base_color_factor(),
+                        pbr.metallic_factor(),
+                        pbr.roughness_factor(),
+                        alpha_mode,
+                    ),
+                    base_color_image: pbr
+                        .base_color_texture()
+                        .map(|info| texture_image(info.texture(), info.tex_coord())),
+                    normal_image: material
+                        .normal_texture()
+                        .map(|normal| texture_image(normal.texture(), normal.tex_coord())),
+                }
+            })
+            .collect()
+    }
+
+    /// Nodes of the default scene and their primitives, or of every root node
+    /// when the document has no scene
+    pub fn scene(&self, buffers: &[Vec<u8>]) -> anyhow::Result<Scene> {
+        let roots = match self
+            .gltf
+            .default_scene()
+            .or_else(|| self.gltf.scenes().next())
+        {
+            Some(scene) => scene.nodes().collect::<Vec<_>>(),
+            None => {
+                let mut is_child = vec![false; self.gltf.nodes().len()];
+                for child in self.gltf.nodes().flat_map(|node| node.children()) {
+                    is_child[child.index()] = true;
+                }
+                self.gltf
+                    .nodes()
+                    .filter(|node| !is_child[node.index()])
+                    .collect()
+            }
+        };
+
+        let mut scene = Scene {
+            nodes: Vec::new(),
+            primitives: Vec::new(),
+        };
+        let mut visited = vec![false; self.gltf.nodes().len()];
+        // Depth first, so that parents come before their children
+        let mut stack = roots
+            .into_iter()
+            .rev()
+            .map(|node| (node, None, Matrix4::identity()))
+            .collect::<Vec<_>>();
+        while let Some((node, parent, parent_transform)) = stack.pop() {
+            if std::mem::replace(&mut visited[node.index()], true) {
+                bail!("Node {} appears twice in the hierarchy", node.index());
+            }
+            let transform = Matrix4::from(node.transform().matrix());
+            let global = parent_transform * transform;
+
+            let mut meshes = Vec::new();
+            if let Some(mesh) = node.mesh() {
+                let name = mesh
+                    .name()
+                    .map_or_else(|| format!("mesh {}", mesh.index()), str::to_string);
+                for primitive in mesh.primitives() {
+                    let primitive = Self::primitive(&primitive, &name, global, buffers)
+                        .with_context(|| {
+                            format!("Invalid primitive {} of {}", primitive.index(), name)
+                        })?;
+                    if let Some(primitive) = primitive {
+                        meshes.push(scene.primitives.len());
+                        scene.primitives.push(primitive);
+                    }
+                }
+            }
+
+            let node_index = scene.nodes.len();
+            scene.nodes.push(model::Node {
+                name: node
+                    .name()
+                    .map_or_else(|| format!("node {}", node.index()), str::to_string),
+                parent,
+                transform,
+                meshes,
+            });
+            stack.extend(
+                node.children()
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .rev()
+                    .map(|child| (child, Some(node_index), global)),
+            );
+        }
+        Ok(scene)
+    }
+
+    /// Triangles of a primitive moved by `transform`, `None` for other
+    /// topologies and empty primitives
+    fn primitive(
+        primitive: &gltf::Primitive,
+        name: &str,
+        transform: Matrix4<f32>,
+        buffers: &[Vec<u8>],
+    ) -> anyhow::Result<Option<Primitive>> {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            log::warn!(
+                "Skipping a primitive of {} drawn in mode {:?}",
+                name,
+                primitive.mode()
+            );
+            return Ok(None);
+        }
+        let positions = primitive
+            .get(&gltf::Semantic::Positions)
+            .context("Missing positions")?;
+        let vertex_count = positions.count();
+        if vertex_count == 0 {
+            return Ok(None);
+        }
+        // The readers of the `gltf` crate trust the accessors to hold what
+        // the specification allows, so that is checked first
+        let float = [DataType::F32];
+        let check = |semantic: gltf::Semantic, dimensions, types: &[DataType]| {
+            let Some(accessor) = primitive.get(&semantic) else {
+                return Ok(false);
+            };
+            check_accessor(&accessor, dimensions, types)
+                .with_context(|| format!("Invalid {:?}", semantic))?;
+            if accessor.count() != vertex_count {
+                bail!(
+                    "{} {:?} for {} positions",
+                    accessor.count(),
+                    semantic,
+                    vertex_count
+                );
+            }
+            Ok(true)
+        };
+        check(gltf::Semantic::Positions, Dimensions::Vec3, &float)?;
+        let has_normals = check(gltf::Semantic::Normals, Dimensions::Vec3, &float)?;
+        let has_tangents = check(gltf::Semantic::Tangents, Dimensions::Vec4, &float)?;
+        let has_tex_coords = check(
+            gltf::Semantic::TexCoords(0),
+            Dimensions::Vec2,
+            &[DataType::U8, DataType::U16, DataType::F32],
+        )?;
+
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+        let out_of_bounds = |what: &str| format!("The {} are out of bounds of their buffer", what);
+        let positions = reader
+            .read_positions()
+            .with_context(|| out_of_bounds("positions"))?
+            .collect::<Vec<_>>();
+        let normals = has_normals
+            .then(|| reader.read_normals().map(Iterator::collect::<Vec<_>>))
+            .map(|normals| normals.with_context(|| out_of_bounds("normals")))
+            .transpose()?;
+        let tangents = has_tangents
+            .then(|| reader.read_tangents().map(Iterator::collect::<Vec<_>>))
+            .map(|tangents| tangents.with_context(|| out_of_bounds("tangents")))
+            .transpose()?;
+        let tex_coords = has_tex_coords
+            .then(|| {
+                reader
+                    .read_tex_coords(0)
+                    .map(|uvs| uvs.into_f32().collect::<Vec<_>>())
+            })
+            .map(|uvs| uvs.with_context(|| out_of_bounds("texture coordinates")))
+            .transpose()?;
+
+        let mut indices = match primitive.indices() {
+            Some(accessor) if accessor.count() == 0 => return Ok(None),
+            Some(accessor) => {
+                check_accessor(
+                    &accessor,
+                    Dimensions::Scalar,
+                    &[DataType::U8, DataType::U16, DataType::U32],
+                )
+                .context("Invalid indices")?;
+                reader
+                    .read_indices()
+                    .with_context(|| out_of_bounds("indices"))?
+                    .into_u32()
+                    .collect::<Vec<_>>()
+            }
+            None => (0..vertex_count as u32).collect(),
+        };
+        if !indices.len().is_multiple_of(3) {
+            bail!("{} indices do not make triangles", indices.len());
+        }
+        if let Some(index) = indices.iter().find(|&&i| i as usize >= vertex_count) {
+            bail!(
+                "Index {} is out of bounds of the {} vertices",
+                index,
+                vertex_count
+            );
+        }
+
+        let linear = Matrix3::from_cols(
+            transform.x.truncate(),
+            transform.y.truncate(),
+            transform.z.truncate(),
+        );
+        let normal_matrix = linear
+            .invert()
+            .map_or(linear, |inverse| inverse.transpose());
+        let vertices = (0..vertex_count)
+            .map(|i| {
+                let normal = normals
+                    .as_ref()
+                    .map_or(Vector3::new(0.0, 0.0, 0.0), |normals| {
+                        Vector3::from(normals[i])
+                    });
+                let (tangent, bitangent) = tangents.as_ref().map_or(
+                    (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)),
+                    |tangents| {
+                        let [x, y, z, w] = tangents[i];
+                        let tangent = Vector3::new(x, y, z);
+                        (tangent, normal.cross(tangent) * w)
+                    },
+                );
+                model::ModelVertex {
+                    position: (transform * Vector3::from(positions[i]).extend(1.0))
+                        .truncate()
+                        .into(),
+                    tex_coords: tex_coords.as_ref().map_or([0.0; 2], |uvs| uvs[i]),
+                    normal: normalized(normal_matrix * normal).into(),
+                    tangent: normalized(linear * tangent).into(),
+                    bitangent: normalized(linear * bitangent).into(),
+                }
+            })
+            .collect();
+        // Mirroring transforms turn the triangles inside out
+        if linear.determinant() < 0.0 {
+            for triangle in indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+
+        Ok(Some(Primitive {
+            name: name.to_string(),
+            vertices,
+            indices,
+            material: primitive.material().index(),
+            has_normals,
+            has_tex_coords,
+            has_tangents: has_tangents && has_normals,
+        }))
+    }
+}
+
+/// Accessors must have the right shape, one of the component `types` and
+/// elements that don't overlap
+fn check_accessor(
+    accessor: &gltf::Accessor,
+    dimensions: Dimensions,
+    types: &[DataType],
+) -> anyhow::Result<()> {
+    if accessor.dimensions() != dimensions || !types.contains(&accessor.data_type()) {
+        bail!(
+            "Accessor {} holds {:?} of {:?}, expected {:?} of {:?}",
+            accessor.index(),
+            accessor.dimensions(),
+            accessor.data_type(),
+            dimensions,
+            types
+        );
+    }
+    let stride = accessor.view().and_then(|view| view.stride());
+    if stride.is_some_and(|stride| stride < accessor.size()) {
+        bail!("Accessor {} has elements overlapping", accessor.index());
+    }
+    Ok(())
+}
+
+fn buffer_view<'a>(view: &gltf::buffer::View, buffers: &'a [Vec<u8>]) -> anyhow::Result<&'a [u8]> {
+    let buffer = buffers
+        .get(view.buffer().index())
+        .with_context(|| format!("Buffer view {} has no buffer", view.index()))?;
+    view.offset()
+        .checked_add(view.length())
+        .and_then(|end| buffer.get(view.offset()..end))
+        .with_context(|| {
+            format!(
+                "Buffer view {} is out of bounds of its buffer",
+                view.index()
+            )
+        })
+}
+
+/// Image of a texture, only the first texture coordinates being imported
+fn texture_image(texture: gltf::Texture, set: u32) -> usize {
+    if set != 0 {
+        log::warn!("Texture coordinates {} are not supported, using 0", set);
+    }
+    texture.source().index()
+}
+
+/// Data URIs are decoded by the `gltf` crate, files are left to the caller
+fn uri_source(uri: &str) -> anyhow::Result<Source> {
+    if uri.starts_with("data:") {
+        let data = gltf::buffer::Data::from_source(gltf::buffer::Source::Uri(uri), None)
+            .context("Invalid data URI")?;
+        Ok(Source::Bytes(data.0))
+    } else {
+        let path = urlencoding::decode(uri).context("Invalid URI")?;
+        Ok(Source::File(path.into_owned()))
+    }
+}
+
+fn normalized(v: Vector3<f32>) -> Vector3<f32> {
+    if v.magnitude2() > 0.0 {
+        v.normalize()
+    } else {
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A quad made of two triangles, moved by a parent and a child node
+    const QUAD: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [1] }],
+        "nodes": [
+            { "name": "child", "mesh": 0, "scale": [-1, 1, 1] },
+            { "name": "parent", "children": [0], "translation": [0, 0, 5] }
+        ],
+        "meshes": [{ "name": "quad", "primitives": [{
+            "attributes": { "POSITION": 0, "TEXCOORD_0": 1 },
+            "indices": 2,
+            "material": 0
+        }] }],
+        "materials": [{
+            "pbrMetallicRoughness": {
+                "baseColorFactor": [1, 0, 0, 0.5],
+                "baseColorTexture": { "index": 0 },
+                "metallicFactor": 0
+            },
+            "alphaMode": "BLEND"
+        }],
+        "textures": [{ "source": 0 }],
+        "images": [{ "uri": "red%20brick.png" }],
+        "buffers": [{ "byteLength": 76, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAACAPwAAgD8AAAAAAAAAAAAAgD8AAAAAAAAHB/8ABwf//wcHAP8HBwAAAQACAAAAAgADAA==" }],
+        "bufferViews": [
+            { "buffer": 0, "byteLength": 48 },
+            { "buffer": 0, "byteOffset": 48, "byteLength": 16, "byteStride": 4 },
+            { "buffer": 0, "byteOffset": 64, "byteLength": 12 }
+        ],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+            { "bufferView": 1, "componentType": 5121, "normalized": true, "count": 4, "type": "VEC2" },
+            { "bufferView": 2, "componentType": 5123, "count": 6, "type": "SCALAR" }
+        ]
+    }"#;
+
+    fn load(text: &str) -> (Document, Vec<Vec<u8>>) {
+        let mut document = Document::parse(text.as_bytes()).unwrap();
+        let buffers = document
+            .buffer_sources()
+            .unwrap()
+            .into_iter()
+            .map(|source| match source {
+                Source::Bytes(bytes) => bytes,
+                Source::File(path) => panic!("Unexpected file {}", path),
+            })
+            .collect();
+        (document, buffers)
+    }
+
+    #[test]
+    fn primitives_are_placed_by_their_nodes() {
+        let (document, buffers) = load(QUAD);
+        let scene = document.scene(&buffers).unwrap();
+
+        let names = scene
+            .nodes
+            .iter()
+            .map(|n| n.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["parent", "child"]);
+        assert_eq!(scene.nodes[1].parent, Some(0));
+        assert_eq!(scene.nodes[1].meshes, vec![0]);
+
+        let quad = &scene.primitives[0];
+        assert_eq!(quad.material, Some(0));
+        assert!(quad.has_tex_coords && !quad.has_normals);
+        let positions = quad.vertices.iter().map(|v| v.position).collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            [
+                [0.0, 0.0, 5.0],
+                [-1.0, 0.0, 5.0],
+                [-1.0, 1.0, 5.0],
+                [0.0, 1.0, 5.0]
+            ]
+        );
+        assert_eq!(quad.vertices[2].tex_coords, [1.0, 1.0]);
+        // Flipped by the mirroring scale
+        assert_eq!(quad.indices, [0, 2, 1, 0, 3, 2]);
+    }
+
+    #[test]
+    fn materials_and_images_are_read() {
+        let (document, buffers) = load(QUAD);
+        let materials = document.materials();
+        assert_eq!(materials[0].base_color_image, Some(0));
+        assert_eq!(materials[0].normal_image, None);
+        assert_eq!(materials[0].params.diffuse, [1.0, 0.0, 0.0]);
+        assert_eq!(materials[0].params.opacity, 0.5);
+        assert_eq!(materials[0].params.alpha_mode, model::AlphaMode::Blend);
+        assert_eq!(
+            document.image_source(0, &buffers).unwrap(),
+            Source::File("red brick.png".to_string())
+        );
+    }
+
+    #[test]
+    fn mistyped_accessors_are_errors() {
+        // Signed bytes are not allowed for texture coordinates
+        let quad = QUAD.replacen(r#""componentType": 5121"#, r#""componentType": 5120"#, 1);
+        let (document, buffers) = load(&quad);
+        assert!(document.scene(&buffers).is_err());
+    }
+
+    #[test]
+    fn glb_chunks_are_split() {
+        let json = br#"{"asset":{"version":"2.0"},"buffers":[{"byteLength":4}]}"#;
+        let glb = gltf::Glb {
+            header: gltf::binary::Header {
+                magic: *b"glTF",
+                version: 2,
+                length: 0,
+            },
+            json: json[..].into(),
+            bin: Some(vec![1, 2, 3, 4].into()),
+        }
+        .to_vec()
+        .unwrap();
+
+        let mut document = Document::parse(&glb).unwrap();
+        assert_eq!(
+            document.buffer_sources().unwrap(),
+            vec![Source::Bytes(vec![1, 2, 3, 4])]
+        );
+    }
+
+    #[test]
+    fn unsupported_documents_are_errors() {
+        assert!(Document::parse(br#"{"asset":{"version":"1.0"}}"#).is_err());
+        let draco = r#"{
+            "asset": { "version": "2.0" },
+            "extensionsRequired": ["KHR_draco_mesh_compression"]
+        }"#;
+        assert!(Document::parse(draco.as_bytes()).is_err());
+    }
+}
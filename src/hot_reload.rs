@@ -0,0 +1,131 @@
+//! Watching `res/` in the source tree during development, so that assets
+//! saved there are reloaded without restarting
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
+
+use anyhow::Context;
+use notify::Watcher;
+
+/// Reports the files changed in the source resources, after copying them
+/// over the ones `build.rs` copied next to the build, which the resources
+/// are read from
+pub struct AssetWatcher {
+    source_dir: PathBuf,
+    target_dir: PathBuf,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    // Stops watching when dropped
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl AssetWatcher {
+    pub fn new() -> anyhow::Result<Self> {
+        let source_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("res");
+        // Events come with canonical paths on some platforms
+        let source_dir = source_dir
+            .canonicalize()
+            .with_context(|| format!("Can't watch {}", source_dir.display()))?;
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(&source_dir, notify::RecursiveMode::Recursive)?;
+        log::info!("Watching {} for changes", source_dir.display());
+        Ok(Self {
+            source_dir,
+            target_dir: Path::new(env!("OUT_DIR")).join("res"),
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    /// Files changed since the last call, relative to the resources
+    pub fn changed_files(&mut self) -> Vec<String> {
+        let mut changed = BTreeSet::new();
+        for event in self.events.try_iter() {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    log::warn!("Failed to watch the resources: {}", e);
+                    continue;
+                }
+            };
+            if !matches!(
+                event.kind,
+                notify::EventKind::Create(_) | notify::EventKind::Modify(_)
+            ) {
+                continue;
+            }
+            for path in event.paths {
+                // Files renamed away or deleted right after the event
+                if !path.is_file() {
+                    continue;
+                }
+                if let Some(file_name) = asset_path(&self.source_dir, &path) {
+                    changed.insert(file_name);
+                }
+            }
+        }
+
+        changed
+            .into_iter()
+            .filter(|file_name| match self.copy(file_name) {
+                Ok(()) => true,
+                Err(e) => {
+                    log::warn!("Failed to copy {}: {:#}", file_name, e);
+                    false
+                }
+            })
+            .collect()
+    }
+
+    fn copy(&self, file_name: &str) -> anyhow::Result<()> {
+        let target = self.target_dir.join(file_name);
+        if let Some(directory) = target.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        std::fs::copy(self.source_dir.join(file_name), &target)
+            .with_context(|| format!("Can't write {}", target.display()))?;
+        Ok(())
+    }
+}
+
+/// Name of the resource at `path`, `None` for the files outside of
+/// `source_dir`, the hidden ones like editor swap files, and the cooked
+/// ones, which are only ever written by the cook tool
+fn asset_path(source_dir: &Path, path: &Path) -> Option<String> {
+    let components = path
+        .strip_prefix(source_dir)
+        .ok()?
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+    let hidden = components
+        .iter()
+        .any(|component| component.starts_with('.') || component.ends_with('~'));
+    if hidden || components.first() == Some(&crate::cooked::COOKED_DIR) {
+        return None;
+    }
+    Some(components.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_source_assets_are_reported() {
+        let res = Path::new("/project/res");
+        let asset = |path: &str| asset_path(res, Path::new(path));
+        assert_eq!(asset("/project/res/cube.obj").as_deref(), Some("cube.obj"));
+        assert_eq!(
+            asset("/project/res/textures/grass.png").as_deref(),
+            Some("textures/grass.png")
+        );
+        assert_eq!(asset("/project/res/cooked/cube.obj.mesh"), None);
+        assert_eq!(asset("/project/res/.cube.obj.swp"), None);
+        assert_eq!(asset("/project/res/cube.mtl~"), None);
+        assert_eq!(asset("/project/src/lib.rs"), None);
+    }
+}
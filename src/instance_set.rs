@@ -0,0 +1,324 @@
+use std::{marker::PhantomData, ops::Range};
+
+use crate::{model, Instance, InstanceRaw, INSTANCE_PAYLOAD_LOCATION};
+
+/// Refers to an instance of an `InstanceSet` for as long as it lives,
+/// whatever happens to the other instances. Ids of removed instances are
+/// handed out again with a new generation, so stale ones find nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InstanceId {
+    slot: usize,
+    generation: u32,
+}
+
+/// Where the instance of an id is, if it is still alive
+#[derive(Default)]
+struct Slot {
+    generation: u32,
+    index: Option<usize>,
+}
+
+/// Instances of a model that can be added, removed and moved at runtime.
+/// Instances stay tightly packed so they can be drawn with a single call,
+/// and those touched since the last upload are tracked.
+pub struct InstanceSet<P = ()> {
+    instances: Vec<Instance<P>>,
+    /// Id of the instance at every index
+    ids: Vec<InstanceId>,
+    /// Whether the instance at every index changed since the last upload
+    dirty: Vec<bool>,
+    slots: Vec<Slot>,
+    /// Slots of the removed instances, reused by the next ones
+    free_slots: Vec<usize>,
+}
+
+impl<P> Default for InstanceSet<P> {
+    fn default() -> Self {
+        Self {
+            instances: Vec::new(),
+            ids: Vec::new(),
+            dirty: Vec::new(),
+            slots: Vec::new(),
+            free_slots: Vec::new(),
+        }
+    }
+}
+
+impl<P> InstanceSet<P> {
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn add(&mut self, instance: Instance<P>) -> InstanceId {
+        let slot = self.free_slots.pop().unwrap_or_else(|| {
+            self.slots.push(Slot::default());
+            self.slots.len() - 1
+        });
+        let index = self.instances.len();
+        self.slots[slot].index = Some(index);
+        let id = InstanceId {
+            slot,
+            generation: self.slots[slot].generation,
+        };
+        self.instances.push(instance);
+        self.ids.push(id);
+        self.dirty.push(true);
+        id
+    }
+
+    /// Fills the hole with the last instance, so only that one moves
+    pub fn remove(&mut self, id: InstanceId) -> Option<Instance<P>> {
+        let index = self.index(id)?;
+        let slot = &mut self.slots[id.slot];
+        slot.index = None;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_slots.push(id.slot);
+
+        let instance = self.instances.swap_remove(index);
+        self.ids.swap_remove(index);
+        self.dirty.swap_remove(index);
+        if let Some(&moved) = self.ids.get(index) {
+            self.slots[moved.slot].index = Some(index);
+            self.dirty[index] = true;
+        }
+        Some(instance)
+    }
+
+    pub fn get(&self, id: InstanceId) -> Option<&Instance<P>> {
+        self.instances.get(self.index(id)?)
+    }
+
+    /// Marks the instance for upload, whether it is changed or not
+    pub fn get_mut(&mut self, id: InstanceId) -> Option<&mut Instance<P>> {
+        let index = self.index(id)?;
+        self.dirty[index] = true;
+        self.instances.get_mut(index)
+    }
+
+    fn index(&self, id: InstanceId) -> Option<usize> {
+        let slot = self.slots.get(id.slot)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        slot.index
+    }
+
+    /// Runs of instances changed since the last call
+    fn take_dirty(&mut self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for (index, dirty) in self.dirty.iter_mut().enumerate() {
+            if !std::mem::take(dirty) {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(range) if range.end == index => range.end += 1,
+                _ => ranges.push(index..index + 1),
+            }
+        }
+        ranges
+    }
+}
+
+/// GPU copy of an `InstanceSet`, bound as the per-instance vertex buffer.
+/// Payloads go to the next buffer, laid out by their `model::Vertex`
+/// implementation from `INSTANCE_PAYLOAD_LOCATION` on.
+pub struct InstanceBuffer<P = ()> {
+    label: String,
+    buffer: wgpu::Buffer,
+    /// Only for payloads that aren't zero-sized
+    payload_buffer: Option<wgpu::Buffer>,
+    /// Instances the buffer can hold
+    capacity: usize,
+    /// Instances uploaded by the last `prepare`
+    count: u32,
+    _payload: PhantomData<P>,
+}
+
+impl<P: model::Vertex + bytemuck::Pod> InstanceBuffer<P> {
+    const MIN_CAPACITY: usize = 16;
+
+    pub fn new(device: &wgpu::Device, label: &str) -> Self {
+        debug_assert!(P::desc()
+            .attributes
+            .iter()
+            .all(|attribute| attribute.shader_location >= INSTANCE_PAYLOAD_LOCATION));
+        // Buffer writes are made of whole words
+        assert!(
+            std::mem::size_of::<P>().is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT as usize),
+            "instance payloads must be a multiple of 4 bytes",
+        );
+        Self {
+            label: label.to_string(),
+            buffer: Self::create_buffer::<InstanceRaw>(device, label, Self::MIN_CAPACITY),
+            payload_buffer: Self::create_payload_buffer(device, label, Self::MIN_CAPACITY),
+            capacity: Self::MIN_CAPACITY,
+            count: 0,
+            _payload: PhantomData,
+        }
+    }
+
+    fn create_payload_buffer(
+        device: &wgpu::Device,
+        label: &str,
+        capacity: usize,
+    ) -> Option<wgpu::Buffer> {
+        (std::mem::size_of::<P>() > 0)
+            .then(|| Self::create_buffer::<P>(device, &format!("{label} Payloads"), capacity))
+    }
+
+    fn create_buffer<T>(device: &wgpu::Device, label: &str, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (capacity * std::mem::size_of::<T>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Uploads the instances changed since the last call. The buffer doubles
+    /// in size whenever it runs out of room, then every instance is uploaded.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        set: &mut InstanceSet<P>,
+    ) {
+        if set.len() > self.capacity {
+            self.capacity = set.len().next_power_of_two();
+            self.buffer = Self::create_buffer::<InstanceRaw>(device, &self.label, self.capacity);
+            self.payload_buffer = Self::create_payload_buffer(device, &self.label, self.capacity);
+            set.dirty.fill(true);
+        }
+        for dirty in set.take_dirty() {
+            let raw = set.instances[dirty.clone()]
+                .iter()
+                .map(Instance::to_raw)
+                .collect::<Vec<_>>();
+            let offset = dirty.start * std::mem::size_of::<InstanceRaw>();
+            queue.write_buffer(
+                &self.buffer,
+                offset as wgpu::BufferAddress,
+                bytemuck::cast_slice(&raw),
+            );
+            if let Some(payload_buffer) = &self.payload_buffer {
+                let payloads = set.instances[dirty.clone()]
+                    .iter()
+                    .map(|instance| instance.payload)
+                    .collect::<Vec<_>>();
+                let offset = dirty.start * std::mem::size_of::<P>();
+                queue.write_buffer(
+                    payload_buffer,
+                    offset as wgpu::BufferAddress,
+                    bytemuck::cast_slice(&payloads),
+                );
+            }
+        }
+        self.count = set.len() as u32;
+    }
+
+    /// Instances to draw, as of the last `prepare`
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Binds the instances to vertex buffer 1 and their payloads to 2
+    pub fn bind<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_vertex_buffer(1, self.buffer.slice(..));
+        if let Some(payload_buffer) = &self.payload_buffer {
+            render_pass.set_vertex_buffer(2, payload_buffer.slice(..));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{One, Quaternion, Vector3, Zero};
+
+    use super::*;
+
+    fn set(count: usize) -> (InstanceSet, Vec<InstanceId>) {
+        let mut set = InstanceSet::default();
+        let ids = (0..count)
+            .map(|i| {
+                set.add(Instance::new(
+                    Vector3::new(i as f32, 0.0, 0.0),
+                    Quaternion::one(),
+                ))
+            })
+            .collect();
+        (set, ids)
+    }
+
+    #[test]
+    fn ids_survive_removals() {
+        let (mut set, ids) = set(4);
+
+        assert_eq!(set.remove(ids[1]).unwrap().position.x, 1.0);
+        assert!(set.remove(ids[1]).is_none());
+        assert!(set.get(ids[1]).is_none());
+        assert_eq!(set.len(), 3);
+        // The last instance filled the hole
+        assert_eq!(set.get(ids[3]).unwrap().position.x, 3.0);
+        assert_eq!(set.get(ids[0]).unwrap().position.x, 0.0);
+    }
+
+    #[test]
+    fn removed_ids_are_reused_but_stale_ones_are_not() {
+        let (mut set, ids) = set(2);
+        set.remove(ids[0]);
+        let id = set.add(Instance::new(
+            Vector3::new(5.0, 0.0, 0.0),
+            Quaternion::one(),
+        ));
+
+        assert_eq!(set.slots.len(), 2);
+        assert_ne!(id, ids[0]);
+        assert!(set.get(ids[0]).is_none());
+        assert!(set.get_mut(ids[0]).is_none());
+        assert!(set.remove(ids[0]).is_none());
+        assert_eq!(set.get(id).unwrap().position.x, 5.0);
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn payloads_move_with_their_instances() {
+        let mut set = InstanceSet::<u32>::default();
+        let ids = (0..3)
+            .map(|i| {
+                set.add(Instance::with_payload(
+                    Vector3::zero(),
+                    Quaternion::one(),
+                    i,
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        set.remove(ids[0]);
+        assert_eq!(set.get(ids[2]).unwrap().payload, 2);
+        assert_eq!(set.instances[0].payload, 2);
+    }
+
+    #[test]
+    fn only_changed_instances_are_dirty() {
+        let (mut set, ids) = set(8);
+        assert_eq!(set.take_dirty(), [Range { start: 0, end: 8 }]);
+        assert_eq!(set.take_dirty(), []);
+
+        // Far apart changes don't upload what lies between them
+        set.get_mut(ids[0]).unwrap().position.y = 1.0;
+        set.get_mut(ids[2]).unwrap().position.y = 1.0;
+        set.get_mut(ids[3]).unwrap().position.y = 1.0;
+        set.get_mut(ids[7]).unwrap().position.y = 1.0;
+        assert_eq!(set.take_dirty(), [0..1, 2..4, 7..8]);
+
+        // Removing the last instance leaves nothing to upload
+        set.remove(ids[7]);
+        assert_eq!(set.take_dirty(), []);
+        set.remove(ids[0]);
+        assert_eq!(set.take_dirty(), [Range { start: 0, end: 1 }]);
+    }
+}
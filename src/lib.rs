@@ -15,13 +15,40 @@ use wasm_bindgen::prelude::*;
 
 use crate::render::{DefaultState, State};
 
+mod animation;
+mod assets;
+mod atmosphere;
 mod camera;
+mod capture;
+mod cluster;
+mod cooked;
+mod deferred;
+mod gltf;
+#[cfg(not(target_arch = "wasm32"))]
+mod hot_reload;
+mod instance_set;
+mod mesh_optimizer;
 mod model;
+mod morph;
+mod normals;
+mod particle;
 mod resources;
+mod sprite;
+mod tangents;
+mod terrain;
+mod text;
 mod texture;
+mod ui;
 
 mod render;
 
+pub use capture::CaptureConfig;
+pub use cooked::{cook_models, CookReport};
+pub use mesh_optimizer::OptimizeOptions;
+pub use model::VertexFormat;
+pub use resources::{LoadOptions, UvFallback};
+pub use render::RenderPath;
+
 const NUM_INSTANCES_PER_ROW: u32 = 10;
 
 #[repr(C)]
@@ -29,6 +56,8 @@ const NUM_INSTANCES_PER_ROW: u32 = 10;
 struct CameraUniform {
     view_position: [f32; 4],
     view_proj: [[f32; 4]; 4],
+    /// Only read by the pipelines drawing the world, overlays ignore it
+    fog: atmosphere::FogUniform,
 }
 
 impl CameraUniform {
@@ -36,6 +65,7 @@ impl CameraUniform {
         Self {
             view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
+            fog: bytemuck::Zeroable::zeroed(),
         }
     }
 
@@ -45,18 +75,58 @@ impl CameraUniform {
     }
 }
 
-struct Instance {
+/// Per-instance vertex locations from which a payload describes its
+/// attributes, the ones before belong to the vertices and to `InstanceRaw`
+const INSTANCE_PAYLOAD_LOCATION: u32 = 15;
+
+/// `P` is extra data for custom pipelines, uploaded by
+/// `instance_set::InstanceBuffer` to a vertex buffer of its own. The
+/// built-in pipelines draw instances without one.
+struct Instance<P = ()> {
     position: cgmath::Vector3<f32>,
     rotation: cgmath::Quaternion<f32>,
+    /// Applied before the rotation, no component may be 0
+    scale: cgmath::Vector3<f32>,
+    /// Multiplies the diffuse texture
+    color: [f32; 4],
+    payload: P,
 }
 
 impl Instance {
+    fn new(position: cgmath::Vector3<f32>, rotation: cgmath::Quaternion<f32>) -> Self {
+        Self::with_payload(position, rotation, ())
+    }
+}
+
+impl<P> Instance<P> {
+    fn with_payload(
+        position: cgmath::Vector3<f32>,
+        rotation: cgmath::Quaternion<f32>,
+        payload: P,
+    ) -> Self {
+        Self {
+            position,
+            rotation,
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+            color: [1.0; 4],
+            payload,
+        }
+    }
+
     fn to_raw(&self) -> InstanceRaw {
+        let rotation = cgmath::Matrix3::from(self.rotation);
+        let scale = cgmath::Matrix3::from_diagonal(self.scale);
+        // Normals take the inverse transpose, which keeps them perpendicular
+        // to the surface under non-uniform scaling
+        let normal = (rotation * scale)
+            .invert()
+            .map_or(rotation, |inverse| inverse.transpose());
         InstanceRaw {
             model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from(self.rotation))
+                * cgmath::Matrix4::from(rotation * scale))
             .into(),
-            normal: cgmath::Matrix3::from(self.rotation).into(),
+            normal: normal.into(),
+            color: self.color,
         }
     }
 }
@@ -67,6 +137,7 @@ impl Instance {
 struct InstanceRaw {
     model: [[f32; 4]; 4],
     normal: [[f32; 3]; 3],
+    color: [f32; 4],
 }
 
 impl model::Vertex for InstanceRaw {
@@ -111,6 +182,13 @@ impl model::Vertex for InstanceRaw {
                     shader_location: 11,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                // Locations 12 and 13 are taken by the joints and weights
+                // of skinned vertices
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
@@ -125,8 +203,24 @@ struct LightUniform {
     _padding2: u32,
 }
 
+/// Options picked before the window opens
+#[derive(Default)]
+pub struct Config {
+    pub capture: CaptureConfig,
+    pub render_path: RenderPath,
+    /// Processing and upload of the meshes of loaded models
+    pub model_loading: LoadOptions,
+    /// Reloads the assets saved in `res/` while running, for development
+    pub hot_reload: bool,
+}
+
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn run() {
+    run_with_config(Config::default()).await
+}
+
+pub async fn run_with_config(config: Config) {
+    let capture_config = config.capture;
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             std::panic::set_hook(Box::new(console_error_panic_hook::hook));
@@ -167,9 +261,17 @@ pub async fn run() {
     }
 
     let mut renderer = Arc::from(GraphicsRenderer::initialize(&window).await);
-    let mut default_state = Arc::from(DefaultState::new(renderer.deref()).await);
+    let mut default_state = Arc::from(DefaultState::new(
+        renderer.deref(),
+        config.render_path,
+        config.model_loading,
+        config.hot_reload,
+    ).await);
+    let mut ui = ui::Ui::new(&event_loop, &window, &renderer.device, &renderer.config);
 
     let mut last_render_time = instant::Instant::now();
+    let mut screenshot_requested = false;
+    let mut recorder: Option<capture::Recorder> = None;
     event_loop.run(move |base_event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
         let renderer = Arc::get_mut(&mut renderer).unwrap();
@@ -177,6 +279,12 @@ pub async fn run() {
 
         match base_event {
             Event::MainEventsCleared => window.request_redraw(),
+            // The UI gets the first look at window events so that clicks on a
+            // panel don't also move the camera
+            Event::WindowEvent {
+                ref event,
+                window_id,
+            } if window_id == window.id() && ui.on_event(event) => {}
             Event::WindowEvent {
                 ref event,
                 window_id,
@@ -192,11 +300,51 @@ pub async fn run() {
                         },
                     ..
                 } => *control_flow = ControlFlow::Exit,
+                #[cfg(not(target_arch = "wasm32"))]
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                    ..
+                } if *key == capture_config.screenshot_key => {
+                    screenshot_requested = true;
+                    renderer.request_capture();
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                    ..
+                } if *key == capture_config.record_key => match recorder.take() {
+                    Some(recording) => {
+                        log::info!(
+                            "Recorded {} frames to {}",
+                            recording.frame_count(),
+                            recording.directory().display()
+                        );
+                        if let Err(e) = recording.finish() {
+                            log::error!("Failed to finish recording: {}", e);
+                        }
+                    }
+                    None => match capture::Recorder::start(&capture_config) {
+                        Ok(recording) => recorder = Some(recording),
+                        Err(e) => log::error!("Failed to start recording: {}", e),
+                    },
+                },
                 WindowEvent::Resized(physical_size) => {
                     renderer.resize(*physical_size);
+                    state.resize(&renderer.device, &renderer.config, renderer.size);
                 }
                 WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
                     renderer.resize(**new_inner_size);
+                    state.resize(&renderer.device, &renderer.config, renderer.size);
                 }
                 _ => {
                     state.input(&base_event);
@@ -204,17 +352,28 @@ pub async fn run() {
             },
             Event::RedrawRequested(window_id) if window_id == window.id() => {
                 let now = instant::Instant::now();
-                let dt = now - last_render_time;
+                let dt = match (&recorder, capture_config.record_fixed_dt) {
+                    (Some(_), Some(fixed_dt)) => fixed_dt,
+                    _ => now - last_render_time,
+                };
                 last_render_time = now;
 
+                if recorder.is_some() {
+                    renderer.request_capture();
+                }
+
                 #[cfg(not(target_arch = "wasm32"))]
                 tracy_client::Client::running().unwrap().span(tracy_client::span_location!("update"), 0);
-                state.update(&renderer.queue, dt);
+                state.update(&renderer.device, &renderer.queue, dt);
+                ui.run(&window, |ctx| state.ui(ctx));
+                ui.prepare(&renderer.device, &renderer.queue, &renderer.config);
 
                 #[cfg(not(target_arch = "wasm32"))]
                 tracy_client::Client::running().unwrap().span(tracy_client::span_location!("render"), 0);
                 match renderer.render_frame(|view, command| {
-                    default_state.render(view, command)
+                    default_state.render(view, command)?;
+                    ui.render(view, command);
+                    Ok(())
                 }) {
                     Ok(_) => {}
                     Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
@@ -223,6 +382,23 @@ pub async fn run() {
                     Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
                     Err(wgpu::SurfaceError::Timeout) => log::warn!("Surface timeout"),
                 }
+
+                if let Some(frame) = renderer.take_captured_frame() {
+                    if screenshot_requested {
+                        screenshot_requested = false;
+                        let path = capture_config.screenshot_path();
+                        match capture::save_png(&frame, &path) {
+                            Ok(_) => log::info!("Screenshot saved to {}", path.display()),
+                            Err(e) => log::error!("Failed to save screenshot: {}", e),
+                        }
+                    }
+                    if let Some(recording) = recorder.as_mut() {
+                        if let Err(e) = recording.write_frame(&frame) {
+                            log::error!("Recording stopped: {}", e);
+                            recorder = None;
+                        }
+                    }
+                }
                 #[cfg(not(target_arch = "wasm32"))]
                 tracy_client::Client::running().unwrap().frame_mark();
             }
@@ -232,3 +408,45 @@ pub async fn run() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        let instance = Instance {
+            scale: cgmath::Vector3::new(4.0, 1.0, 1.0),
+            ..Instance::new(
+                cgmath::Vector3::zero(),
+                cgmath::Quaternion::from_angle_z(cgmath::Deg(30.0)),
+            )
+        };
+        let raw = instance.to_raw();
+        let model = cgmath::Matrix3::from_cols(
+            cgmath::Vector4::from(raw.model[0]).truncate(),
+            cgmath::Vector4::from(raw.model[1]).truncate(),
+            cgmath::Vector4::from(raw.model[2]).truncate(),
+        );
+        let normal_matrix = cgmath::Matrix3::from(raw.normal);
+
+        // A slanted face stretched along x keeps a normal at right angles
+        let tangent = cgmath::Vector3::new(1.0, 1.0, 0.0);
+        let normal = cgmath::Vector3::new(1.0, -1.0, 0.0);
+        assert!((model * tangent).dot(normal_matrix * normal).abs() < 1e-5);
+    }
+
+    #[test]
+    fn payloads_have_their_locations_to_themselves() {
+        use model::Vertex;
+
+        assert!(InstanceRaw::desc()
+            .attributes
+            .iter()
+            .all(|attribute| attribute.shader_location < INSTANCE_PAYLOAD_LOCATION));
+        assert!(model::ModelVertex::desc()
+            .attributes
+            .iter()
+            .all(|attribute| attribute.shader_location < INSTANCE_PAYLOAD_LOCATION));
+    }
+}
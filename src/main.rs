@@ -1,5 +1,18 @@
-use tuto1::run;
+use tuto1::{run_with_config, Config, OptimizeOptions, RenderPath, VertexFormat};
 
 fn main() {
-    async_std::task::block_on(run());
+    let mut config = Config::default();
+    if std::env::args().any(|arg| arg == "--deferred") {
+        config.render_path = RenderPath::Deferred;
+    }
+    if std::env::args().any(|arg| arg == "--compact-vertices") {
+        config.model_loading.vertex_format = VertexFormat::Compact;
+    }
+    if std::env::args().any(|arg| arg == "--optimize-meshes") {
+        config.model_loading.optimize = Some(OptimizeOptions::default());
+    }
+    if std::env::args().any(|arg| arg == "--hot-reload") {
+        config.hot_reload = true;
+    }
+    async_std::task::block_on(run_with_config(config));
 }
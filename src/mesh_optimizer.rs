@@ -0,0 +1,308 @@
+//! Index and vertex reordering run on meshes as they are loaded, so that the
+//! GPU transforms and fetches as few vertices as possible
+
+use std::{collections::HashMap, fmt};
+
+/// Steps run on the meshes of a loaded model
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptimizeOptions {
+    /// Merges vertices whose attributes are bitwise identical
+    pub weld: bool,
+    /// Reorders triangles for the post-transform cache, then vertices in the
+    /// order they are first used
+    pub reorder: bool,
+    /// Uses 16 bit indices when every vertex can be addressed by them
+    pub small_indices: bool,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        Self {
+            weld: true,
+            reorder: true,
+            small_indices: true,
+        }
+    }
+}
+
+/// Measures of a mesh to compare before and after optimizing it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshStats {
+    pub vertices: usize,
+    pub triangles: usize,
+    /// Average cache miss ratio, vertices transformed per triangle through
+    /// a FIFO cache of `STATS_CACHE_SIZE` entries. 0.5 is the best possible
+    /// on large regular grids, 3 the worst.
+    pub acmr: f32,
+    pub index_bytes: usize,
+}
+
+impl MeshStats {
+    /// Size of the post-transform cache simulated to compute the ACMR
+    pub const STATS_CACHE_SIZE: usize = 16;
+
+    pub fn new(vertex_count: usize, indices: &[u32], index_format: wgpu::IndexFormat) -> Self {
+        let mut cache = std::collections::VecDeque::with_capacity(Self::STATS_CACHE_SIZE);
+        let mut misses = 0;
+        for &index in indices {
+            if !cache.contains(&index) {
+                misses += 1;
+                if cache.len() == Self::STATS_CACHE_SIZE {
+                    cache.pop_front();
+                }
+                cache.push_back(index);
+            }
+        }
+        let triangles = indices.len() / 3;
+        let index_size = match index_format {
+            wgpu::IndexFormat::Uint16 => 2,
+            wgpu::IndexFormat::Uint32 => 4,
+        };
+        Self {
+            vertices: vertex_count,
+            triangles,
+            acmr: misses as f32 / triangles.max(1) as f32,
+            index_bytes: indices.len() * index_size,
+        }
+    }
+}
+
+impl fmt::Display for MeshStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} vertices, {} triangles, ACMR {:.3}, {} index bytes",
+            self.vertices, self.triangles, self.acmr, self.index_bytes
+        )
+    }
+}
+
+/// Merges the vertices that are bitwise identical, keeping the first one,
+/// and points the indices at the merged vertices
+pub fn weld<V: bytemuck::Pod>(vertices: &[V], indices: &mut [u32]) -> Vec<V> {
+    let mut unique = Vec::with_capacity(vertices.len());
+    let mut seen = HashMap::with_capacity(vertices.len());
+    let remap = vertices
+        .iter()
+        .map(|vertex| {
+            *seen
+                .entry(bytemuck::bytes_of(vertex).to_vec())
+                .or_insert_with(|| {
+                    unique.push(*vertex);
+                    unique.len() as u32 - 1
+                })
+        })
+        .collect::<Vec<_>>();
+    for index in indices {
+        *index = remap[*index as usize];
+    }
+    unique
+}
+
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+/// Score of a vertex in Forsyth's algorithm: vertices of the last triangle
+/// and recently used ones score high, as do vertices with few triangles left,
+/// so that lone triangles don't get stranded.
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: usize) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        None => 0.0,
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            let scale = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+    };
+    cache_score + VALENCE_BOOST_SCALE * (remaining_triangles as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+/// Reorders the triangles so that consecutive triangles share vertices, using
+/// Tom Forsyth's linear-speed vertex cache optimisation. Works well whatever
+/// the actual cache size of the GPU.
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+    let mut vertex_triangles = vec![Vec::new(); vertex_count];
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &vertex in corners {
+            vertex_triangles[vertex as usize].push(triangle);
+        }
+    }
+    let mut scores = vertex_triangles
+        .iter()
+        .map(|triangles| vertex_score(None, triangles.len()))
+        .collect::<Vec<_>>();
+    let triangle_score = |scores: &[f32], triangle: usize| -> f32 {
+        indices[triangle * 3..triangle * 3 + 3]
+            .iter()
+            .map(|&vertex| scores[vertex as usize])
+            .sum()
+    };
+
+    let mut emitted = vec![false; triangle_count];
+    let mut result = Vec::with_capacity(triangle_count * 3);
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut best = (0..triangle_count)
+        .max_by(|&a, &b| triangle_score(&scores, a).total_cmp(&triangle_score(&scores, b)));
+    // Scan position for when the cache holds no triangle left to draw
+    let mut next_unemitted = 0;
+    while let Some(triangle) = best.or_else(|| {
+        while next_unemitted < triangle_count && emitted[next_unemitted] {
+            next_unemitted += 1;
+        }
+        (next_unemitted < triangle_count).then_some(next_unemitted)
+    }) {
+        emitted[triangle] = true;
+        let corners = &indices[triangle * 3..triangle * 3 + 3];
+        result.extend_from_slice(corners);
+
+        let mut new_cache = Vec::with_capacity(CACHE_SIZE + 3);
+        for &vertex in corners {
+            vertex_triangles[vertex as usize].retain(|&t| t != triangle);
+            if !new_cache.contains(&vertex) {
+                new_cache.push(vertex);
+            }
+        }
+        new_cache.extend(cache.iter().filter(|vertex| !corners.contains(vertex)));
+        for (position, &vertex) in new_cache.iter().enumerate() {
+            let position = (position < CACHE_SIZE).then_some(position);
+            scores[vertex as usize] =
+                vertex_score(position, vertex_triangles[vertex as usize].len());
+        }
+
+        // Only the triangles of the vertices just touched changed score
+        best = None;
+        let mut best_score = f32::MIN;
+        for &vertex in &new_cache {
+            for &candidate in &vertex_triangles[vertex as usize] {
+                let score = triangle_score(&scores, candidate);
+                if score > best_score {
+                    best_score = score;
+                    best = Some(candidate);
+                }
+            }
+        }
+        new_cache.truncate(CACHE_SIZE);
+        cache = new_cache;
+    }
+    result
+}
+
+/// Orders the vertices as the indices first use them, dropping the unused
+/// ones, so that vertex fetches walk through memory
+pub fn optimize_vertex_fetch<V: Copy>(vertices: &[V], indices: &mut [u32]) -> Vec<V> {
+    let mut remap = vec![None; vertices.len()];
+    let mut result = Vec::with_capacity(vertices.len());
+    for index in indices {
+        let new_index = remap[*index as usize].get_or_insert_with(|| {
+            result.push(vertices[*index as usize]);
+            result.len() as u32 - 1
+        });
+        *index = *new_index;
+    }
+    result
+}
+
+/// Index buffer contents, in 16 bits when allowed and every vertex fits.
+/// 0xFFFF is left out, WebGL always reading it as a strip restart.
+pub fn pack_indices(
+    indices: &[u32],
+    vertex_count: usize,
+    small_indices: bool,
+) -> (Vec<u8>, wgpu::IndexFormat) {
+    if small_indices && vertex_count <= u16::MAX as usize {
+        let small = indices.iter().map(|&i| i as u16).collect::<Vec<_>>();
+        (
+            bytemuck::cast_slice(&small).to_vec(),
+            wgpu::IndexFormat::Uint16,
+        )
+    } else {
+        (
+            bytemuck::cast_slice(indices).to_vec(),
+            wgpu::IndexFormat::Uint32,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Indices of a `size` by `size` quad grid, with its rows interleaved
+    /// like a mesh exported in no particular order
+    fn scrambled_grid(size: u32) -> (usize, Vec<u32>) {
+        let row = size + 1;
+        let mut quads = (0..size * size).collect::<Vec<_>>();
+        // Deterministic shuffle
+        for i in (1..quads.len()).rev() {
+            quads.swap(i, (i * 7919) % (i + 1));
+        }
+        let indices = quads
+            .into_iter()
+            .flat_map(|quad| {
+                let a = (quad / size) * row + quad % size;
+                [a, a + 1, a + row, a + 1, a + row + 1, a + row]
+            })
+            .collect();
+        ((row * row) as usize, indices)
+    }
+
+    #[test]
+    fn welding_merges_identical_vertices() {
+        let vertices = [[0.0f32, 1.0], [2.0, 3.0], [0.0, 1.0], [2.0, 3.0]];
+        let mut indices = vec![0, 1, 2, 3, 2, 1];
+        let welded = weld(&vertices, &mut indices);
+        assert_eq!(welded, vec![[0.0, 1.0], [2.0, 3.0]]);
+        assert_eq!(indices, vec![0, 1, 0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn cache_optimisation_keeps_triangles_and_lowers_acmr() {
+        let (vertex_count, indices) = scrambled_grid(32);
+        let optimized = optimize_vertex_cache(&indices, vertex_count);
+
+        let triangles = |indices: &[u32]| {
+            let mut triangles = indices.chunks(3).map(|t| t.to_vec()).collect::<Vec<_>>();
+            triangles.sort();
+            triangles
+        };
+        assert_eq!(triangles(&optimized), triangles(&indices));
+
+        let before = MeshStats::new(vertex_count, &indices, wgpu::IndexFormat::Uint32);
+        let after = MeshStats::new(vertex_count, &optimized, wgpu::IndexFormat::Uint32);
+        assert!(after.acmr < 1.0, "{}", after);
+        assert!(after.acmr < before.acmr * 0.6, "{} -> {}", before, after);
+    }
+
+    #[test]
+    fn vertices_are_fetched_in_order() {
+        let vertices = ['a', 'b', 'c', 'd', 'e'];
+        let mut indices = vec![3, 1, 0, 3, 2, 1];
+        let reordered = optimize_vertex_fetch(&vertices, &mut indices);
+        assert_eq!(reordered, vec!['d', 'b', 'a', 'c']);
+        assert_eq!(indices, vec![0, 1, 2, 0, 3, 1]);
+    }
+
+    #[test]
+    fn small_meshes_use_16_bit_indices() {
+        let indices = [0, 1, 65534];
+        let (bytes, format) = pack_indices(&indices, 65535, true);
+        assert_eq!(format, wgpu::IndexFormat::Uint16);
+        assert_eq!(bytes, vec![0, 0, 1, 0, 254, 255]);
+        // Index 0xFFFF would be a primitive restart
+        assert_eq!(
+            pack_indices(&[0, 1, 65535], 65536, true).1,
+            wgpu::IndexFormat::Uint32
+        );
+        assert_eq!(
+            pack_indices(&indices, 3, false).1,
+            wgpu::IndexFormat::Uint32
+        );
+    }
+}
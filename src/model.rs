@@ -1,11 +1,25 @@
 use std::ops::Range;
 
-use crate::texture;
+use cgmath::{InnerSpace, Matrix4, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::{animation, assets::Handle, morph, texture};
 
 pub trait Vertex {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a>;
 }
 
+/// The payload of instances that have none, no buffer is bound for it
+impl Vertex for () {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: 0,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[],
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ModelVertex {
@@ -54,10 +68,340 @@ impl Vertex for ModelVertex {
     }
 }
 
+/// Vertex layout the meshes of a model are uploaded with, picked when the
+/// model is loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VertexFormat {
+    /// `ModelVertex`, full precision floats
+    #[default]
+    Full,
+    /// `CompactVertex`, half the size with quantised attributes
+    Compact,
+}
+
+impl VertexFormat {
+    pub fn desc<'a>(self) -> wgpu::VertexBufferLayout<'a> {
+        match self {
+            VertexFormat::Full => ModelVertex::desc(),
+            VertexFormat::Compact => CompactVertex::desc(),
+        }
+    }
+
+    /// Size of a vertex in this format
+    pub fn size(self) -> usize {
+        match self {
+            VertexFormat::Full => std::mem::size_of::<ModelVertex>(),
+            VertexFormat::Compact => std::mem::size_of::<CompactVertex>(),
+        }
+    }
+
+    /// Vertex buffer contents in this format
+    pub fn pack(self, vertices: &[ModelVertex]) -> Vec<u8> {
+        match self {
+            VertexFormat::Full => bytemuck::cast_slice(vertices).to_vec(),
+            VertexFormat::Compact => {
+                let compact = vertices.iter().map(CompactVertex::from).collect::<Vec<_>>();
+                bytemuck::cast_slice(&compact).to_vec()
+            }
+        }
+    }
+}
+
+/// `ModelVertex` in 28 bytes instead of 56. The bitangent is rebuilt in the
+/// shader from the normal, the tangent and the handedness of the frame.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CompactVertex {
+    pub position: [f32; 3],
+    /// Half floats
+    pub tex_coords: [u16; 2],
+    /// Snorm16, w holds the handedness as -1 or 1
+    pub normal: [i16; 4],
+    /// Octahedral encoding in Snorm16
+    pub tangent: [i16; 2],
+}
+
+impl From<&ModelVertex> for CompactVertex {
+    fn from(vertex: &ModelVertex) -> Self {
+        let normal = Vector3::from(vertex.normal);
+        let tangent = Vector3::from(vertex.tangent);
+        let handedness = if normal.cross(tangent).dot(vertex.bitangent.into()) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        let normal = if normal.magnitude2() > 0.0 {
+            normal.normalize()
+        } else {
+            normal
+        };
+        let [u, v] = vertex.tex_coords;
+        let [x, y] = encode_octahedral(tangent);
+        Self {
+            position: vertex.position,
+            tex_coords: [f32_to_f16(u), f32_to_f16(v)],
+            normal: [
+                to_snorm16(normal.x),
+                to_snorm16(normal.y),
+                to_snorm16(normal.z),
+                to_snorm16(handedness),
+            ],
+            tangent: [to_snorm16(x), to_snorm16(y)],
+        }
+    }
+}
+
+impl Vertex for CompactVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<CompactVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float16x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Snorm16x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Snorm16x2,
+                },
+            ],
+        }
+    }
+}
+
+fn to_snorm16(value: f32) -> i16 {
+    (value.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
+
+/// Rounds to the nearest half float, out of range values become infinite
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal, or too small and flushed to zero
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        return sign | ((mantissa + (1 << (shift - 1))) >> shift) as u16;
+    }
+    // A rounding carry correctly moves on to the exponent
+    let half = ((exponent as u32) << 10) | (mantissa >> 13);
+    sign | (half + ((mantissa >> 12) & 1)) as u16
+}
+
+/// Maps a direction onto the octahedron unfolded into [-1, 1]²
+fn encode_octahedral(v: Vector3<f32>) -> [f32; 2] {
+    let length = v.x.abs() + v.y.abs() + v.z.abs();
+    if length == 0.0 {
+        return [0.0, 0.0];
+    }
+    let v = v / length;
+    if v.z >= 0.0 {
+        [v.x, v.y]
+    } else {
+        let sign = |value: f32| if value >= 0.0 { 1.0 } else { -1.0 };
+        [(1.0 - v.y.abs()) * sign(v.x), (1.0 - v.x.abs()) * sign(v.y)]
+    }
+}
+
+/// Vertex of a mesh deformed by a skeleton, bound to up to four joints
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinnedVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 3],
+    pub bitangent: [f32; 3],
+    /// Indices into the skeleton joints
+    pub joints: [u16; 4],
+    /// Influence of each joint, summing to 1
+    pub weights: [f32; 4],
+}
+
+impl Vertex for SkinnedVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<SkinnedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            // Same locations as `ModelVertex`, joints and weights come after
+            // the instance attributes
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 11]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 14]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Uint16x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// How the opacity of a material is used
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AlphaMode {
+    /// Opacity is ignored
+    #[default]
+    Opaque,
+    /// Fragments less opaque than the cutoff are discarded
+    Mask(f32),
+    /// Blended over what is behind, after every opaque mesh is drawn
+    Blend,
+}
+
+/// Colours and scalars of a material, named after their MTL statements
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialParams {
+    /// `Ka`, scales the ambient light
+    pub ambient: [f32; 3],
+    /// `Kd`, multiplies the diffuse texture
+    pub diffuse: [f32; 3],
+    /// `Ks`
+    pub specular: [f32; 3],
+    /// `Ns`, the exponent of the specular highlights
+    pub shininess: f32,
+    /// `d`, multiplies the alpha of the diffuse texture
+    pub opacity: f32,
+    /// `illum`: 0 for the colour alone, 1 without specular highlights and
+    /// 2 or more with them
+    pub illumination: u32,
+    pub alpha_mode: AlphaMode,
+}
+
+impl Default for MaterialParams {
+    /// White and shiny, shading like materials did before they had
+    /// parameters
+    fn default() -> Self {
+        Self {
+            ambient: [1.0; 3],
+            diffuse: [1.0; 3],
+            specular: [1.0; 3],
+            shininess: 32.0,
+            opacity: 1.0,
+            illumination: 2,
+            alpha_mode: AlphaMode::Opaque,
+        }
+    }
+}
+
+impl MaterialParams {
+    /// Closest Blinn-Phong parameters to a glTF metallic-roughness material.
+    /// Metals tint their highlights and rough surfaces spread them.
+    pub fn from_metallic_roughness(
+        base_color: [f32; 4],
+        metallic: f32,
+        roughness: f32,
+        alpha_mode: AlphaMode,
+    ) -> Self {
+        let [r, g, b, a] = base_color;
+        let metallic = metallic.clamp(0.0, 1.0);
+        // Dielectrics reflect about 4% of the light whatever their colour
+        let specular = [r, g, b].map(|c| 0.04 + (c - 0.04) * metallic);
+        // Matching the width of the GGX lobe, with alpha = roughness²
+        let alpha = roughness.clamp(0.0, 1.0).powi(2).max(0.01);
+        Self {
+            ambient: [1.0; 3],
+            diffuse: [r, g, b],
+            specular,
+            shininess: (2.0 / (alpha * alpha) - 2.0).clamp(1.0, 256.0),
+            opacity: a,
+            illumination: 2,
+            alpha_mode,
+        }
+    }
+
+    fn to_uniform(self) -> MaterialUniform {
+        let [ar, ag, ab] = self.ambient;
+        let [dr, dg, db] = self.diffuse;
+        let [sr, sg, sb] = self.specular;
+        MaterialUniform {
+            ambient: [ar, ag, ab, 0.0],
+            diffuse: [dr, dg, db, self.opacity],
+            // A null exponent would light the whole surface
+            specular: [sr, sg, sb, self.shininess.max(1.0)],
+            illumination: self.illumination,
+            alpha_cutoff: match self.alpha_mode {
+                AlphaMode::Mask(cutoff) => cutoff,
+                AlphaMode::Opaque | AlphaMode::Blend => 0.0,
+            },
+            _padding: [0; 2],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    ambient: [f32; 4],
+    diffuse: [f32; 4],
+    specular: [f32; 4],
+    illumination: u32,
+    alpha_cutoff: f32,
+    _padding: [u32; 2],
+}
+
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: texture::Texture,
-    pub normal_texture: texture::Texture,
+    pub diffuse_texture: Handle<texture::Texture>,
+    pub normal_texture: Handle<texture::Texture>,
+    pub params: MaterialParams,
     pub bind_group: wgpu::BindGroup,
 }
 
@@ -65,10 +409,16 @@ impl Material {
     pub fn new(
         device: &wgpu::Device,
         name: &str,
-        diffuse_texture: texture::Texture,
-        normal_texture: texture::Texture,
+        diffuse_texture: Handle<texture::Texture>,
+        normal_texture: Handle<texture::Texture>,
+        params: MaterialParams,
         layout: &wgpu::BindGroupLayout,
     ) -> Self {
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Params Buffer", name)),
+            contents: bytemuck::cast_slice(&[params.to_uniform()]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
             entries: &[
@@ -88,6 +438,10 @@ impl Material {
                     binding: 3,
                     resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: params_buffer.as_entire_binding(),
+                },
             ],
             label: Some(name),
         });
@@ -96,25 +450,56 @@ impl Material {
             name: String::from(name),
             diffuse_texture,
             normal_texture,
+            params,
             bind_group,
         }
     }
 }
 
 pub struct Mesh {
+    #[allow(dead_code)]
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
+    /// `Uint16` for meshes small enough once optimized
+    pub index_format: wgpu::IndexFormat,
     pub num_elements: u32,
     pub material: usize,
+    /// Only set for meshes drawn with the morph pipeline
+    pub morph_targets: Option<morph::MorphTargets>,
+}
+
+/// Node of the scene a model was exported from. The meshes are already
+/// placed by the transforms, which are kept for reference.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub name: String,
+    /// Always comes before the node in `Model::nodes`
+    pub parent: Option<usize>,
+    /// Relative to the parent
+    pub transform: Matrix4<f32>,
+    /// Indices into `Model::meshes`
+    pub meshes: Vec<usize>,
 }
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
-    pub materials: Vec<Material>,
+    /// Shared with the other models using the same materials
+    pub materials: Vec<Handle<Material>>,
+    /// Only set for skinned models, whose meshes hold `SkinnedVertex`
+    pub skeleton: Option<animation::Skeleton>,
+    pub animations: Vec<animation::AnimationClip>,
+    /// Layout of the mesh vertices. Skinned and morphed meshes only come in
+    /// `Full`, their pipelines read `ModelVertex`.
+    pub vertex_format: VertexFormat,
+    /// Empty for formats without a hierarchy, like OBJ
+    #[allow(dead_code)]
+    pub nodes: Vec<Node>,
 }
 
 pub trait DrawModel<'a> {
+    #[allow(dead_code)]
     fn draw_mesh(
         &mut self,
         mesh: &'a Mesh,
@@ -131,6 +516,7 @@ pub trait DrawModel<'a> {
         light_bind_group: &'a wgpu::BindGroup,
     );
 
+    #[allow(dead_code)]
     fn draw_model(
         &mut self,
         model: &'a Model,
@@ -144,6 +530,16 @@ pub trait DrawModel<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
+    /// Only draws the meshes with a blended material when `blended` is set,
+    /// only the other meshes otherwise
+    fn draw_model_instanced_blended(
+        &mut self,
+        model: &'a Model,
+        blended: bool,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
     fn draw_model_instanced_with_material(
         &mut self,
         model: &'a Model,
@@ -177,7 +573,7 @@ where
         light_bind_group: &'b wgpu::BindGroup,
     ) {
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
         self.set_bind_group(0, &material.bind_group, &[]);
         self.set_bind_group(1, camera_bind_group, &[]);
         self.set_bind_group(2, light_bind_group, &[]);
@@ -212,6 +608,28 @@ where
         }
     }
 
+    fn draw_model_instanced_blended(
+        &mut self,
+        model: &'b Model,
+        blended: bool,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for mesh in &model.meshes {
+            let material = &model.materials[mesh.material];
+            if (material.params.alpha_mode == AlphaMode::Blend) == blended {
+                self.draw_mesh_instanced(
+                    mesh,
+                    material,
+                    instances.clone(),
+                    camera_bind_group,
+                    light_bind_group,
+                );
+            }
+        }
+    }
+
     fn draw_model_instanced_with_material(
         &mut self,
         model: &'b Model,
@@ -233,6 +651,7 @@ where
 }
 
 pub trait DrawLight<'a> {
+    #[allow(dead_code)]
     fn draw_light_mesh(
         &mut self,
         mesh: &'a Mesh,
@@ -283,7 +702,7 @@ where
         light_bind_group: &'b wgpu::BindGroup,
     ) {
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
         self.set_bind_group(0, camera_bind_group, &[]);
         self.set_bind_group(1, light_bind_group, &[]);
         self.draw_indexed(0..mesh.num_elements, 0, instances);
@@ -314,3 +733,47 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Same decoding as the compact vertex shaders
+    fn decode_octahedral([x, y]: [f32; 2]) -> Vector3<f32> {
+        let mut v = Vector3::new(x, y, 1.0 - x.abs() - y.abs());
+        let fold = (-v.z).max(0.0);
+        v.x += if v.x >= 0.0 { -fold } else { fold };
+        v.y += if v.y >= 0.0 { -fold } else { fold };
+        v.normalize()
+    }
+
+    #[test]
+    fn half_floats_round_to_nearest() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(0.333_333), 0x3555);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        // Smallest subnormal
+        assert_eq!(f32_to_f16(5.96e-8), 0x0001);
+    }
+
+    #[test]
+    fn octahedral_directions_survive_quantisation() {
+        for v in [
+            Vector3::new(0.0, 0.0, 1.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::new(0.3, -0.8, 0.52),
+            Vector3::new(-0.6, 0.2, -0.77),
+        ] {
+            let v = v.normalize();
+            let encoded = encode_octahedral(v).map(|x| to_snorm16(x) as f32 / i16::MAX as f32);
+            assert!(
+                (decode_octahedral(encoded) - v).magnitude() < 1e-3,
+                "{:?}",
+                v
+            );
+        }
+    }
+}
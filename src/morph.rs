@@ -0,0 +1,216 @@
+use anyhow::bail;
+use cgmath::Vector3;
+use wgpu::util::DeviceExt;
+
+use crate::{cluster, render};
+
+/// Offsets of every vertex of a mesh, blended in by the weight of the target.
+/// Normals and tangents may be left empty when the target only moves
+/// vertices, e.g. for small corrective shapes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MorphTarget {
+    pub name: String,
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+    pub tangents: Vec<Vector3<f32>>,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct MorphDelta {
+    position: [f32; 4],
+    normal: [f32; 4],
+    tangent: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MorphInfo {
+    target_count: u32,
+    vertex_count: u32,
+    _padding: [u32; 2],
+}
+
+/// Deltas of every target, target after target, as read by the shader
+fn pack_deltas(vertex_count: usize, targets: &[MorphTarget]) -> anyhow::Result<Vec<MorphDelta>> {
+    let mut deltas = Vec::with_capacity(vertex_count * targets.len());
+    for target in targets {
+        for (attribute, values) in [
+            ("positions", &target.positions),
+            ("normals", &target.normals),
+            ("tangents", &target.tangents),
+        ] {
+            if !values.is_empty() && values.len() != vertex_count {
+                bail!(
+                    "Morph target {} has {} {} for {} vertices",
+                    target.name,
+                    values.len(),
+                    attribute,
+                    vertex_count
+                );
+            }
+        }
+        let delta = |values: &[Vector3<f32>], i: usize| {
+            values
+                .get(i)
+                .map_or([0.0; 4], |value| [value.x, value.y, value.z, 0.0])
+        };
+        deltas.extend((0..vertex_count).map(|i| MorphDelta {
+            position: delta(&target.positions, i),
+            normal: delta(&target.normals, i),
+            tangent: delta(&target.tangents, i),
+        }));
+    }
+    Ok(deltas)
+}
+
+/// Morph targets of a mesh, uploaded once
+pub struct MorphTargets {
+    names: Vec<String>,
+    info_buffer: wgpu::Buffer,
+    delta_buffer: wgpu::Buffer,
+}
+
+impl MorphTargets {
+    pub fn new(
+        device: &wgpu::Device,
+        vertex_count: usize,
+        targets: &[MorphTarget],
+    ) -> anyhow::Result<Self> {
+        if targets.is_empty() {
+            bail!("A mesh with morph targets needs at least one target");
+        }
+        let deltas = pack_deltas(vertex_count, targets)?;
+        let info_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Morph Info Buffer"),
+            contents: bytemuck::cast_slice(&[MorphInfo {
+                target_count: targets.len() as u32,
+                vertex_count: vertex_count as u32,
+                _padding: [0; 2],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let delta_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Morph Delta Buffer"),
+            contents: bytemuck::cast_slice(&deltas),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        Ok(Self {
+            names: targets.iter().map(|target| target.name.clone()).collect(),
+            info_buffer,
+            delta_buffer,
+        })
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+}
+
+/// Weight of every morph target of a mesh, for each of its instances.
+/// Instances are matched by their index in the instance buffer.
+pub struct MorphWeights {
+    weights: Vec<f32>,
+    target_count: usize,
+    instance_count: usize,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl MorphWeights {
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline: &render::MorphPipeline,
+        targets: &MorphTargets,
+        instance_count: usize,
+        clustered_lights: &cluster::ClusteredLights,
+    ) -> Self {
+        let weights = vec![0.0; targets.names.len() * instance_count.max(1)];
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Morph Weight Buffer"),
+            contents: bytemuck::cast_slice(&weights),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let [clusters, ranges, indices] = clustered_lights.bind_group_entries();
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipeline.get_morph_bind_layout(),
+            entries: &[
+                clusters,
+                ranges,
+                indices,
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: targets.info_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: targets.delta_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("morph_bind_group"),
+        });
+
+        Self {
+            weights,
+            target_count: targets.names.len(),
+            instance_count,
+            buffer,
+            bind_group,
+        }
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.instance_count
+    }
+
+    /// Weights of `instance`, one per target
+    pub fn weights_mut(&mut self, instance: usize) -> &mut [f32] {
+        let start = instance * self.target_count;
+        &mut self.weights[start..start + self.target_count]
+    }
+
+    pub fn prepare(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.weights));
+    }
+
+    /// Binds the clusters, targets and weights for the morph pipeline
+    pub fn bind<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_bind_group(3, &self.bind_group, &[]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(positions: usize, normals: usize) -> MorphTarget {
+        MorphTarget {
+            name: "smile".to_string(),
+            positions: vec![Vector3::new(1.0, 2.0, 3.0); positions],
+            normals: vec![Vector3::new(0.0, 1.0, 0.0); normals],
+            tangents: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn deltas_are_packed_target_after_target() {
+        let deltas = pack_deltas(2, &[target(2, 0), target(2, 2)]).unwrap();
+        assert_eq!(deltas.len(), 4);
+        assert_eq!(deltas[0].position, [1.0, 2.0, 3.0, 0.0]);
+        // Missing attributes do not move
+        assert_eq!(deltas[1].normal, [0.0; 4]);
+        assert_eq!(deltas[3].normal, [0.0, 1.0, 0.0, 0.0]);
+        assert_eq!(deltas[3].tangent, [0.0; 4]);
+    }
+
+    #[test]
+    fn every_vertex_needs_a_delta() {
+        assert!(pack_deltas(3, &[target(2, 0)]).is_err());
+        assert!(pack_deltas(2, &[target(2, 1)]).is_err());
+    }
+}
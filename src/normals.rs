@@ -0,0 +1,115 @@
+//! Normals of meshes imported without any
+
+use std::collections::HashMap;
+
+use cgmath::{Angle, Deg, InnerSpace, Vector3, Zero};
+
+use crate::{mesh_optimizer, model::ModelVertex};
+
+/// Gives every corner of every triangle the normal of the triangles around
+/// the same position that meet it at less than `smoothing_angle`, weighted by
+/// their area. Edges sharper than that stay hard, 0° giving flat shading and
+/// 180° smoothing everything.
+///
+/// Vertices are split where they need several normals, then the identical
+/// corners are welded back together.
+pub fn generate_normals(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    smoothing_angle: Deg<f32>,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let position = |index: u32| Vector3::from(vertices[index as usize].position);
+    // Not normalized, so that larger triangles weigh more
+    let face_normals = indices
+        .chunks_exact(3)
+        .map(|c| (position(c[1]) - position(c[0])).cross(position(c[2]) - position(c[0])))
+        .collect::<Vec<_>>();
+    let unit = |normal: Vector3<f32>| {
+        if normal.magnitude2() > 0.0 {
+            normal.normalize()
+        } else {
+            normal
+        }
+    };
+
+    // Triangles around each position, whichever vertex they use there
+    let key = |index: u32| vertices[index as usize].position.map(f32::to_bits);
+    let mut faces_at = HashMap::<_, Vec<usize>>::new();
+    for (face, corners) in indices.chunks_exact(3).enumerate() {
+        for &index in corners {
+            faces_at.entry(key(index)).or_default().push(face);
+        }
+    }
+
+    // Small slack so that coplanar triangles always smooth together
+    let min_cos = smoothing_angle.cos() - 1e-5;
+    let mut corners = Vec::with_capacity(indices.len());
+    for (face, face_corners) in indices.chunks_exact(3).enumerate() {
+        let face_normal = unit(face_normals[face]);
+        for &index in face_corners {
+            let normal = faces_at[&key(index)]
+                .iter()
+                .filter(|&&other| {
+                    other == face || unit(face_normals[other]).dot(face_normal) >= min_cos
+                })
+                .fold(Vector3::zero(), |sum, &other| sum + face_normals[other]);
+            let normal = if normal.magnitude2() > 0.0 {
+                normal.normalize()
+            } else {
+                Vector3::unit_y()
+            };
+            corners.push(ModelVertex {
+                normal: normal.into(),
+                ..vertices[index as usize]
+            });
+        }
+    }
+
+    let mut indices = (0..corners.len() as u32).collect::<Vec<_>>();
+    let vertices = mesh_optimizer::weld(&corners, &mut indices);
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two triangles folded at a right angle along the x axis
+    fn fold() -> (Vec<ModelVertex>, Vec<u32>) {
+        let vertex = |position| ModelVertex {
+            position,
+            tex_coords: [0.0; 2],
+            normal: [0.0; 3],
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        };
+        let vertices = vec![
+            vertex([0.0, 0.0, 0.0]),
+            vertex([1.0, 0.0, 0.0]),
+            vertex([0.0, 0.0, -1.0]),
+            vertex([0.0, 1.0, 0.0]),
+        ];
+        (vertices, vec![0, 1, 2, 1, 0, 3])
+    }
+
+    #[test]
+    fn sharp_edges_stay_hard() {
+        let (vertices, indices) = fold();
+        let (vertices, indices) = generate_normals(&vertices, &indices, Deg(60.0));
+        // The shared edge is split
+        assert_eq!(vertices.len(), 6);
+        assert_eq!(vertices[indices[0] as usize].normal, [0.0, 1.0, 0.0]);
+        assert_eq!(vertices[indices[3] as usize].normal, [0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn wide_angles_smooth_edges() {
+        let (vertices, indices) = fold();
+        let (vertices, indices) = generate_normals(&vertices, &indices, Deg(120.0));
+        assert_eq!(vertices.len(), 4);
+        let shared = Vector3::from(vertices[indices[0] as usize].normal);
+        assert!((shared - Vector3::new(0.0, 1.0, -1.0).normalize()).magnitude() < 1e-6);
+        // Corners only touching one triangle keep its normal
+        assert_eq!(vertices[indices[2] as usize].normal, [0.0, 1.0, 0.0]);
+    }
+}
@@ -0,0 +1,336 @@
+use bytemuck::Zeroable;
+use cgmath::{InnerSpace, Matrix4, Point3, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::{model, render};
+
+/// Region new particles are spawned from, relative to the emitter position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmitterShape {
+    /// Spawned at the emitter position, flying in every direction
+    Point,
+    /// Spawned inside the sphere, flying in every direction
+    Sphere { radius: f32 },
+    /// Spawned at the emitter position, flying at most `angle` radians away
+    /// from the emitter direction
+    Cone { angle: f32 },
+    /// Spawned inside the box, flying along the emitter direction
+    Box { half_extents: Vector3<f32> },
+}
+
+impl EmitterShape {
+    fn id(&self) -> u32 {
+        match self {
+            EmitterShape::Point => 0,
+            EmitterShape::Sphere { .. } => 1,
+            EmitterShape::Cone { .. } => 2,
+            EmitterShape::Box { .. } => 3,
+        }
+    }
+
+    fn params(&self) -> [f32; 4] {
+        match *self {
+            EmitterShape::Point => [0.0; 4],
+            EmitterShape::Sphere { radius } => [radius, 0.0, 0.0, 0.0],
+            EmitterShape::Cone { angle } => [angle, 0.0, 0.0, 0.0],
+            EmitterShape::Box { half_extents } => {
+                [half_extents.x, half_extents.y, half_extents.z, 0.0]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParticleBlend {
+    /// Colours add up, for glowing effects like fire or sparks
+    Additive,
+    /// Regular transparency, for effects like smoke or dust
+    Alpha,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmitterConfig {
+    pub position: Point3<f32>,
+    pub shape: EmitterShape,
+    /// Main direction of the `Cone` and `Box` shapes
+    pub direction: Vector3<f32>,
+    /// Particles spawned per second
+    pub rate: f32,
+    /// Range the lifetime of each particle is picked from, in seconds
+    pub lifetime: [f32; 2],
+    /// Range the initial speed of each particle is picked from
+    pub speed: [f32; 2],
+    pub gravity: Vector3<f32>,
+    /// Colour at birth, interpolated towards `color_end` over the lifetime
+    pub color_start: [f32; 4],
+    pub color_end: [f32; 4],
+    /// World size at birth, interpolated towards `size_end` over the lifetime
+    pub size_start: f32,
+    pub size_end: f32,
+    pub blend: ParticleBlend,
+}
+
+impl Default for EmitterConfig {
+    fn default() -> Self {
+        Self {
+            position: Point3::new(0.0, 0.0, 0.0),
+            shape: EmitterShape::Point,
+            direction: Vector3::unit_y(),
+            rate: 100.0,
+            lifetime: [1.0, 2.0],
+            speed: [1.0, 2.0],
+            gravity: Vector3::new(0.0, -9.81, 0.0),
+            color_start: [1.0, 1.0, 1.0, 1.0],
+            color_end: [1.0, 1.0, 1.0, 0.0],
+            size_start: 0.1,
+            size_end: 0.1,
+            blend: ParticleBlend::Alpha,
+        }
+    }
+}
+
+/// Particle state as stored on the GPU. Dead particles have reached their
+/// lifetime, freshly created buffers only hold dead particles.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Particle {
+    position: [f32; 3],
+    age: f32,
+    velocity: [f32; 3],
+    lifetime: f32,
+}
+
+impl model::Vertex for Particle {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<Particle>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Emitter parameters shared by the simulation and the rendering shaders
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct EmitterUniform {
+    position: [f32; 3],
+    shape: u32,
+    shape_params: [f32; 4],
+    direction: [f32; 3],
+    spawn_start: u32,
+    gravity: [f32; 3],
+    spawn_count: u32,
+    color_start: [f32; 4],
+    color_end: [f32; 4],
+    camera_right: [f32; 3],
+    size_start: f32,
+    camera_up: [f32; 3],
+    size_end: f32,
+    lifetime: [f32; 2],
+    speed: [f32; 2],
+    dt: f32,
+    seed: u32,
+    capacity: u32,
+    _padding: u32,
+}
+
+/// Decides which slots of the particle ring buffer are respawned each frame
+#[derive(Debug, Clone, PartialEq)]
+struct Spawner {
+    capacity: u32,
+    next: u32,
+    /// Fraction of a particle carried over to the next frame
+    accumulator: f32,
+}
+
+impl Spawner {
+    fn new(capacity: u32) -> Self {
+        Self {
+            capacity,
+            next: 0,
+            accumulator: 0.0,
+        }
+    }
+
+    /// Returns the first slot to spawn into and how many particles to spawn
+    fn advance(&mut self, rate: f32, dt: f32) -> (u32, u32) {
+        self.accumulator += rate.max(0.0) * dt;
+        let count = self.accumulator.floor();
+        self.accumulator -= count;
+        let count = (count as u32).min(self.capacity);
+
+        let start = self.next;
+        self.next = (self.next + count) % self.capacity;
+        (start, count)
+    }
+}
+
+/// A particle effect simulated entirely on the GPU. Once the buffer is full,
+/// new particles replace the oldest ones.
+pub struct ParticleEmitter {
+    pub config: EmitterConfig,
+    spawner: Spawner,
+    seed: u32,
+    uniform_buffer: wgpu::Buffer,
+    particle_buffer: wgpu::Buffer,
+    simulate_bind_group: wgpu::BindGroup,
+    render_bind_group: wgpu::BindGroup,
+}
+
+impl ParticleEmitter {
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &render::ParticlePipelines,
+        config: EmitterConfig,
+        capacity: u32,
+    ) -> Self {
+        let capacity = capacity.max(1);
+        let uniform = EmitterUniform {
+            capacity,
+            ..EmitterUniform::zeroed()
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle Emitter Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let particle_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle Buffer"),
+            contents: bytemuck::cast_slice(&vec![Particle::zeroed(); capacity as usize]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+        });
+
+        let simulate_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipelines.get_simulate_bind_layout(),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: particle_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("particle_simulate_bind_group"),
+        });
+        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipelines.get_render_bind_layout(),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("particle_render_bind_group"),
+        });
+
+        Self {
+            config,
+            spawner: Spawner::new(capacity),
+            seed: 0,
+            uniform_buffer,
+            particle_buffer,
+            simulate_bind_group,
+            render_bind_group,
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.spawner.capacity
+    }
+
+    /// Uploads the parameters for the next simulation step. `view` is the
+    /// camera view matrix, particles are turned to face it.
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: f32, view: Matrix4<f32>) {
+        let (spawn_start, spawn_count) = self.spawner.advance(self.config.rate, dt);
+        self.seed = self.seed.wrapping_add(1);
+
+        // The rows of the view matrix are the camera axes in world space
+        let right = Vector3::new(view.x.x, view.y.x, view.z.x).normalize();
+        let up = Vector3::new(view.x.y, view.y.y, view.z.y).normalize();
+
+        let config = &self.config;
+        let uniform = EmitterUniform {
+            position: config.position.into(),
+            shape: config.shape.id(),
+            shape_params: config.shape.params(),
+            direction: config.direction.normalize().into(),
+            spawn_start,
+            gravity: config.gravity.into(),
+            spawn_count,
+            color_start: config.color_start,
+            color_end: config.color_end,
+            camera_right: right.into(),
+            size_start: config.size_start,
+            camera_up: up.into(),
+            size_end: config.size_end,
+            lifetime: config.lifetime,
+            speed: config.speed,
+            dt,
+            seed: self.seed,
+            capacity: self.spawner.capacity,
+            _padding: 0,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Spawns and moves the particles. Must be recorded before the pass
+    /// rendering them.
+    pub fn simulate<'a>(
+        &'a self,
+        compute_pass: &mut wgpu::ComputePass<'a>,
+        pipelines: &'a render::ParticlePipelines,
+    ) {
+        let workgroups = self
+            .spawner
+            .capacity
+            .div_ceil(render::ParticlePipelines::WORKGROUP_SIZE);
+        compute_pass.set_pipeline(pipelines.get_simulate_pipeline());
+        compute_pass.set_bind_group(0, &self.simulate_bind_group, &[]);
+        compute_pass.dispatch_workgroups(workgroups, 1, 1);
+    }
+
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a render::ParticlePipelines,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(pipelines.get_render_pipeline(self.config.blend));
+        render_pass.set_vertex_buffer(0, self.particle_buffer.slice(..));
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.render_bind_group, &[]);
+        render_pass.draw(0..6, 0..self.spawner.capacity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawner_carries_fractions_and_wraps() {
+        let mut spawner = Spawner::new(8);
+
+        assert_eq!(spawner.advance(10.0, 0.25), (0, 2));
+        // 0.5 particle left over from the previous frame
+        assert_eq!(spawner.advance(10.0, 0.25), (2, 3));
+        assert_eq!(spawner.advance(10.0, 0.4), (5, 4));
+        assert_eq!(spawner.next, 1);
+        // Never spawns more than the buffer holds
+        assert_eq!(spawner.advance(1000.0, 1.0), (1, 8));
+        assert_eq!(spawner.next, 1);
+    }
+}
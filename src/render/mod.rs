@@ -1,7 +1,13 @@
 mod pipelines;
 
-pub use pipelines::utils::create_render_pipeline;
-pub use pipelines::{GlobalBindLayout, Pipelines};
+pub use pipelines::utils::{
+    create_gbuffer_pipeline, create_render_pipeline, create_render_pipeline_with_options,
+    PipelineOptions,
+};
+pub use pipelines::{
+    DeferredPipelines, GlobalBindLayout, MorphPipeline, ParticlePipelines, Pipelines,
+    MAX_JOINTS, MAX_TERRAIN_LAYERS,
+};
 
 mod renderer;
-pub use renderer::{DefaultState, State};
+pub use renderer::{DefaultState, RenderPath, State};
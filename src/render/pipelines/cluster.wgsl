@@ -0,0 +1,81 @@
+// Point lights assigned to view clusters by `ClusteredLights`, for the
+// shaders including this file, which declare the `camera` uniform and leave
+// group 3 to the clusters
+
+struct PointLight {
+    position_radius: vec4<f32>,
+    color_intensity: vec4<f32>,
+}
+struct Clusters {
+    // Clusters along X, Y and depth, then the number of lights
+    grid: vec4<u32>,
+    screen_size: vec2<f32>,
+    z_near: f32,
+    z_far: f32,
+    lights: array<PointLight, 256>,
+}
+@group(3) @binding(0)
+var<uniform> clusters: Clusters;
+// First light index of each cluster in the low 16 bits, light count in the
+// high 16 bits, four clusters per element
+@group(3) @binding(1)
+var<uniform> cluster_ranges: array<vec4<u32>, 576>;
+// 16-bit light indices, eight per element
+@group(3) @binding(2)
+var<uniform> cluster_indices: array<vec4<u32>, 1024>;
+
+// Cluster holding the fragment, depth slices are spaced exponentially
+fn cluster_index(frag_coord: vec4<f32>) -> u32 {
+    let z_near = clusters.z_near;
+    let z_far = clusters.z_far;
+    let depth = z_near * z_far / (z_far - frag_coord.z * (z_far - z_near));
+    let slice = log(depth / z_near) / log(z_far / z_near) * f32(clusters.grid.z);
+    let tile = frag_coord.xy / clusters.screen_size * vec2<f32>(clusters.grid.xy);
+
+    let x = min(u32(max(tile.x, 0.0)), clusters.grid.x - 1u);
+    let y = min(u32(max(tile.y, 0.0)), clusters.grid.y - 1u);
+    let z = min(u32(max(slice, 0.0)), clusters.grid.z - 1u);
+    return (z * clusters.grid.y + y) * clusters.grid.x + x;
+}
+
+// Sums the point lights of the fragment's cluster, with highlights of the
+// `specular` colour and sharpness
+fn point_lighting(
+    frag_coord: vec4<f32>,
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    albedo: vec3<f32>,
+    specular: vec3<f32>,
+    shininess: f32,
+) -> vec3<f32> {
+    let cluster = cluster_index(frag_coord);
+    let range = cluster_ranges[cluster / 4u][cluster % 4u];
+    let first = range & 0xffffu;
+    let count = range >> 16u;
+    let view_dir = normalize(camera.view_pos.xyz - position);
+
+    var result = vec3<f32>(0.0);
+    for (var i = first; i < first + count; i += 1u) {
+        let packed = cluster_indices[i / 8u][(i / 2u) % 4u];
+        let point_light = clusters.lights[(packed >> (16u * (i % 2u))) & 0xffffu];
+
+        let to_light = point_light.position_radius.xyz - position;
+        let distance = length(to_light);
+        let radius = point_light.position_radius.w;
+        if distance >= radius {
+            continue;
+        }
+
+        // Same falloff as the deferred light volumes
+        let falloff = 1.0 - distance / radius;
+        let light_color = point_light.color_intensity.rgb * point_light.color_intensity.a
+            * falloff * falloff;
+
+        let light_dir = to_light / distance;
+        let half_dir = normalize(view_dir + light_dir);
+        let diffuse_strength = max(dot(normal, light_dir), 0.0);
+        let specular_strength = pow(max(dot(normal, half_dir), 0.0), shininess);
+        result += (diffuse_strength * albedo + specular_strength * specular) * light_color;
+    }
+    return result;
+}
@@ -0,0 +1,211 @@
+use crate::{
+    deferred::PointLight,
+    model::{self, Vertex, VertexFormat},
+    render::{self, PipelineOptions},
+    InstanceRaw,
+};
+
+use super::GlobalBindLayout;
+
+/// Pipelines of the deferred renderer: the geometry passes filling the
+/// G-buffer and the lighting passes reading it.
+pub struct DeferredPipelines {
+    gbuffer_bind_layout: wgpu::BindGroupLayout,
+    geometry: wgpu::RenderPipeline,
+    compact_geometry: wgpu::RenderPipeline,
+    terrain_geometry: wgpu::RenderPipeline,
+    light: wgpu::RenderPipeline,
+    point_light: wgpu::RenderPipeline,
+}
+
+impl DeferredPipelines {
+    pub fn new(
+        global_bind_layout: &GlobalBindLayout,
+        terrain_layers_bind_layout: &wgpu::BindGroupLayout,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let texture_entry = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                sample_type,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let color = wgpu::TextureSampleType::Float { filterable: false };
+        let gbuffer_bind_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    texture_entry(0, color),
+                    texture_entry(1, color),
+                    texture_entry(2, color),
+                    texture_entry(3, wgpu::TextureSampleType::Depth),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("gbuffer_bind_group_layout"),
+            });
+
+        // Same layout as the forward model pipeline, so `DrawModel` works
+        let geometry_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Geometry Pipeline Layout"),
+            bind_group_layouts: &[
+                global_bind_layout.get_texture_bind_layout(),
+                global_bind_layout.get_camera_bind_layout(),
+                global_bind_layout.get_light_bind_layout(),
+            ],
+            push_constant_ranges: &[],
+        });
+        let geometry_pipeline = |format: VertexFormat, vertex_entry_point| {
+            render::create_gbuffer_pipeline(
+                device,
+                &geometry_layout,
+                &[format.desc(), InstanceRaw::desc()],
+                wgpu::ShaderModuleDescriptor {
+                    label: Some("Geometry Shader"),
+                    source: wgpu::ShaderSource::Wgsl(include_str!("gbuffer.wgsl").into()),
+                },
+                vertex_entry_point,
+                "fs_main",
+            )
+        };
+        let geometry = geometry_pipeline(VertexFormat::Full, "vs_main");
+        let compact_geometry = geometry_pipeline(VertexFormat::Compact, "vs_compact");
+
+        let terrain_geometry_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Terrain Geometry Pipeline Layout"),
+                bind_group_layouts: &[
+                    global_bind_layout.get_camera_bind_layout(),
+                    global_bind_layout.get_light_bind_layout(),
+                    terrain_layers_bind_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+        let terrain_geometry = render::create_gbuffer_pipeline(
+            device,
+            &terrain_geometry_layout,
+            &[model::ModelVertex::desc()],
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Terrain Geometry Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("fog.wgsl"),
+                        include_str!("cluster.wgsl"),
+                        include_str!("terrain.wgsl")
+                    )
+                    .into(),
+                ),
+            },
+            "vs_main",
+            "fs_gbuffer",
+        );
+
+        let lighting_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Deferred Lighting Pipeline Layout"),
+            bind_group_layouts: &[
+                global_bind_layout.get_camera_bind_layout(),
+                global_bind_layout.get_light_bind_layout(),
+                &gbuffer_bind_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        // Lighting passes read the depth buffer, they can't test against it
+        let light = render::create_render_pipeline_with_options(
+            device,
+            &lighting_layout,
+            config.format,
+            None,
+            &[],
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Deferred Light Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("fog.wgsl"),
+                        include_str!("deferred_light.wgsl")
+                    )
+                    .into(),
+                ),
+            },
+            &PipelineOptions {
+                cull_mode: None,
+                ..Default::default()
+            },
+        );
+        // Only the back faces of the light volumes are drawn so that they
+        // still cover the screen once the camera is inside of them
+        let point_light = render::create_render_pipeline_with_options(
+            device,
+            &lighting_layout,
+            config.format,
+            None,
+            &[PointLight::desc()],
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Deferred Point Light Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("fog.wgsl"),
+                        include_str!("deferred_point_light.wgsl")
+                    )
+                    .into(),
+                ),
+            },
+            &PipelineOptions {
+                blend: wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::REPLACE,
+                },
+                cull_mode: Some(wgpu::Face::Front),
+                ..Default::default()
+            },
+        );
+
+        Self {
+            gbuffer_bind_layout,
+            geometry,
+            compact_geometry,
+            terrain_geometry,
+            light,
+            point_light,
+        }
+    }
+
+    /// Layout of the G-buffer textures read by the lighting passes
+    pub fn get_gbuffer_bind_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.gbuffer_bind_layout
+    }
+
+    pub fn get_geometry_pipeline(&self, format: VertexFormat) -> &wgpu::RenderPipeline {
+        match format {
+            VertexFormat::Full => &self.geometry,
+            VertexFormat::Compact => &self.compact_geometry,
+        }
+    }
+
+    pub fn get_terrain_geometry_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.terrain_geometry
+    }
+
+    pub fn get_light_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.light
+    }
+
+    pub fn get_point_light_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.point_light
+    }
+}
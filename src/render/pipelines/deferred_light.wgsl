@@ -0,0 +1,88 @@
+// Applies the ambient term and the main light to every pixel of the G-buffer
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    fog: Fog,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Light {
+    position: vec3<f32>,
+    color: vec3<f32>,
+}
+@group(1) @binding(0)
+var<uniform> light: Light;
+
+struct Deferred {
+    inv_view_proj: mat4x4<f32>,
+}
+@group(2) @binding(0)
+var t_albedo: texture_2d<f32>;
+@group(2) @binding(1)
+var t_normal: texture_2d<f32>;
+@group(2) @binding(2)
+var t_material: texture_2d<f32>;
+@group(2) @binding(3)
+var t_depth: texture_depth_2d;
+@group(2) @binding(4)
+var<uniform> deferred: Deferred;
+
+// Vertex shader
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+// A single triangle covering the whole screen
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+// Fragment shader
+
+fn world_position(pixel: vec2<i32>, depth: f32) -> vec3<f32> {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let uv = (vec2<f32>(pixel) + 0.5) / size;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = deferred.inv_view_proj * ndc;
+    return world.xyz / world.w;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let depth = textureLoad(t_depth, pixel, 0);
+    if depth >= 1.0 {
+        // Nothing was drawn here, keep the clear colour
+        discard;
+    }
+
+    let albedo = textureLoad(t_albedo, pixel, 0).rgb;
+    let normal = normalize(textureLoad(t_normal, pixel, 0).xyz);
+    let material = textureLoad(t_material, pixel, 0);
+    let position = world_position(pixel, depth);
+
+    // We don't need (or want) much ambient light, so 0.1 is fine
+    let ambient_strength = 0.1;
+    let ambient_color = light.color * ambient_strength;
+
+    let light_dir = normalize(light.position - position);
+    let view_dir = normalize(camera.view_pos.xyz - position);
+    let half_dir = normalize(view_dir + light_dir);
+
+    let diffuse_strength = max(dot(normal, light_dir), 0.0);
+    let diffuse_color = light.color * diffuse_strength;
+
+    let specular_strength = pow(max(dot(normal, half_dir), 0.0), material.g * 256.0);
+    let specular_color = specular_strength * material.r * light.color;
+
+    let result = (ambient_color + diffuse_color + specular_color) * albedo;
+
+    return vec4<f32>(apply_fog(result, position), 1.0);
+}
@@ -0,0 +1,115 @@
+// Adds the contribution of point lights, each drawn as a box around its
+// radius of influence so only the pixels it can reach are shaded
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    fog: Fog,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Deferred {
+    inv_view_proj: mat4x4<f32>,
+}
+@group(2) @binding(0)
+var t_albedo: texture_2d<f32>;
+@group(2) @binding(1)
+var t_normal: texture_2d<f32>;
+@group(2) @binding(2)
+var t_material: texture_2d<f32>;
+@group(2) @binding(3)
+var t_depth: texture_depth_2d;
+@group(2) @binding(4)
+var<uniform> deferred: Deferred;
+
+// Vertex shader
+
+struct LightInput {
+    @location(0) position_radius: vec4<f32>,
+    @location(1) color_intensity: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) position_radius: vec4<f32>,
+    @location(1) color_intensity: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    light: LightInput,
+) -> VertexOutput {
+    // Corners of a unit cube, six faces of two triangles each
+    var indices = array<u32, 36>(
+        0u, 2u, 1u, 1u, 2u, 3u,
+        4u, 5u, 6u, 5u, 7u, 6u,
+        0u, 1u, 4u, 1u, 5u, 4u,
+        2u, 6u, 3u, 3u, 6u, 7u,
+        0u, 4u, 2u, 2u, 4u, 6u,
+        1u, 3u, 5u, 3u, 7u, 5u,
+    );
+    let corner = indices[vertex_index];
+    let offset = vec3<f32>(
+        f32(corner & 1u),
+        f32((corner >> 1u) & 1u),
+        f32((corner >> 2u) & 1u),
+    ) * 2.0 - 1.0;
+
+    let world_position = light.position_radius.xyz + offset * light.position_radius.w;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.position_radius = light.position_radius;
+    out.color_intensity = light.color_intensity;
+    return out;
+}
+
+// Fragment shader
+
+fn world_position(pixel: vec2<i32>, depth: f32) -> vec3<f32> {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let uv = (vec2<f32>(pixel) + 0.5) / size;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = deferred.inv_view_proj * ndc;
+    return world.xyz / world.w;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let depth = textureLoad(t_depth, pixel, 0);
+    if depth >= 1.0 {
+        discard;
+    }
+
+    let position = world_position(pixel, depth);
+    let to_light = in.position_radius.xyz - position;
+    let distance = length(to_light);
+    let radius = in.position_radius.w;
+    if distance >= radius {
+        discard;
+    }
+
+    let albedo = textureLoad(t_albedo, pixel, 0).rgb;
+    let normal = normalize(textureLoad(t_normal, pixel, 0).xyz);
+    let material = textureLoad(t_material, pixel, 0);
+
+    // Smooth falloff reaching exactly zero at the radius
+    let falloff = 1.0 - distance / radius;
+    let attenuation = falloff * falloff;
+    let light_color = in.color_intensity.rgb * in.color_intensity.a * attenuation;
+
+    let light_dir = to_light / distance;
+    let view_dir = normalize(camera.view_pos.xyz - position);
+    let half_dir = normalize(view_dir + light_dir);
+
+    let diffuse_strength = max(dot(normal, light_dir), 0.0);
+    let specular_strength = pow(max(dot(normal, half_dir), 0.0), material.g * 256.0);
+
+    let result = (diffuse_strength * albedo + specular_strength * material.r) * light_color;
+    // The lighting pass already blended the fog in, only the share of this
+    // light that makes it through the fog is added
+    return vec4<f32>(result * (1.0 - fog_amount(position)), 1.0);
+}
@@ -0,0 +1,50 @@
+// Fog applied by the shaders including this file, which declare the
+// `camera` uniform holding it
+
+struct Fog {
+    color: vec3<f32>,
+    // 0 for no fog, 1 for linear, 2 for exponential and 3 for height fog
+    mode: u32,
+    sun_color: vec3<f32>,
+    sun_scattering: f32,
+    sun_direction: vec3<f32>,
+    // Start and end distances of linear fog, or the density, then the
+    // falloff and base altitude of height fog
+    params: vec4<f32>,
+}
+
+// Share of the colour at `position` hidden by fog
+fn fog_amount(position: vec3<f32>) -> f32 {
+    let fog = camera.fog;
+    let to_point = position - camera.view_pos.xyz;
+    let distance = length(to_point);
+    if fog.mode == 1u {
+        return clamp((distance - fog.params.x) / max(fog.params.y - fog.params.x, 0.0001), 0.0, 1.0);
+    } else if fog.mode == 2u {
+        return 1.0 - exp(-fog.params.x * distance);
+    } else if fog.mode == 3u {
+        // Density integrated along the view ray, it decays exponentially
+        // with the altitude
+        let falloff = fog.params.y;
+        let density = fog.params.x * exp(-falloff * (camera.view_pos.y - fog.params.z));
+        let climb = falloff * to_point.y;
+        var depth = distance;
+        if abs(climb) > 0.0001 {
+            depth = distance * (1.0 - exp(-climb)) / climb;
+        }
+        return 1.0 - exp(-density * depth);
+    }
+    return 0.0;
+}
+
+// Blends the fog over `color`, the fog glows when looking towards the sun
+fn apply_fog(color: vec3<f32>, position: vec3<f32>) -> vec3<f32> {
+    let view_dir = normalize(position - camera.view_pos.xyz);
+    let sun_amount = pow(max(dot(view_dir, camera.fog.sun_direction), 0.0), 8.0);
+    let fog_color = mix(
+        camera.fog.color,
+        camera.fog.sun_color,
+        sun_amount * camera.fog.sun_scattering,
+    );
+    return mix(color, fog_color, fog_amount(position));
+}
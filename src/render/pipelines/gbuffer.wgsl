@@ -0,0 +1,171 @@
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+}
+
+// Quantised layout of `CompactVertex`: half float UVs, a Snorm16 normal
+// with the handedness of the tangent frame in w and an octahedral tangent
+struct CompactVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec4<f32>,
+    @location(3) tangent: vec2<f32>,
+}
+
+fn decode_octahedral(encoded: vec2<f32>) -> vec3<f32> {
+    var v = vec3<f32>(encoded, 1.0 - abs(encoded.x) - abs(encoded.y));
+    // Fold the lower hemisphere back out of the corners
+    let fold = max(-v.z, 0.0);
+    v.x += select(fold, -fold, v.x >= 0.0);
+    v.y += select(fold, -fold, v.y >= 0.0);
+    return normalize(v);
+}
+
+fn decode_compact(model: CompactVertexInput) -> VertexInput {
+    let normal = normalize(model.normal.xyz);
+    let tangent = decode_octahedral(model.tangent);
+    var out: VertexInput;
+    out.position = model.position;
+    out.tex_coords = model.tex_coords;
+    out.normal = normal;
+    out.tangent = tangent;
+    out.bitangent = cross(normal, tangent) * model.normal.w;
+    return out;
+}
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+    @location(14) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_tangent: vec3<f32>,
+    @location(3) world_bitangent: vec3<f32>,
+    @location(4) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    return vertex(model, instance);
+}
+
+@vertex
+fn vs_compact(
+    model: CompactVertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    return vertex(decode_compact(model), instance);
+}
+
+fn vertex(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    // Tangents lie in the surface and follow it like positions do
+    let tangent_model_matrix = mat3x3<f32>(
+        instance.model_matrix_0.xyz,
+        instance.model_matrix_1.xyz,
+        instance.model_matrix_2.xyz,
+    );
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    out.color = instance.color;
+    out.world_normal = normal_matrix * model.normal;
+    out.world_tangent = tangent_model_matrix * model.tangent;
+    out.world_bitangent = tangent_model_matrix * model.bitangent;
+    return out;
+}
+
+// Fragment shader
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+
+struct Material {
+    ambient: vec4<f32>,
+    // Diffuse colour, then the opacity
+    diffuse: vec4<f32>,
+    // Specular colour, then the shininess
+    specular: vec4<f32>,
+    // 0 for the colour alone, 1 without specular highlights, 2 and above
+    // with them
+    illumination: u32,
+    // Less opaque fragments are discarded
+    alpha_cutoff: f32,
+}
+@group(0) @binding(4)
+var<uniform> material: Material;
+
+struct GBufferOutput {
+    @location(0) albedo: vec4<f32>,
+    // World space normal
+    @location(1) normal: vec4<f32>,
+    // Specular strength and shininess divided by 256
+    @location(2) material: vec4<f32>,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> GBufferOutput {
+    let object_color = textureSample(t_diffuse, s_diffuse, in.tex_coords)
+        * material.diffuse * in.color;
+    let object_normal = textureSample(t_normal, s_normal, in.tex_coords);
+    if object_color.a < material.alpha_cutoff {
+        discard;
+    }
+
+    let tangent_matrix = mat3x3<f32>(
+        normalize(in.world_tangent),
+        normalize(in.world_bitangent),
+        normalize(in.world_normal),
+    );
+
+    var out: GBufferOutput;
+    out.albedo = vec4<f32>(object_color.rgb, 1.0);
+    out.normal = vec4<f32>(normalize(tangent_matrix * (object_normal.xyz * 2.0 - 1.0)), 0.0);
+    // The lighting pass has a single specular strength, and no unlit or
+    // ambient colour
+    let specular = dot(material.specular.rgb, vec3<f32>(1.0 / 3.0))
+        * f32(material.illumination >= 2u);
+    out.material = vec4<f32>(specular, min(material.specular.a, 256.0) / 256.0, 0.0, 0.0);
+    return out;
+}
@@ -1,12 +1,10 @@
-use crate::{
-    model::{self, Vertex},
-    render, texture,
-};
+use crate::{model::VertexFormat, render, texture};
 
 use super::GlobalBindLayout;
 
 pub struct LightPipeline {
     pipeline: wgpu::RenderPipeline,
+    compact_pipeline: wgpu::RenderPipeline,
 }
 
 impl LightPipeline {
@@ -15,33 +13,42 @@ impl LightPipeline {
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
     ) -> Self {
-        let pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Light Pipeline Layout"),
-                bind_group_layouts: &[
-                    global_bind_layout.get_camera_bind_layout(),
-                    global_bind_layout.get_light_bind_layout(),
-                ],
-                push_constant_ranges: &[],
-            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light Pipeline Layout"),
+            bind_group_layouts: &[
+                global_bind_layout.get_camera_bind_layout(),
+                global_bind_layout.get_light_bind_layout(),
+            ],
+            push_constant_ranges: &[],
+        });
+        // Only the position is read, which both vertex formats store as is
+        let create_pipeline = |format: VertexFormat| {
             let shader = wgpu::ShaderModuleDescriptor {
                 label: Some("Light Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("light.wgsl").into()),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(include_str!("fog.wgsl"), include_str!("light.wgsl")).into(),
+                ),
             };
             render::create_render_pipeline(
-                &device,
+                device,
                 &layout,
                 config.format,
                 Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc()],
+                &[format.desc()],
                 shader,
             )
         };
 
-        Self { pipeline }
+        Self {
+            pipeline: create_pipeline(VertexFormat::Full),
+            compact_pipeline: create_pipeline(VertexFormat::Compact),
+        }
     }
 
-    pub fn get_pipeline(&self) -> &wgpu::RenderPipeline {
-        &self.pipeline
+    pub fn get_pipeline(&self, format: VertexFormat) -> &wgpu::RenderPipeline {
+        match format {
+            VertexFormat::Full => &self.pipeline,
+            VertexFormat::Compact => &self.compact_pipeline,
+        }
     }
 }
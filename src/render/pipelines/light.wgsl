@@ -3,6 +3,7 @@
 struct Camera {
     view_pos: vec4<f32>,
     view_proj: mat4x4<f32>,
+    fog: Fog,
 }
 @group(0) @binding(0)
 var<uniform> camera: Camera;
@@ -21,6 +22,7 @@ struct VertexInput {
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) color: vec3<f32>,
+    @location(1) world_position: vec3<f32>,
 }
 
 @vertex
@@ -28,9 +30,11 @@ fn vs_main(
     model: VertexInput,
 ) -> VertexOutput {
     let scale = 0.25;
+    let world_position = model.position * scale + light.position;
     var out: VertexOutput;
-    out.clip_position = camera.view_proj * vec4<f32>(model.position * scale + light.position, 1.0);
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
     out.color = light.color;
+    out.world_position = world_position;
     return out;
 }
 
@@ -38,5 +42,5 @@ fn vs_main(
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
+    return vec4<f32>(apply_fog(in.color, in.world_position), 1.0);
 }
\ No newline at end of file
@@ -1,14 +1,30 @@
+mod deferred;
 mod light;
 mod model;
+mod morph;
+mod particle;
+mod skinned;
+mod sky;
+mod sprite;
+mod terrain;
+mod text;
 pub mod utils;
 
-pub use light::LightPipeline;
-pub use model::ModelPipeline;
+use super::RenderPath;
+use crate::model::VertexFormat;
+
+pub use deferred::DeferredPipelines;
+pub use morph::MorphPipeline;
+pub use particle::ParticlePipelines;
+pub use skinned::MAX_JOINTS;
+pub use terrain::MAX_TERRAIN_LAYERS;
 
 pub struct GlobalBindLayout {
     texture: wgpu::BindGroupLayout,
+    simple_texture: wgpu::BindGroupLayout,
     light: wgpu::BindGroupLayout,
     camera: wgpu::BindGroupLayout,
+    cluster: wgpu::BindGroupLayout,
 }
 
 impl GlobalBindLayout {
@@ -48,10 +64,44 @@ impl GlobalBindLayout {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    // Colours and scalars of the material
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("texture_bind_group_layout"),
             });
 
+        let simple_texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: Some("simple_texture_bind_group_layout"),
+            });
+
         let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
@@ -82,10 +132,18 @@ impl GlobalBindLayout {
                 label: None,
             });
 
+        let cluster_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &Self::cluster_layout_entries(),
+                label: Some("cluster_bind_group_layout"),
+            });
+
         Self {
             texture: texture_bind_group_layout,
+            simple_texture: simple_texture_bind_group_layout,
             light: light_bind_group_layout,
             camera: camera_bind_group_layout,
+            cluster: cluster_bind_group_layout,
         }
     }
 
@@ -93,6 +151,11 @@ impl GlobalBindLayout {
         &self.texture
     }
 
+    /// Layout of a single texture and its sampler, used by overlays like text
+    pub fn get_simple_texture_bind_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.simple_texture
+    }
+
     pub fn get_light_bind_layout(&self) -> &wgpu::BindGroupLayout {
         &self.light
     }
@@ -100,11 +163,40 @@ impl GlobalBindLayout {
     pub fn get_camera_bind_layout(&self) -> &wgpu::BindGroupLayout {
         &self.camera
     }
+
+    /// Layout of the point lights assigned to view clusters
+    pub fn get_cluster_bind_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.cluster
+    }
+
+    /// Point lights, then the light range and light indices of every
+    /// cluster. Uniform buffers keep clustered shading working on WebGL.
+    /// Pipelines short of bind groups put their own bindings after these.
+    pub fn cluster_layout_entries() -> [wgpu::BindGroupLayoutEntry; 3] {
+        [0, 1, 2].map(|binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        })
+    }
 }
 
 pub struct Pipelines {
     render: model::ModelPipeline,
     light: light::LightPipeline,
+    text: text::TextPipeline,
+    sprite: sprite::SpritePipeline,
+    particle: Option<ParticlePipelines>,
+    terrain: terrain::TerrainPipeline,
+    sky: sky::SkyPipeline,
+    skinned: skinned::SkinnedPipeline,
+    morph: Option<MorphPipeline>,
+    deferred: Option<DeferredPipelines>,
 }
 
 impl Pipelines {
@@ -112,18 +204,99 @@ impl Pipelines {
         global_bind_layout: &GlobalBindLayout,
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        render_path: RenderPath,
     ) -> Self {
+        let terrain = terrain::TerrainPipeline::new(global_bind_layout, device, config);
+        let deferred = (render_path == RenderPath::Deferred).then(|| {
+            DeferredPipelines::new(
+                global_bind_layout,
+                terrain.get_layers_bind_layout(),
+                device,
+                config,
+            )
+        });
+
         Self {
             render: model::ModelPipeline::new(global_bind_layout, device, config),
             light: light::LightPipeline::new(global_bind_layout, device, config),
+            text: text::TextPipeline::new(global_bind_layout, device, config),
+            sprite: sprite::SpritePipeline::new(global_bind_layout, device, config),
+            particle: ParticlePipelines::is_supported(device)
+                .then(|| ParticlePipelines::new(global_bind_layout, device, config)),
+            terrain,
+            sky: sky::SkyPipeline::new(global_bind_layout, device, config),
+            skinned: skinned::SkinnedPipeline::new(global_bind_layout, device, config),
+            morph: MorphPipeline::is_supported(device)
+                .then(|| MorphPipeline::new(global_bind_layout, device, config)),
+            deferred,
         }
     }
 
-    pub fn get_render_pipeline(&self) -> &wgpu::RenderPipeline {
-        self.render.get_pipeline()
+    /// Pipeline for models whose meshes are in `format`
+    pub fn get_render_pipeline(&self, format: VertexFormat) -> &wgpu::RenderPipeline {
+        self.render.get_pipeline(format)
+    }
+
+    /// Pipeline for the meshes of models in `format` whose material is
+    /// blended
+    pub fn get_transparent_pipeline(&self, format: VertexFormat) -> &wgpu::RenderPipeline {
+        self.render.get_transparent_pipeline(format)
+    }
+
+    pub fn get_light_pipeline(&self, format: VertexFormat) -> &wgpu::RenderPipeline {
+        self.light.get_pipeline(format)
+    }
+
+    pub fn get_text_screen_pipeline(&self) -> &wgpu::RenderPipeline {
+        self.text.get_screen_pipeline()
+    }
+
+    pub fn get_text_world_pipeline(&self) -> &wgpu::RenderPipeline {
+        self.text.get_world_pipeline()
+    }
+
+    pub fn get_sprite_pipeline(&self) -> &wgpu::RenderPipeline {
+        self.sprite.get_pipeline()
+    }
+
+    /// Missing when the device cannot run compute shaders
+    pub fn get_particle_pipelines(&self) -> Option<&ParticlePipelines> {
+        self.particle.as_ref()
+    }
+
+    pub fn get_terrain_pipeline(&self) -> &wgpu::RenderPipeline {
+        self.terrain.get_pipeline()
+    }
+
+    /// Layout of the splat map and material layers blended by the terrain
+    pub fn get_terrain_layers_bind_layout(&self) -> &wgpu::BindGroupLayout {
+        self.terrain.get_layers_bind_layout()
+    }
+
+    pub fn get_sky_pipeline(&self) -> &wgpu::RenderPipeline {
+        self.sky.get_pipeline()
+    }
+
+    pub fn get_sky_bind_layout(&self) -> &wgpu::BindGroupLayout {
+        self.sky.get_sky_bind_layout()
+    }
+
+    pub fn get_skinned_pipeline(&self) -> &wgpu::RenderPipeline {
+        self.skinned.get_pipeline()
+    }
+
+    /// Layout of the joint palette of one skinned instance
+    pub fn get_joints_bind_layout(&self) -> &wgpu::BindGroupLayout {
+        self.skinned.get_joints_bind_layout()
+    }
+
+    /// Missing when the device cannot read storage buffers in vertex shaders
+    pub fn get_morph_pipeline(&self) -> Option<&MorphPipeline> {
+        self.morph.as_ref()
     }
 
-    pub fn get_light_pipeline(&self) -> &wgpu::RenderPipeline {
-        self.light.get_pipeline()
+    /// Only created for the deferred render path
+    pub fn get_deferred_pipelines(&self) -> Option<&DeferredPipelines> {
+        self.deferred.as_ref()
     }
 }
@@ -1,12 +1,16 @@
 use crate::{
-    model::{self, Vertex},
-    render, texture, InstanceRaw,
+    model::{Vertex, VertexFormat},
+    render::{self, PipelineOptions},
+    texture, InstanceRaw,
 };
 
 use super::GlobalBindLayout;
 
 pub struct ModelPipeline {
     pipeline: wgpu::RenderPipeline,
+    compact_pipeline: wgpu::RenderPipeline,
+    transparent_pipeline: wgpu::RenderPipeline,
+    compact_transparent_pipeline: wgpu::RenderPipeline,
 }
 
 impl ModelPipeline {
@@ -22,29 +26,68 @@ impl ModelPipeline {
                     global_bind_layout.get_texture_bind_layout(),
                     global_bind_layout.get_camera_bind_layout(),
                     global_bind_layout.get_light_bind_layout(),
+                    global_bind_layout.get_cluster_bind_layout(),
                 ],
                 push_constant_ranges: &[],
             });
 
-        let pipeline = {
+        // Both vertex formats share the shader, each with its own entry point
+        let create_pipeline = |format: VertexFormat, options: PipelineOptions| {
             let shader = wgpu::ShaderModuleDescriptor {
                 label: Some("Normal Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("model.wgsl").into()),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("fog.wgsl"),
+                        include_str!("cluster.wgsl"),
+                        include_str!("shading.wgsl"),
+                        include_str!("model.wgsl")
+                    )
+                    .into(),
+                ),
             };
-            render::create_render_pipeline(
-                &device,
+            render::create_render_pipeline_with_options(
+                device,
                 &render_pipeline_layout,
                 config.format,
                 Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                &[format.desc(), InstanceRaw::desc()],
                 shader,
+                &PipelineOptions {
+                    vertex_entry_point: match format {
+                        VertexFormat::Full => "vs_main",
+                        VertexFormat::Compact => "vs_compact",
+                    },
+                    ..options
+                },
             )
         };
+        // Blended meshes are seen through, so they don't hide what is drawn
+        // after them
+        let transparent = || PipelineOptions {
+            blend: wgpu::BlendState::ALPHA_BLENDING,
+            depth_write_enabled: false,
+            ..Default::default()
+        };
+
+        Self {
+            pipeline: create_pipeline(VertexFormat::Full, Default::default()),
+            compact_pipeline: create_pipeline(VertexFormat::Compact, Default::default()),
+            transparent_pipeline: create_pipeline(VertexFormat::Full, transparent()),
+            compact_transparent_pipeline: create_pipeline(VertexFormat::Compact, transparent()),
+        }
+    }
 
-        Self { pipeline }
+    pub fn get_pipeline(&self, format: VertexFormat) -> &wgpu::RenderPipeline {
+        match format {
+            VertexFormat::Full => &self.pipeline,
+            VertexFormat::Compact => &self.compact_pipeline,
+        }
     }
 
-    pub fn get_pipeline(&self) -> &wgpu::RenderPipeline {
-        &self.pipeline
+    pub fn get_transparent_pipeline(&self, format: VertexFormat) -> &wgpu::RenderPipeline {
+        match format {
+            VertexFormat::Full => &self.transparent_pipeline,
+            VertexFormat::Compact => &self.compact_transparent_pipeline,
+        }
     }
 }
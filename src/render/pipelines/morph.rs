@@ -0,0 +1,108 @@
+use crate::{
+    model::{self, Vertex},
+    render, texture, InstanceRaw,
+};
+
+use super::GlobalBindLayout;
+
+/// Meshes deformed by weighted morph targets. The deltas and weights are
+/// read from storage buffers in the vertex shader, so the pipeline is only
+/// created when the device supports them, which excludes WebGL.
+pub struct MorphPipeline {
+    morph_bind_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl MorphPipeline {
+    pub fn is_supported(device: &wgpu::Device) -> bool {
+        device.limits().max_storage_buffers_per_shader_stage >= 2
+    }
+
+    pub fn new(
+        global_bind_layout: &GlobalBindLayout,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        // The clusters, then the target and vertex counts, the deltas of every
+        // target and the weights of every instance. Devices only guarantee
+        // four bind groups.
+        let [clusters, ranges, indices] = GlobalBindLayout::cluster_layout_entries();
+        let morph_bind_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                clusters,
+                ranges,
+                indices,
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(4),
+                storage_entry(5),
+            ],
+            label: Some("morph_bind_group_layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Morph Pipeline Layout"),
+            bind_group_layouts: &[
+                global_bind_layout.get_texture_bind_layout(),
+                global_bind_layout.get_camera_bind_layout(),
+                global_bind_layout.get_light_bind_layout(),
+                &morph_bind_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Morph Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("fog.wgsl"),
+                    include_str!("cluster.wgsl"),
+                    include_str!("shading.wgsl"),
+                    include_str!("morph.wgsl")
+                )
+                .into(),
+            ),
+        };
+        let pipeline = render::create_render_pipeline(
+            device,
+            &layout,
+            config.format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            shader,
+        );
+
+        Self {
+            morph_bind_layout,
+            pipeline,
+        }
+    }
+
+    /// Layout of the clusters, the morph targets of a mesh and the weights of
+    /// its instances
+    pub fn get_morph_bind_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.morph_bind_layout
+    }
+
+    pub fn get_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+}
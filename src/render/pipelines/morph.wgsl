@@ -0,0 +1,55 @@
+// Models with vertices deformed by weighted morph targets, shaded by
+// shading.wgsl. The targets share the last bind group with the clusters.
+
+struct MorphInfo {
+    target_count: u32,
+    vertex_count: u32,
+}
+// Offsets from the base vertex, w is unused
+struct MorphDelta {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+    tangent: vec4<f32>,
+}
+@group(3) @binding(3)
+var<uniform> morph_info: MorphInfo;
+// Every vertex of the first target, then of the second, and so on
+@group(3) @binding(4)
+var<storage, read> deltas: array<MorphDelta>;
+// `target_count` weights per instance
+@group(3) @binding(5)
+var<storage, read> weights: array<f32>;
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    // Add the weighted deltas of every target
+    var position = model.position;
+    var normal = model.normal;
+    var tangent = model.tangent;
+    for (var i = 0u; i < morph_info.target_count; i += 1u) {
+        let weight = weights[instance_index * morph_info.target_count + i];
+        if weight != 0.0 {
+            let delta = deltas[i * morph_info.vertex_count + vertex_index];
+            position += delta.position.xyz * weight;
+            normal += delta.normal.xyz * weight;
+            tangent += delta.tangent.xyz * weight;
+        }
+    }
+    normal = normalize(normal);
+    tangent = normalize(tangent - normal * dot(tangent, normal));
+    // Keep the handedness of the original tangent frame
+    let handedness = sign(dot(cross(model.normal, model.tangent), model.bitangent));
+
+    var morphed: VertexInput;
+    morphed.position = position;
+    morphed.tex_coords = model.tex_coords;
+    morphed.normal = normal;
+    morphed.tangent = tangent;
+    morphed.bitangent = cross(normal, tangent) * handedness;
+    return vertex(morphed, instance);
+}
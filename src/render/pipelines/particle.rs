@@ -0,0 +1,160 @@
+use crate::{
+    model::Vertex,
+    particle::{Particle, ParticleBlend},
+    render::{self, PipelineOptions},
+    texture,
+};
+
+use super::GlobalBindLayout;
+
+/// Compute simulation and billboard rendering of GPU particles. Only created
+/// when the device supports compute shaders, which excludes WebGL.
+pub struct ParticlePipelines {
+    simulate_bind_layout: wgpu::BindGroupLayout,
+    render_bind_layout: wgpu::BindGroupLayout,
+    simulate: wgpu::ComputePipeline,
+    additive: wgpu::RenderPipeline,
+    alpha: wgpu::RenderPipeline,
+}
+
+impl ParticlePipelines {
+    /// Particles processed by each invocation group of the simulation
+    pub const WORKGROUP_SIZE: u32 = 64;
+
+    pub fn is_supported(device: &wgpu::Device) -> bool {
+        let limits = device.limits();
+        limits.max_compute_workgroup_size_x >= Self::WORKGROUP_SIZE
+            && limits.max_storage_buffers_per_shader_stage > 0
+    }
+
+    pub fn new(
+        global_bind_layout: &GlobalBindLayout,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let emitter_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::COMPUTE
+                | wgpu::ShaderStages::VERTEX
+                | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let simulate_bind_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    emitter_entry,
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("particle_simulate_bind_group_layout"),
+            });
+        let render_bind_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[emitter_entry],
+                label: Some("particle_render_bind_group_layout"),
+            });
+
+        let simulate_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Simulate Pipeline Layout"),
+            bind_group_layouts: &[&simulate_bind_layout],
+            push_constant_ranges: &[],
+        });
+        let simulate_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Simulate Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("particle_simulate.wgsl").into()),
+        });
+        let simulate = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Particle Simulate Pipeline"),
+            layout: Some(&simulate_layout),
+            module: &simulate_shader,
+            entry_point: "cs_main",
+        });
+
+        let render_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Render Pipeline Layout"),
+            bind_group_layouts: &[
+                global_bind_layout.get_camera_bind_layout(),
+                &render_bind_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        // Particles are tested against the scene depth but never write it,
+        // so overlapping particles blend with each other
+        let create_render_pipeline = |blend| {
+            render::create_render_pipeline_with_options(
+                device,
+                &render_layout,
+                config.format,
+                Some(texture::Texture::DEPTH_FORMAT),
+                &[Particle::desc()],
+                wgpu::ShaderModuleDescriptor {
+                    label: Some("Particle Shader"),
+                    source: wgpu::ShaderSource::Wgsl(
+                        concat!(include_str!("fog.wgsl"), include_str!("particle.wgsl")).into(),
+                    ),
+                },
+                &PipelineOptions {
+                    blend,
+                    depth_write_enabled: false,
+                    cull_mode: None,
+                    ..Default::default()
+                },
+            )
+        };
+        let additive = create_render_pipeline(wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+        });
+        let alpha = create_render_pipeline(wgpu::BlendState::ALPHA_BLENDING);
+
+        Self {
+            simulate_bind_layout,
+            render_bind_layout,
+            simulate,
+            additive,
+            alpha,
+        }
+    }
+
+    /// Layout of the emitter uniform and the particle storage buffer
+    pub fn get_simulate_bind_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.simulate_bind_layout
+    }
+
+    /// Layout of the emitter uniform alone
+    pub fn get_render_bind_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.render_bind_layout
+    }
+
+    pub fn get_simulate_pipeline(&self) -> &wgpu::ComputePipeline {
+        &self.simulate
+    }
+
+    pub fn get_render_pipeline(&self, blend: ParticleBlend) -> &wgpu::RenderPipeline {
+        match blend {
+            ParticleBlend::Additive => &self.additive,
+            ParticleBlend::Alpha => &self.alpha,
+        }
+    }
+}
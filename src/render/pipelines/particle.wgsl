@@ -0,0 +1,92 @@
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    fog: Fog,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Emitter {
+    position: vec3<f32>,
+    shape: u32,
+    shape_params: vec4<f32>,
+    direction: vec3<f32>,
+    spawn_start: u32,
+    gravity: vec3<f32>,
+    spawn_count: u32,
+    color_start: vec4<f32>,
+    color_end: vec4<f32>,
+    camera_right: vec3<f32>,
+    size_start: f32,
+    camera_up: vec3<f32>,
+    size_end: f32,
+    lifetime: vec2<f32>,
+    speed: vec2<f32>,
+    dt: f32,
+    seed: u32,
+    capacity: u32,
+    _padding: u32,
+}
+@group(1) @binding(0)
+var<uniform> emitter: Emitter;
+
+struct ParticleInput {
+    @location(0) position_age: vec4<f32>,
+    @location(1) velocity_lifetime: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) local: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    particle: ParticleInput,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, 0.5),
+    );
+    let corner = corners[vertex_index];
+
+    var out: VertexOutput;
+    out.local = corner;
+
+    let age = particle.position_age.w;
+    let lifetime = particle.velocity_lifetime.w;
+    if age >= lifetime {
+        // Dead particles collapse outside of the clip volume
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        out.color = vec4<f32>(0.0);
+        return out;
+    }
+
+    let t = age / lifetime;
+    let size = mix(emitter.size_start, emitter.size_end, t);
+    let world_position = particle.position_age.xyz
+        + (emitter.camera_right * corner.x + emitter.camera_up * corner.y) * size;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.color = mix(emitter.color_start, emitter.color_end, t);
+    // Fading out lets the fogged scene behind show through, which suits
+    // both additive and alpha blending
+    out.color.a *= 1.0 - fog_amount(world_position);
+    return out;
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Round particle fading out towards its edge
+    let falloff = clamp(1.0 - length(in.local) * 2.0, 0.0, 1.0);
+    return vec4<f32>(in.color.rgb, in.color.a * falloff);
+}
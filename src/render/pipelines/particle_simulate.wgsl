@@ -0,0 +1,128 @@
+struct Emitter {
+    position: vec3<f32>,
+    shape: u32,
+    shape_params: vec4<f32>,
+    direction: vec3<f32>,
+    spawn_start: u32,
+    gravity: vec3<f32>,
+    spawn_count: u32,
+    color_start: vec4<f32>,
+    color_end: vec4<f32>,
+    camera_right: vec3<f32>,
+    size_start: f32,
+    camera_up: vec3<f32>,
+    size_end: f32,
+    lifetime: vec2<f32>,
+    speed: vec2<f32>,
+    dt: f32,
+    seed: u32,
+    capacity: u32,
+    _padding: u32,
+}
+@group(0) @binding(0)
+var<uniform> emitter: Emitter;
+
+struct Particle {
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+}
+@group(0) @binding(1)
+var<storage, read_write> particles: array<Particle>;
+
+const SHAPE_POINT: u32 = 0u;
+const SHAPE_SPHERE: u32 = 1u;
+const SHAPE_CONE: u32 = 2u;
+const SHAPE_BOX: u32 = 3u;
+const PI: f32 = 3.14159265;
+
+// PCG hash, good enough to decorrelate neighbouring particles
+fn hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+struct Rng {
+    state: u32,
+}
+
+fn random(rng: ptr<function, Rng>) -> f32 {
+    (*rng).state = hash((*rng).state);
+    return f32((*rng).state) / 4294967295.0;
+}
+
+fn random_unit_vector(rng: ptr<function, Rng>) -> vec3<f32> {
+    let z = random(rng) * 2.0 - 1.0;
+    let angle = random(rng) * 2.0 * PI;
+    let r = sqrt(max(1.0 - z * z, 0.0));
+    return vec3<f32>(r * cos(angle), r * sin(angle), z);
+}
+
+// Random direction at most `angle` radians away from `axis`
+fn random_cone_vector(rng: ptr<function, Rng>, axis: vec3<f32>, angle: f32) -> vec3<f32> {
+    let z = mix(cos(angle), 1.0, random(rng));
+    let phi = random(rng) * 2.0 * PI;
+    let r = sqrt(max(1.0 - z * z, 0.0));
+
+    var helper = vec3<f32>(1.0, 0.0, 0.0);
+    if abs(axis.x) > 0.9 {
+        helper = vec3<f32>(0.0, 1.0, 0.0);
+    }
+    let tangent = normalize(cross(axis, helper));
+    let bitangent = cross(axis, tangent);
+    return tangent * (r * cos(phi)) + bitangent * (r * sin(phi)) + axis * z;
+}
+
+fn spawn(index: u32) -> Particle {
+    var rng: Rng;
+    rng.state = hash(index ^ hash(emitter.seed));
+
+    var offset = vec3<f32>(0.0);
+    var direction = random_unit_vector(&rng);
+    // Not a switch, whose case selectors can only be literals in naga;
+    // SHAPE_POINT keeps the random direction and no offset
+    if emitter.shape == SHAPE_SPHERE {
+        // Uniformly distributed in the volume of the sphere
+        offset = direction * emitter.shape_params.x * pow(random(&rng), 1.0 / 3.0);
+    } else if emitter.shape == SHAPE_CONE {
+        direction = random_cone_vector(&rng, emitter.direction, emitter.shape_params.x);
+    } else if emitter.shape == SHAPE_BOX {
+        let unit = vec3<f32>(random(&rng), random(&rng), random(&rng)) * 2.0 - 1.0;
+        offset = unit * emitter.shape_params.xyz;
+        direction = emitter.direction;
+    }
+
+    var particle: Particle;
+    particle.position = emitter.position + offset;
+    particle.velocity = direction * mix(emitter.speed.x, emitter.speed.y, random(&rng));
+    particle.age = 0.0;
+    particle.lifetime = mix(emitter.lifetime.x, emitter.lifetime.y, random(&rng));
+    return particle;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= emitter.capacity {
+        return;
+    }
+
+    // New particles are written over the slots following the last spawned
+    // ones, like a ring buffer, replacing the oldest particles when full.
+    let slot = (index + emitter.capacity - emitter.spawn_start) % emitter.capacity;
+    if slot < emitter.spawn_count {
+        particles[index] = spawn(index);
+        return;
+    }
+
+    var particle = particles[index];
+    if particle.age >= particle.lifetime {
+        return;
+    }
+    particle.velocity += emitter.gravity * emitter.dt;
+    particle.position += particle.velocity * emitter.dt;
+    particle.age += emitter.dt;
+    particles[index] = particle;
+}
@@ -0,0 +1,179 @@
+// Material shading shared by model.wgsl, skinned.wgsl and morph.wgsl, which
+// deform the vertices their own way before handing them to `vertex`. Needs
+// fog.wgsl and cluster.wgsl.
+
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    fog: Fog,
+}
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+struct Light {
+    position: vec3<f32>,
+    color: vec3<f32>,
+}
+@group(2) @binding(0)
+var<uniform> light: Light;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+}
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+    @location(14) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) tangent_position: vec3<f32>,
+    @location(2) tangent_light_position: vec3<f32>,
+    @location(3) tangent_view_position: vec3<f32>,
+    @location(4) world_position: vec3<f32>,
+    @location(5) world_normal: vec3<f32>,
+    @location(6) world_tangent: vec3<f32>,
+    @location(7) world_bitangent: vec3<f32>,
+    @location(8) color: vec4<f32>,
+}
+
+// Places a vertex, already deformed in model space, in the world and in its
+// tangent frame
+fn vertex(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    // Tangents lie in the surface and follow it like positions do
+    let tangent_model_matrix = mat3x3<f32>(
+        instance.model_matrix_0.xyz,
+        instance.model_matrix_1.xyz,
+        instance.model_matrix_2.xyz,
+    );
+
+    // Construct the tangent matrix
+    let world_normal = normalize(normal_matrix * model.normal);
+    let world_tangent = normalize(tangent_model_matrix * model.tangent);
+    let world_bitangent = normalize(tangent_model_matrix * model.bitangent);
+    let tangent_matrix = transpose(mat3x3<f32>(
+        world_tangent,
+        world_bitangent,
+        world_normal,
+    ));
+
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.tex_coords = model.tex_coords;
+    out.color = instance.color;
+    out.tangent_position = tangent_matrix * world_position.xyz;
+    out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
+    out.tangent_light_position = tangent_matrix * light.position;
+    out.world_position = world_position.xyz;
+    out.world_normal = world_normal;
+    out.world_tangent = world_tangent;
+    out.world_bitangent = world_bitangent;
+    return out;
+}
+
+// Fragment shader
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+
+struct Material {
+    ambient: vec4<f32>,
+    // Diffuse colour, then the opacity
+    diffuse: vec4<f32>,
+    // Specular colour, then the shininess
+    specular: vec4<f32>,
+    // 0 for the colour alone, 1 without specular highlights, 2 and above
+    // with them
+    illumination: u32,
+    // Less opaque fragments are discarded
+    alpha_cutoff: f32,
+}
+@group(0) @binding(4)
+var<uniform> material: Material;
+
+// Colour of the specular highlights, none below illumination model 2
+fn specular_tint() -> vec3<f32> {
+    return material.specular.rgb * f32(material.illumination >= 2u);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords)
+        * material.diffuse * in.color;
+    let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
+    if object_color.a < material.alpha_cutoff {
+        discard;
+    }
+    if material.illumination == 0u {
+        return vec4<f32>(apply_fog(object_color.rgb, in.world_position), object_color.a);
+    }
+    
+    // We don't need (or want) much ambient light, so 0.1 is fine
+    let ambient_strength = 0.1;
+    let ambient_color = light.color * ambient_strength * material.ambient.rgb;
+
+    // Create the lighting vectors
+    let tangent_normal = object_normal.xyz * 2.0 - 1.0;
+    let light_dir = normalize(in.tangent_light_position - in.tangent_position);
+    let view_dir = normalize(in.tangent_view_position - in.tangent_position);
+    let half_dir = normalize(view_dir + light_dir);
+
+    let diffuse_strength = max(dot(tangent_normal, light_dir), 0.0);
+    let diffuse_color = light.color * diffuse_strength;
+
+    let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), material.specular.a);
+    let specular_color = specular_strength * light.color * specular_tint();
+
+    // Point lights are shaded in world space
+    let world_normal = normalize(mat3x3<f32>(
+        normalize(in.world_tangent),
+        normalize(in.world_bitangent),
+        normalize(in.world_normal),
+    ) * tangent_normal);
+    let point_color = point_lighting(
+        in.clip_position,
+        in.world_position,
+        world_normal,
+        object_color.xyz,
+        specular_tint(),
+        material.specular.a,
+    );
+
+    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz
+        + point_color;
+
+    return vec4<f32>(apply_fog(result, in.world_position), object_color.a);
+}
\ No newline at end of file
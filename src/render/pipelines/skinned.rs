@@ -0,0 +1,93 @@
+use crate::{
+    model::{self, Vertex},
+    render, texture, InstanceRaw,
+};
+
+use super::GlobalBindLayout;
+
+/// Joints a skinned mesh can be bound to, sized so that one palette fits
+/// in a WebGL uniform buffer
+pub const MAX_JOINTS: usize = 128;
+
+pub struct SkinnedPipeline {
+    joints_bind_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl SkinnedPipeline {
+    pub fn new(
+        global_bind_layout: &GlobalBindLayout,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        // The clusters, then the joints, each instance selecting its palette
+        // with a dynamic offset. Devices only guarantee four bind groups.
+        let [clusters, ranges, indices] = GlobalBindLayout::cluster_layout_entries();
+        let joints_bind_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    clusters,
+                    ranges,
+                    indices,
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: wgpu::BufferSize::new(
+                                (MAX_JOINTS * std::mem::size_of::<[[f32; 4]; 4]>()) as u64,
+                            ),
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("joints_bind_group_layout"),
+            });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skinned Pipeline Layout"),
+            bind_group_layouts: &[
+                global_bind_layout.get_texture_bind_layout(),
+                global_bind_layout.get_camera_bind_layout(),
+                global_bind_layout.get_light_bind_layout(),
+                &joints_bind_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Skinned Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("fog.wgsl"),
+                    include_str!("cluster.wgsl"),
+                    include_str!("shading.wgsl"),
+                    include_str!("skinned.wgsl")
+                )
+                .into(),
+            ),
+        };
+        let pipeline = render::create_render_pipeline(
+            device,
+            &layout,
+            config.format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[model::SkinnedVertex::desc(), InstanceRaw::desc()],
+            shader,
+        );
+
+        Self {
+            joints_bind_layout,
+            pipeline,
+        }
+    }
+
+    pub fn get_joints_bind_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.joints_bind_layout
+    }
+
+    pub fn get_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+}
@@ -0,0 +1,46 @@
+// Models with vertices deformed by a skeleton, shaded by shading.wgsl. The
+// joints share the last bind group with the clusters.
+
+struct SkinnedVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+    @location(12) joints: vec4<u32>,
+    @location(13) weights: vec4<f32>,
+}
+
+struct Joints {
+    // Skinning matrix of every joint, in model space
+    matrices: array<mat4x4<f32>, 128>,
+}
+@group(3) @binding(3)
+var<uniform> joints: Joints;
+
+@vertex
+fn vs_main(
+    model: SkinnedVertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // Blend the joints the vertex is bound to
+    let skin_matrix = joints.matrices[model.joints.x] * model.weights.x
+        + joints.matrices[model.joints.y] * model.weights.y
+        + joints.matrices[model.joints.z] * model.weights.z
+        + joints.matrices[model.joints.w] * model.weights.w;
+    // Joints are only expected to scale uniformly, so the upper 3x3 part
+    // also transforms normals
+    let skin_normal_matrix = mat3x3<f32>(
+        skin_matrix[0].xyz,
+        skin_matrix[1].xyz,
+        skin_matrix[2].xyz,
+    );
+
+    var skinned: VertexInput;
+    skinned.position = (skin_matrix * vec4<f32>(model.position, 1.0)).xyz;
+    skinned.tex_coords = model.tex_coords;
+    skinned.normal = skin_normal_matrix * model.normal;
+    skinned.tangent = skin_normal_matrix * model.tangent;
+    skinned.bitangent = skin_normal_matrix * model.bitangent;
+    return vertex(skinned, instance);
+}
@@ -0,0 +1,75 @@
+use crate::render::{self, PipelineOptions};
+use crate::texture;
+
+use super::GlobalBindLayout;
+
+pub struct SkyPipeline {
+    sky_bind_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl SkyPipeline {
+    pub fn new(
+        global_bind_layout: &GlobalBindLayout,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let sky_bind_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("sky_bind_group_layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sky Pipeline Layout"),
+            bind_group_layouts: &[
+                global_bind_layout.get_camera_bind_layout(),
+                &sky_bind_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Sky Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("sky.wgsl").into()),
+        };
+        // The sky sits on the far plane, it only shows where nothing else
+        // was drawn
+        let pipeline = render::create_render_pipeline_with_options(
+            device,
+            &layout,
+            config.format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[],
+            shader,
+            &PipelineOptions {
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                cull_mode: None,
+                ..Default::default()
+            },
+        );
+
+        Self {
+            sky_bind_layout,
+            pipeline,
+        }
+    }
+
+    /// Layout of the sky colours and the inverse view projection
+    pub fn get_sky_bind_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.sky_bind_layout
+    }
+
+    pub fn get_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+}
@@ -0,0 +1,55 @@
+// Analytic sky drawn behind everything else
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Sky {
+    inv_view_proj: mat4x4<f32>,
+    sun_direction: vec3<f32>,
+    sun_color: vec3<f32>,
+    zenith_color: vec3<f32>,
+    horizon_color: vec3<f32>,
+}
+@group(1) @binding(0)
+var<uniform> sky: Sky;
+
+// Vertex shader
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// A single triangle covering the whole screen on the far plane
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.ndc = uv * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(out.ndc, 1.0, 1.0);
+    return out;
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let world = sky.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let view_dir = normalize(world.xyz / world.w - camera.view_pos.xyz);
+
+    // Blue overhead, paler towards the horizon, darker below it
+    let height = view_dir.y;
+    var color = mix(sky.zenith_color, sky.horizon_color, pow(1.0 - max(height, 0.0), 4.0));
+    color *= 1.0 - 0.5 * clamp(-height * 4.0, 0.0, 1.0);
+
+    // Glow scattered around the sun, then the sun disk itself
+    let sun_amount = max(dot(view_dir, sky.sun_direction), 0.0);
+    color += sky.sun_color * (0.25 * pow(sun_amount, 8.0) + 0.5 * pow(sun_amount, 256.0));
+    color += sky.sun_color * smoothstep(0.9995, 0.9998, sun_amount);
+
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,56 @@
+use crate::{
+    model::Vertex,
+    render::{self, PipelineOptions},
+    sprite::SpriteInstance,
+    texture,
+};
+
+use super::GlobalBindLayout;
+
+pub struct SpritePipeline {
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl SpritePipeline {
+    pub fn new(
+        global_bind_layout: &GlobalBindLayout,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sprite Pipeline Layout"),
+            bind_group_layouts: &[
+                global_bind_layout.get_camera_bind_layout(),
+                global_bind_layout.get_simple_texture_bind_layout(),
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Sprite Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("sprite.wgsl").into()),
+        };
+        // Sprites are sorted on the CPU, so the depth buffer is left untouched
+        let pipeline = render::create_render_pipeline_with_options(
+            device,
+            &layout,
+            config.format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[SpriteInstance::desc()],
+            shader,
+            &PipelineOptions {
+                blend: wgpu::BlendState::ALPHA_BLENDING,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                cull_mode: None,
+                ..Default::default()
+            },
+        );
+
+        Self { pipeline }
+    }
+
+    pub fn get_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+}
@@ -0,0 +1,107 @@
+use crate::{
+    model::{self, Vertex},
+    render, texture,
+};
+
+use super::GlobalBindLayout;
+
+/// Number of material layers a terrain can blend
+pub const MAX_TERRAIN_LAYERS: usize = 4;
+
+pub struct TerrainPipeline {
+    layers_bind_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl TerrainPipeline {
+    pub fn new(
+        global_bind_layout: &GlobalBindLayout,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+
+        // Layer parameters, the splat map, then the diffuse and normal
+        // textures of every layer
+        let mut entries = vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            texture_entry(1),
+            sampler_entry(2),
+            sampler_entry(3),
+        ];
+        entries.extend((0..2 * MAX_TERRAIN_LAYERS as u32).map(|i| texture_entry(4 + i)));
+        let layers_bind_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &entries,
+                label: Some("terrain_layers_bind_group_layout"),
+            });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Terrain Pipeline Layout"),
+            bind_group_layouts: &[
+                global_bind_layout.get_camera_bind_layout(),
+                global_bind_layout.get_light_bind_layout(),
+                &layers_bind_layout,
+                global_bind_layout.get_cluster_bind_layout(),
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Terrain Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("fog.wgsl"),
+                    include_str!("cluster.wgsl"),
+                    include_str!("terrain.wgsl")
+                )
+                .into(),
+            ),
+        };
+        let pipeline = render::create_render_pipeline(
+            device,
+            &layout,
+            config.format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[model::ModelVertex::desc()],
+            shader,
+        );
+
+        Self {
+            layers_bind_layout,
+            pipeline,
+        }
+    }
+
+    pub fn get_layers_bind_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layers_bind_layout
+    }
+
+    pub fn get_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+}
@@ -0,0 +1,177 @@
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    fog: Fog,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Light {
+    position: vec3<f32>,
+    color: vec3<f32>,
+}
+@group(1) @binding(0)
+var<uniform> light: Light;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_tangent: vec3<f32>,
+    @location(4) world_bitangent: vec3<f32>,
+}
+
+// Terrain vertices are already in world space
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    out.world_position = model.position;
+    out.world_normal = model.normal;
+    out.world_tangent = model.tangent;
+    out.world_bitangent = model.bitangent;
+    return out;
+}
+
+// Fragment shader
+
+struct Layers {
+    // World units covered by one repetition of each layer's textures
+    scale: vec4<f32>,
+    count: u32,
+}
+@group(2) @binding(0)
+var<uniform> layers: Layers;
+@group(2) @binding(1)
+var t_splat: texture_2d<f32>;
+@group(2) @binding(2)
+var s_splat: sampler;
+@group(2) @binding(3)
+var s_layer: sampler;
+@group(2) @binding(4)
+var t_diffuse_0: texture_2d<f32>;
+@group(2) @binding(5)
+var t_diffuse_1: texture_2d<f32>;
+@group(2) @binding(6)
+var t_diffuse_2: texture_2d<f32>;
+@group(2) @binding(7)
+var t_diffuse_3: texture_2d<f32>;
+@group(2) @binding(8)
+var t_normal_0: texture_2d<f32>;
+@group(2) @binding(9)
+var t_normal_1: texture_2d<f32>;
+@group(2) @binding(10)
+var t_normal_2: texture_2d<f32>;
+@group(2) @binding(11)
+var t_normal_3: texture_2d<f32>;
+
+struct Surface {
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+}
+
+// Blends the layers following the splat map
+fn blend_layers(in: VertexOutput) -> Surface {
+    // Each channel of the splat map weights one layer
+    var weights = textureSample(t_splat, s_splat, in.tex_coords);
+    let mask = vec4<f32>(
+        f32(layers.count > 0u),
+        f32(layers.count > 1u),
+        f32(layers.count > 2u),
+        f32(layers.count > 3u),
+    );
+    weights *= mask;
+    let total = dot(weights, vec4<f32>(1.0));
+    if total < 0.0001 {
+        weights = vec4<f32>(1.0, 0.0, 0.0, 0.0);
+    } else {
+        weights /= total;
+    }
+
+    let uv0 = in.world_position.xz / layers.scale.x;
+    let uv1 = in.world_position.xz / layers.scale.y;
+    let uv2 = in.world_position.xz / layers.scale.z;
+    let uv3 = in.world_position.xz / layers.scale.w;
+    let object_color = textureSample(t_diffuse_0, s_layer, uv0) * weights.x
+        + textureSample(t_diffuse_1, s_layer, uv1) * weights.y
+        + textureSample(t_diffuse_2, s_layer, uv2) * weights.z
+        + textureSample(t_diffuse_3, s_layer, uv3) * weights.w;
+    let object_normal = textureSample(t_normal_0, s_layer, uv0) * weights.x
+        + textureSample(t_normal_1, s_layer, uv1) * weights.y
+        + textureSample(t_normal_2, s_layer, uv2) * weights.z
+        + textureSample(t_normal_3, s_layer, uv3) * weights.w;
+
+    let tangent_matrix = mat3x3<f32>(
+        normalize(in.world_tangent),
+        normalize(in.world_bitangent),
+        normalize(in.world_normal),
+    );
+
+    var out: Surface;
+    out.albedo = object_color.rgb;
+    out.normal = normalize(tangent_matrix * (object_normal.xyz * 2.0 - 1.0));
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let surface = blend_layers(in);
+    let normal = surface.normal;
+
+    // We don't need (or want) much ambient light, so 0.1 is fine
+    let ambient_strength = 0.1;
+    let ambient_color = light.color * ambient_strength;
+
+    let light_dir = normalize(light.position - in.world_position);
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    let half_dir = normalize(view_dir + light_dir);
+
+    let diffuse_strength = max(dot(normal, light_dir), 0.0);
+    let diffuse_color = light.color * diffuse_strength;
+
+    let specular_strength = pow(max(dot(normal, half_dir), 0.0), 32.0);
+    let specular_color = specular_strength * light.color;
+
+    let point_color = point_lighting(
+        in.clip_position,
+        in.world_position,
+        normal,
+        surface.albedo,
+        vec3<f32>(1.0),
+        32.0,
+    );
+
+    let result = (ambient_color + diffuse_color + specular_color) * surface.albedo + point_color;
+
+    return vec4<f32>(apply_fog(result, in.world_position), 1.0);
+}
+
+struct GBufferOutput {
+    @location(0) albedo: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+    @location(2) material: vec4<f32>,
+}
+
+// Deferred shading variant writing the surface to the G-buffer
+@fragment
+fn fs_gbuffer(in: VertexOutput) -> GBufferOutput {
+    let surface = blend_layers(in);
+
+    var out: GBufferOutput;
+    out.albedo = vec4<f32>(surface.albedo, 1.0);
+    out.normal = vec4<f32>(surface.normal, 0.0);
+    out.material = vec4<f32>(1.0, 32.0 / 256.0, 0.0, 0.0);
+    return out;
+}
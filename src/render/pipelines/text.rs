@@ -0,0 +1,73 @@
+use crate::{
+    model::Vertex,
+    render::{self, PipelineOptions},
+    text::TextVertex,
+    texture,
+};
+
+use super::GlobalBindLayout;
+
+pub struct TextPipeline {
+    screen: wgpu::RenderPipeline,
+    world: wgpu::RenderPipeline,
+}
+
+impl TextPipeline {
+    pub fn new(
+        global_bind_layout: &GlobalBindLayout,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Text Pipeline Layout"),
+            bind_group_layouts: &[
+                global_bind_layout.get_camera_bind_layout(),
+                global_bind_layout.get_simple_texture_bind_layout(),
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let create_pipeline = |options: &PipelineOptions| {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Text Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("text.wgsl").into()),
+            };
+            render::create_render_pipeline_with_options(
+                device,
+                &layout,
+                config.format,
+                Some(texture::Texture::DEPTH_FORMAT),
+                &[TextVertex::desc()],
+                shader,
+                options,
+            )
+        };
+
+        // Screen space text is drawn on top of everything, world space text
+        // is hidden behind geometry but never occludes anything itself.
+        let screen = create_pipeline(&PipelineOptions {
+            blend: wgpu::BlendState::ALPHA_BLENDING,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            cull_mode: None,
+            ..Default::default()
+        });
+        let world = create_pipeline(&PipelineOptions {
+            blend: wgpu::BlendState::ALPHA_BLENDING,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Less,
+            cull_mode: None,
+            ..Default::default()
+        });
+
+        Self { screen, world }
+    }
+
+    pub fn get_screen_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.screen
+    }
+
+    pub fn get_world_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.world
+    }
+}
@@ -1,3 +1,31 @@
+use crate::{deferred::GBuffer, texture};
+
+/// Fixed-function state that differs between pipelines. The defaults match
+/// the opaque, depth tested and back-face culled pipelines used for models.
+pub struct PipelineOptions {
+    pub blend: wgpu::BlendState,
+    pub depth_write_enabled: bool,
+    pub depth_compare: wgpu::CompareFunction,
+    pub cull_mode: Option<wgpu::Face>,
+    /// Vertex shader decoding the vertex buffer layouts of the pipeline
+    pub vertex_entry_point: &'static str,
+}
+
+impl Default for PipelineOptions {
+    fn default() -> Self {
+        Self {
+            blend: wgpu::BlendState {
+                alpha: wgpu::BlendComponent::REPLACE,
+                color: wgpu::BlendComponent::REPLACE,
+            },
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            cull_mode: Some(wgpu::Face::Back),
+            vertex_entry_point: "vs_main",
+        }
+    }
+}
+
 pub fn create_render_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
@@ -5,6 +33,26 @@ pub fn create_render_pipeline(
     depth_format: Option<wgpu::TextureFormat>,
     vertex_layouts: &[wgpu::VertexBufferLayout],
     shader: wgpu::ShaderModuleDescriptor,
+) -> wgpu::RenderPipeline {
+    create_render_pipeline_with_options(
+        device,
+        layout,
+        color_format,
+        depth_format,
+        vertex_layouts,
+        shader,
+        &PipelineOptions::default(),
+    )
+}
+
+pub fn create_render_pipeline_with_options(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_format: Option<wgpu::TextureFormat>,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: wgpu::ShaderModuleDescriptor,
+    options: &PipelineOptions,
 ) -> wgpu::RenderPipeline {
     let shader = device.create_shader_module(shader);
 
@@ -13,7 +61,7 @@ pub fn create_render_pipeline(
         layout: Some(layout),
         vertex: wgpu::VertexState {
             module: &shader,
-            entry_point: "vs_main",
+            entry_point: options.vertex_entry_point,
             buffers: vertex_layouts,
         },
         fragment: Some(wgpu::FragmentState {
@@ -21,10 +69,7 @@ pub fn create_render_pipeline(
             entry_point: "fs_main",
             targets: &[Some(wgpu::ColorTargetState {
                 format: color_format,
-                blend: Some(wgpu::BlendState {
-                    alpha: wgpu::BlendComponent::REPLACE,
-                    color: wgpu::BlendComponent::REPLACE,
-                }),
+                blend: Some(options.blend),
                 write_mask: wgpu::ColorWrites::ALL,
             })],
         }),
@@ -32,13 +77,70 @@ pub fn create_render_pipeline(
             topology: wgpu::PrimitiveTopology::TriangleList,
             strip_index_format: None,
             front_face: wgpu::FrontFace::Ccw,
-            cull_mode: Some(wgpu::Face::Back),
+            cull_mode: options.cull_mode,
             polygon_mode: wgpu::PolygonMode::Fill,
             unclipped_depth: false,
             conservative: false,
         },
         depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
             format,
+            depth_write_enabled: options.depth_write_enabled,
+            depth_compare: options.depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+/// Opaque pipeline writing a surface to every target of the G-buffer, for
+/// the deferred renderer
+pub fn create_gbuffer_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: wgpu::ShaderModuleDescriptor,
+    vertex_entry_point: &str,
+    fragment_entry_point: &str,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(shader);
+    let targets = GBuffer::FORMATS.map(|format| {
+        Some(wgpu::ColorTargetState {
+            format,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        })
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("{:?}", shader)),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: vertex_entry_point,
+            buffers: vertex_layouts,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: fragment_entry_point,
+            targets: &targets,
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
             depth_write_enabled: true,
             depth_compare: wgpu::CompareFunction::Less,
             stencil: wgpu::StencilState::default(),
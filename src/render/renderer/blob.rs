@@ -0,0 +1,111 @@
+//! Procedural model showing off morph targets until models carrying them can
+//! be imported
+
+use std::f32::consts::{PI, TAU};
+
+use cgmath::{ElementWise, InnerSpace, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::{assets::Handle, model, morph};
+
+const RADIUS: f32 = 1.5;
+const RINGS: usize = 24;
+const SIDES: usize = 32;
+
+/// Sphere with a "squash" target flattening it and a "spikes" target pushing
+/// bumps out of its surface
+pub fn create_blob(
+    device: &wgpu::Device,
+    material: model::Material,
+) -> anyhow::Result<model::Model> {
+    let mut vertices = Vec::with_capacity((RINGS + 1) * (SIDES + 1));
+    let mut squash = Vec::with_capacity(vertices.capacity());
+    let mut squash_normals = Vec::with_capacity(vertices.capacity());
+    let mut spikes = Vec::with_capacity(vertices.capacity());
+    for ring in 0..=RINGS {
+        let v = ring as f32 / RINGS as f32;
+        let (ring_sin, ring_cos) = (v * PI).sin_cos();
+        for side in 0..=SIDES {
+            let u = side as f32 / SIDES as f32;
+            let (sin, cos) = (u * TAU).sin_cos();
+            let normal = Vector3::new(ring_sin * cos, ring_cos, ring_sin * sin);
+            let position = normal * RADIUS;
+            vertices.push(model::ModelVertex {
+                position: position.into(),
+                tex_coords: [u * 4.0, v * 2.0],
+                normal: normal.into(),
+                tangent: [-sin, 0.0, cos],
+                bitangent: normal.cross(Vector3::new(-sin, 0.0, cos)).into(),
+            });
+
+            // Flattened into an ellipsoid, whose normals lean upwards
+            let scale = Vector3::new(1.3, 0.5, 1.3);
+            squash.push(position.mul_element_wise(scale) - position);
+            let squashed_normal = normal.div_element_wise(scale).normalize();
+            squash_normals.push(squashed_normal - normal);
+
+            // Position only, shading stays smooth under the bumps
+            let bump = ((u * TAU * 6.0).sin() * (v * PI * 6.0).sin()).max(0.0);
+            spikes.push(normal * bump * 0.6);
+        }
+    }
+
+    let row = (SIDES + 1) as u32;
+    let mut indices = Vec::with_capacity(RINGS * SIDES * 6);
+    for ring in 0..RINGS as u32 {
+        for side in 0..SIDES as u32 {
+            let a = ring * row + side;
+            let b = a + 1;
+            let c = a + row;
+            let d = c + 1;
+            indices.extend([a, b, c, b, d, c]);
+        }
+    }
+
+    let morph_targets = morph::MorphTargets::new(
+        device,
+        vertices.len(),
+        &[
+            morph::MorphTarget {
+                name: "squash".to_string(),
+                positions: squash,
+                normals: squash_normals,
+                tangents: Vec::new(),
+            },
+            morph::MorphTarget {
+                name: "spikes".to_string(),
+                positions: spikes,
+                normals: Vec::new(),
+                tangents: Vec::new(),
+            },
+        ],
+    )?;
+
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Blob Vertex Buffer"),
+        contents: bytemuck::cast_slice(&vertices),
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Blob Index Buffer"),
+        contents: bytemuck::cast_slice(&indices),
+        usage: wgpu::BufferUsages::INDEX,
+    });
+
+    Ok(model::Model {
+        meshes: vec![model::Mesh {
+            name: "blob".to_string(),
+            vertex_buffer,
+            index_buffer,
+            index_format: wgpu::IndexFormat::Uint32,
+            num_elements: indices.len() as u32,
+            material: 0,
+            morph_targets: Some(morph_targets),
+        }],
+        materials: vec![Handle::new(material)],
+        skeleton: None,
+        animations: Vec::new(),
+        vertex_format: model::VertexFormat::Full,
+        nodes: Vec::new(),
+    })
+}
@@ -6,39 +6,103 @@ use ::render::graphics_renderer::GraphicsRenderer;
 use wgpu::{util::DeviceExt, Queue};
 use winit::event::{DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, WindowEvent};
 
+use super::{blob, tentacle};
 use crate::{
-    camera,
+    animation, assets, atmosphere, camera, cluster, deferred,
     model::{self, DrawLight, DrawModel},
-    render, resources, texture, CameraUniform, Instance, LightUniform, NUM_INSTANCES_PER_ROW,
+    morph,
+    instance_set, particle, render, resources, sprite, terrain, text, texture, CameraUniform, Instance, LightUniform,
+    NUM_INSTANCES_PER_ROW,
 };
+#[cfg(not(target_arch = "wasm32"))]
+use crate::hot_reload;
 
 pub struct DefaultState {
-    obj_model: model::Model,
+    global_bind_layout: render::GlobalBindLayout,
+    assets: assets::AssetServer,
+    /// Set when hot reload is on
+    #[cfg(not(target_arch = "wasm32"))]
+    asset_watcher: Option<hot_reload::AssetWatcher>,
+    obj_model: assets::Streamed<model::Model>,
     camera: camera::Camera,
     projection: camera::Projection,
     camera_controller: camera::CameraController,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
-    instances: Vec<Instance>,
-    #[allow(dead_code)]
-    instance_buffer: wgpu::Buffer,
+    instances: instance_set::InstanceSet,
+    instance_buffer: instance_set::InstanceBuffer,
+    /// Cubes added from the UI, most recent last
+    spawned_cubes: Vec<instance_set::InstanceId>,
+    /// First row of cubes, bobbing up and down when `wave_cubes` is set
+    first_row: Vec<instance_set::InstanceId>,
+    wave_cubes: bool,
+    wave_time: f32,
     depth_texture: texture::Texture,
     light_uniform: LightUniform,
     light_buffer: wgpu::Buffer,
     light_bind_group: wgpu::BindGroup,
-    #[allow(dead_code)]
     debug_material: model::Material,
+    use_debug_material: bool,
+    /// Degrees per second the light orbits around the vertical axis
+    light_orbit_speed: f32,
+    clear_color: [f32; 3],
     mouse_pressed: bool,
     pipelines: render::Pipelines,
+    text_renderer: text::TextRenderer,
+    font: text::FontId,
+    screen_size: winit::dpi::PhysicalSize<u32>,
+    frame_time: f32,
+    sprite_batch: sprite::SpriteBatch,
+    hud_camera: camera::OrthographicCamera,
+    hud_texture: sprite::SpriteTextureId,
+    elapsed: f32,
+    particle_emitters: Vec<particle::ParticleEmitter>,
+    terrain: terrain::Terrain,
+    /// Only set on the deferred render path
+    deferred: Option<deferred::DeferredRenderer>,
+    fog: atmosphere::Fog,
+    sky: atmosphere::Sky,
+    use_sky: bool,
+    sky_renderer: atmosphere::SkyRenderer,
+    tentacle_model: model::Model,
+    tentacle_buffer: wgpu::Buffer,
+    joint_palettes: animation::JointPalettes,
+    tentacle_controllers: Vec<animation::AnimationController>,
+    tentacle_activity: f32,
+    curl_weight: f32,
+    stretch_requested: bool,
+    last_tentacle_event: Option<String>,
+    animation_speed: f32,
+    /// Blob model and the morph weights of its instances, missing when
+    /// morph targets are not supported
+    blobs: Option<(model::Model, morph::MorphWeights)>,
+    blob_buffer: wgpu::Buffer,
+    /// Weights of the first blob, the others animate on their own
+    blob_expression: [f32; 2],
+    morph_time: f32,
+    /// Point lights of the forward render path
+    clustered_lights: cluster::ClusteredLights,
+    point_lights: Vec<deferred::PointLight>,
+    /// Number of point lights in use, from the start of `point_lights`
+    point_light_count: usize,
 }
 
 impl DefaultState {
-    pub async fn new(renderer: &GraphicsRenderer) -> Self
+    pub async fn new(
+        renderer: &GraphicsRenderer,
+        render_path: render::RenderPath,
+        load_options: resources::LoadOptions,
+        hot_reload: bool,
+    ) -> Self
     {
         let global_bind_layout = render::GlobalBindLayout::new(&renderer.device);
-        let pipelines =
-            render::Pipelines::new(&global_bind_layout, &renderer.device, &renderer.config);
+        let pipelines = render::Pipelines::new(
+            &global_bind_layout,
+            &renderer.device,
+            &renderer.config,
+            render_path,
+        );
 
         let camera = camera::Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
         let projection = camera::Projection::new(
@@ -73,11 +137,15 @@ impl DefaultState {
                 }
             }
         };
-        let instances = iter
+        let grid = iter
             .clone()
             .flat_map(|z| {
                 // UPDATED!
                 iter.clone().map(move |x| {
+                    // Cubes grow taller along the rows and shade from warm
+                    // to cool across them
+                    let height = 0.6 + x as f32 * 0.1;
+                    let warmth = z as f32 / (NUM_INSTANCES_PER_ROW - 1) as f32;
                     let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
                     let z = SPACE_BETWEEN * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
 
@@ -92,18 +160,24 @@ impl DefaultState {
                         cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
                     };
 
-                    Instance { position, rotation }
+                    Instance {
+                        scale: cgmath::Vector3::new(1.0, height, 1.0),
+                        color: [1.0, 0.6 + 0.4 * warmth, 1.0 - 0.4 * warmth, 1.0],
+                        ..Instance::new(position, rotation)
+                    }
                 })
             })
             .collect::<Vec<_>>();
 
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer =
-            (&renderer.device).create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Buffer"),
-                contents: bytemuck::cast_slice(&instance_data),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
+        let mut instances = instance_set::InstanceSet::default();
+        let cube_ids = grid
+            .into_iter()
+            .map(|instance| instances.add(instance))
+            .collect::<Vec<_>>();
+        let first_row = cube_ids[..NUM_INSTANCES_PER_ROW as usize].to_vec();
+        let mut instance_buffer =
+            instance_set::InstanceBuffer::new(&renderer.device, "Instance Buffer");
+        instance_buffer.prepare(&renderer.device, &renderer.queue, &mut instances);
 
         let camera_bind_group = renderer
             .device
@@ -116,12 +190,47 @@ impl DefaultState {
                 label: Some("camera_bind_group"),
             });
 
-        let obj_model = resources::load_model(
-            "cube.obj",
+        let mut assets = assets::AssetServer::new(
             &renderer.device,
             &renderer.queue,
             global_bind_layout.get_texture_bind_layout(),
-        ).await.unwrap();
+            load_options,
+            // Sources edited with hot reload or while developing make the
+            // cooked models stale
+            hot_reload || cfg!(debug_assertions),
+        )
+        .unwrap();
+        #[cfg(not(target_arch = "wasm32"))]
+        let asset_watcher = if hot_reload {
+            match hot_reload::AssetWatcher::new() {
+                Ok(watcher) => Some(watcher),
+                Err(e) => {
+                    log::error!("Hot reload is disabled: {:#}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        #[cfg(target_arch = "wasm32")]
+        if hot_reload {
+            log::warn!("Hot reload isn't available on the web");
+        }
+        // The cubes show as checkered boxes until the model is streamed in
+        let mut obj_model = assets.stream_model("cube.obj");
+        let requested = instant::Instant::now();
+        obj_model.on_loaded(move |model| {
+            log::info!("cube.obj {} after {:?}", model.state(), requested.elapsed());
+        });
+        // Shared with the maps of the cube's material
+        let cube_diffuse = assets
+            .load_texture("cube-diffuse.jpg", false, &renderer.device, &renderer.queue)
+            .await
+            .unwrap();
+        let cube_normal = assets
+            .load_texture("cube-normal.png", true, &renderer.device, &renderer.queue)
+            .await
+            .unwrap();
 
         let light_uniform = LightUniform {
             position: [2.0, 2.0, 2.0],
@@ -179,13 +288,253 @@ impl DefaultState {
             model::Material::new(
                 &renderer.device,
                 "alt-material",
-                diffuse_texture,
-                normal_texture,
+                assets::Handle::new(diffuse_texture),
+                assets::Handle::new(normal_texture),
+                model::MaterialParams::default(),
                 global_bind_layout.get_texture_bind_layout(),
             )
         };
 
+        // Skinned tentacles swaying behind the cubes
+        let tentacle_model = {
+            let material = model::Material::new(
+                &renderer.device,
+                "tentacle-material",
+                cube_diffuse.clone(),
+                cube_normal.clone(),
+                model::MaterialParams::default(),
+                global_bind_layout.get_texture_bind_layout(),
+            );
+            tentacle::create_tentacle(&renderer.device, material).unwrap()
+        };
+        let tentacles = (0..5)
+            .map(|i| {
+                Instance::new(
+                    cgmath::Vector3::new(i as f32 * 6.0 - 12.0, 0.0, -20.0),
+                    cgmath::Quaternion::from_angle_y(cgmath::Deg(i as f32 * 40.0)),
+                )
+            })
+            .collect::<Vec<_>>();
+        let tentacle_buffer = renderer
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Tentacle Instance Buffer"),
+                contents: bytemuck::cast_slice(
+                    &tentacles.iter().map(Instance::to_raw).collect::<Vec<_>>(),
+                ),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+        // Each tentacle starts a little behind the previous one
+        let tentacle_controllers = (0..tentacles.len())
+            .map(|i| {
+                let mut controller = tentacle::create_tentacle_controller().unwrap();
+                controller.update(i as f32 * 0.3, &tentacle_model.animations);
+                controller
+            })
+            .collect::<Vec<_>>();
+
+        // Blobs morphing between shapes next to the tentacles
+        let blob_instances = (0..3)
+            .map(|i| {
+                let mut color = [0.5, 0.5, 0.5, 1.0];
+                color[i] = 1.0;
+                Instance {
+                    color,
+                    ..Instance::new(
+                        cgmath::Vector3::new(i as f32 * 6.0 - 6.0, 2.0, -28.0),
+                        cgmath::Quaternion::one(),
+                    )
+                }
+            })
+            .collect::<Vec<_>>();
+        let blob_buffer = renderer
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Blob Instance Buffer"),
+                contents: bytemuck::cast_slice(
+                    &blob_instances.iter().map(Instance::to_raw).collect::<Vec<_>>(),
+                ),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+        // Also bound along with the morph targets of the blobs and the joints
+        // of the tentacles
+        let clustered_lights = cluster::ClusteredLights::new(&renderer.device, &global_bind_layout);
+        let blobs = match pipelines.get_morph_pipeline() {
+            Some(morph_pipeline) => {
+                let material = model::Material::new(
+                    &renderer.device,
+                    "blob-material",
+                    cube_diffuse.clone(),
+                    cube_normal.clone(),
+                    model::MaterialParams::default(),
+                    global_bind_layout.get_texture_bind_layout(),
+                );
+                let model = blob::create_blob(&renderer.device, material).unwrap();
+                let weights = model.meshes[0].morph_targets.as_ref().map(|targets| {
+                    morph::MorphWeights::new(
+                        &renderer.device,
+                        morph_pipeline,
+                        targets,
+                        blob_instances.len(),
+                        &clustered_lights,
+                    )
+                });
+                weights.map(|weights| (model, weights))
+            }
+            None => {
+                log::warn!("Vertex storage buffers are not supported, morph targets are disabled");
+                None
+            }
+        };
+
+        // Rolling hills below the cubes, cobbles on the heights
+        let terrain = {
+            let heightmap = resources::load_heightmap("terrain-height.png")
+                .await
+                .unwrap();
+            let splat_map = resources::load_texture(
+                "terrain-splat.png",
+                true,
+                &renderer.device,
+                &renderer.queue,
+            )
+            .await
+            .unwrap();
+            // The cube's maps, which the terrain doesn't wait for
+            let terrain_material = model::Material::new(
+                &renderer.device,
+                "terrain-material",
+                cube_diffuse.clone(),
+                cube_normal,
+                model::MaterialParams::default(),
+                global_bind_layout.get_texture_bind_layout(),
+            );
+            terrain::Terrain::new(
+                &renderer.device,
+                &pipelines,
+                &heightmap,
+                &splat_map,
+                &[
+                    terrain::TerrainLayer {
+                        material: &terrain_material,
+                        scale: 4.0,
+                    },
+                    terrain::TerrainLayer {
+                        material: &debug_material,
+                        scale: 2.0,
+                    },
+                ],
+                terrain::TerrainConfig {
+                    origin: (-64.0, -8.0, -64.0).into(),
+                    spacing: 1.0,
+                    height_scale: 12.0,
+                    chunk_size: 32,
+                    lod_distance: 24.0,
+                },
+            )
+            .unwrap()
+        };
+
+        let mut text_renderer = text::TextRenderer::new(&renderer.device, &global_bind_layout);
+        let font = text_renderer
+            .add_font(resources::load_binary("DejaVuSans.ttf").await.unwrap())
+            .unwrap();
+
+        let mut sprite_batch = sprite::SpriteBatch::new(&renderer.device, &global_bind_layout);
+        let hud_texture = sprite_batch.add_texture(
+            &renderer.device,
+            &global_bind_layout,
+            cube_diffuse,
+        );
+        let mut hud_camera =
+            camera::OrthographicCamera::new(renderer.size.width, renderer.size.height);
+        hud_camera.position = Self::hud_origin(renderer.size);
+
+        // A small fire with its smoke on top of the center cube
+        let particle_emitters = match pipelines.get_particle_pipelines() {
+            Some(particle_pipelines) => vec![
+                particle::ParticleEmitter::new(
+                    &renderer.device,
+                    particle_pipelines,
+                    particle::EmitterConfig {
+                        position: (0.0, 1.5, 0.0).into(),
+                        shape: particle::EmitterShape::Cone { angle: 0.3 },
+                        rate: 300.0,
+                        lifetime: [0.4, 0.8],
+                        speed: [1.0, 2.0],
+                        gravity: (0.0, 1.0, 0.0).into(),
+                        color_start: [1.0, 0.6, 0.1, 1.0],
+                        color_end: [0.8, 0.1, 0.0, 0.0],
+                        size_start: 0.3,
+                        size_end: 0.05,
+                        blend: particle::ParticleBlend::Additive,
+                        ..Default::default()
+                    },
+                    512,
+                ),
+                particle::ParticleEmitter::new(
+                    &renderer.device,
+                    particle_pipelines,
+                    particle::EmitterConfig {
+                        position: (0.0, 2.5, 0.0).into(),
+                        shape: particle::EmitterShape::Sphere { radius: 0.2 },
+                        rate: 20.0,
+                        lifetime: [2.0, 3.0],
+                        speed: [0.1, 0.3],
+                        gravity: (0.2, 0.4, 0.0).into(),
+                        color_start: [0.3, 0.3, 0.3, 0.5],
+                        color_end: [0.5, 0.5, 0.5, 0.0],
+                        size_start: 0.3,
+                        size_end: 1.0,
+                        blend: particle::ParticleBlend::Alpha,
+                        ..Default::default()
+                    },
+                    128,
+                ),
+            ],
+            None => {
+                log::warn!("Compute shaders are not supported, particles are disabled");
+                Vec::new()
+            }
+        };
+
+        let deferred = pipelines.get_deferred_pipelines().map(|deferred_pipelines| {
+            deferred::DeferredRenderer::new(
+                &renderer.device,
+                &renderer.config,
+                deferred_pipelines,
+                &depth_texture,
+            )
+        });
+
+        let sky_renderer = atmosphere::SkyRenderer::new(&renderer.device, &pipelines);
+        let joint_palettes =
+            animation::JointPalettes::new(&renderer.device, &pipelines, &clustered_lights);
+
+        // Small coloured lights scattered over the cubes
+        let point_lights = (0..256)
+            .map(|i| {
+                let angle = i as f32 * 2.399;
+                let distance = 2.0 + (i as f32).sqrt() * 1.1;
+                let hue = i as f32 * 0.618;
+                deferred::PointLight {
+                    position: [angle.cos() * distance, 1.0, angle.sin() * distance],
+                    radius: 3.0,
+                    color: [
+                        0.5 + 0.5 * (hue * std::f32::consts::TAU).cos(),
+                        0.5 + 0.5 * ((hue + 1.0 / 3.0) * std::f32::consts::TAU).cos(),
+                        0.5 + 0.5 * ((hue + 2.0 / 3.0) * std::f32::consts::TAU).cos(),
+                    ],
+                    intensity: 2.0,
+                }
+            })
+            .collect();
+
         Self {
+            global_bind_layout,
+            assets,
+            #[cfg(not(target_arch = "wasm32"))]
+            asset_watcher,
             obj_model,
             camera,
             projection,
@@ -195,16 +544,94 @@ impl DefaultState {
             camera_uniform,
             instances,
             instance_buffer,
+            spawned_cubes: Vec::new(),
+            first_row,
+            wave_cubes: false,
+            wave_time: 0.0,
             depth_texture,
             light_uniform,
             light_buffer,
             light_bind_group,
-            #[allow(dead_code)]
             debug_material,
+            use_debug_material: false,
+            light_orbit_speed: 60.0,
+            clear_color: [0.1, 0.2, 0.3],
             mouse_pressed: false,
             pipelines,
+            text_renderer,
+            font,
+            screen_size: renderer.size,
+            frame_time: 0.0,
+            sprite_batch,
+            hud_camera,
+            hud_texture,
+            elapsed: 0.0,
+            particle_emitters,
+            terrain,
+            deferred,
+            // Fully fogged at the far plane, hiding where the scene ends
+            fog: atmosphere::Fog {
+                mode: atmosphere::FogMode::Linear {
+                    start: 40.0,
+                    end: 100.0,
+                },
+                ..Default::default()
+            },
+            sky: Default::default(),
+            use_sky: true,
+            sky_renderer,
+            tentacle_model,
+            tentacle_buffer,
+            joint_palettes,
+            tentacle_controllers,
+            tentacle_activity: 0.5,
+            curl_weight: 0.0,
+            stretch_requested: false,
+            last_tentacle_event: None,
+            animation_speed: 1.0,
+            blobs,
+            blob_buffer,
+            blob_expression: [0.0, 0.0],
+            morph_time: 0.0,
+            clustered_lights,
+            point_lights,
+            point_light_count: 64,
         }
     }
+
+    /// Draws the instanced cubes with the pipeline already set on the pass,
+    /// those whose material is blended when `blended` is set and the other
+    /// ones otherwise
+    fn draw_models<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, blended: bool) {
+        if self.instances.is_empty() {
+            return;
+        }
+        if self.use_debug_material {
+            // The debug material is opaque
+            if !blended {
+                render_pass.draw_model_instanced_with_material(
+                    &self.obj_model,
+                    &self.debug_material,
+                    0..self.instance_buffer.count(),
+                    &self.camera_bind_group,
+                    &self.light_bind_group,
+                );
+            }
+        } else {
+            render_pass.draw_model_instanced_blended(
+                &self.obj_model,
+                blended,
+                0..self.instance_buffer.count(),
+                &self.camera_bind_group,
+                &self.light_bind_group,
+            );
+        }
+    }
+
+    /// Centers the HUD camera so that (0, 0) is the bottom-left corner of the window
+    fn hud_origin(size: winit::dpi::PhysicalSize<u32>) -> cgmath::Point2<f32> {
+        cgmath::Point2::new(size.width as f32 * 0.5, size.height as f32 * 0.5)
+    }
 }
 
 impl super::State for DefaultState {
@@ -214,9 +641,18 @@ impl super::State for DefaultState {
         config: &wgpu::SurfaceConfiguration,
         new_size: winit::dpi::PhysicalSize<u32>,
     ) {
+        self.screen_size = new_size;
+        self.hud_camera.resize(new_size.width, new_size.height);
+        self.hud_camera.position = Self::hud_origin(new_size);
         self.projection.resize(new_size.width, new_size.height);
         self.depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+            texture::Texture::create_depth_texture(device, config, "depth_texture");
+        if let (Some(deferred), Some(deferred_pipelines)) = (
+            self.deferred.as_mut(),
+            self.pipelines.get_deferred_pipelines(),
+        ) {
+            deferred.resize(device, config, deferred_pipelines, &self.depth_texture);
+        }
     }
 
     fn input(&mut self, event: &Event<()>) -> bool {
@@ -258,10 +694,22 @@ impl super::State for DefaultState {
         }
     }
 
-    fn update(&mut self, queue: &Queue, dt: instant::Duration) {
+    fn update(&mut self, device: &wgpu::Device, queue: &Queue, dt: instant::Duration) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(watcher) = &mut self.asset_watcher {
+            self.assets.reload(&watcher.changed_files());
+        }
+        self.assets.poll(
+            device,
+            queue,
+            self.global_bind_layout.get_texture_bind_layout(),
+        );
+        self.obj_model.update();
+
         self.camera_controller.update_camera(&mut self.camera, dt);
         self.camera_uniform
             .update_view_proj(&self.camera, &self.projection);
+        self.camera_uniform.fog = self.fog.uniform(self.use_sky.then_some(&self.sky));
         queue.write_buffer(
             &self.camera_buffer,
             0,
@@ -270,15 +718,441 @@ impl super::State for DefaultState {
 
         // Update the light
         let old_position: cgmath::Vector3<_> = self.light_uniform.position.into();
-        self.light_uniform.position =
-            (cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(1.0))
-                * old_position)
-                .into();
+        self.light_uniform.position = (cgmath::Quaternion::from_axis_angle(
+            (0.0, 1.0, 0.0).into(),
+            cgmath::Deg(self.light_orbit_speed * dt.as_secs_f32()),
+        ) * old_position)
+            .into();
         queue.write_buffer(
             &self.light_buffer,
             0,
             bytemuck::cast_slice(&[self.light_uniform]),
         );
+
+        // A spinning badge in the corner, with the light colour as a second layer
+        self.elapsed += dt.as_secs_f32();
+        self.sprite_batch.draw(sprite::Sprite {
+            rotation: self.elapsed,
+            ..sprite::Sprite::new(self.hud_texture, [60.0, 60.0], [64.0, 64.0])
+        });
+        self.sprite_batch.draw(sprite::Sprite {
+            tint: [
+                self.light_uniform.color[0],
+                self.light_uniform.color[1],
+                self.light_uniform.color[2],
+                0.5,
+            ],
+            uv_rect: [0.25, 0.25, 0.75, 0.75],
+            layer: 1,
+            ..sprite::Sprite::new(self.hud_texture, [60.0, 60.0], [32.0, 32.0])
+        });
+        self.sprite_batch.prepare(device, queue, &self.hud_camera);
+
+        self.terrain.update(device, self.camera.position);
+
+        // Only the bobbing cubes are uploaded again
+        if self.wave_cubes {
+            self.wave_time += dt.as_secs_f32();
+            for (i, &id) in self.first_row.iter().enumerate() {
+                if let Some(instance) = self.instances.get_mut(id) {
+                    instance.position.y = (self.wave_time * 2.0 + i as f32 * 0.5).sin();
+                }
+            }
+        }
+        self.instance_buffer
+            .prepare(device, queue, &mut self.instances);
+
+        if let Some(skeleton) = self.tentacle_model.skeleton.as_ref() {
+            let clips = &self.tentacle_model.animations;
+            let mut palettes = Vec::with_capacity(self.tentacle_controllers.len());
+            for controller in &mut self.tentacle_controllers {
+                controller.set_parameter("activity", self.tentacle_activity);
+                let stretch = if self.stretch_requested { 1.0 } else { 0.0 };
+                controller.set_parameter("stretch", stretch);
+                controller.layers_mut()[1].weight = self.curl_weight;
+                let events = controller.update(dt.as_secs_f32() * self.animation_speed, clips);
+                if let Some(event) = events.into_iter().last() {
+                    self.last_tentacle_event = Some(event);
+                }
+                palettes.push(skeleton.skinning_matrices(&controller.pose(skeleton, clips)));
+            }
+            self.stretch_requested = false;
+            self.joint_palettes.prepare(
+                device,
+                queue,
+                &self.pipelines,
+                &self.clustered_lights,
+                &palettes,
+            );
+        }
+
+        // The other blobs breathe in and out of each shape
+        self.morph_time += dt.as_secs_f32() * self.animation_speed;
+        if let Some((_, weights)) = self.blobs.as_mut() {
+            weights.weights_mut(0).copy_from_slice(&self.blob_expression);
+            let wave = |speed: f32| (self.morph_time * speed).sin() * 0.5 + 0.5;
+            weights.weights_mut(1).copy_from_slice(&[wave(1.0), 0.0]);
+            weights.weights_mut(2).copy_from_slice(&[wave(0.7), wave(1.3)]);
+            weights.prepare(queue);
+        }
+
+        // Each light circles the center at its own pace
+        for (i, light) in self.point_lights.iter_mut().enumerate() {
+            let speed = 0.2 + (i % 7) as f32 * 0.1;
+            let rotation = cgmath::Quaternion::from_angle_y(cgmath::Rad(speed * dt.as_secs_f32()));
+            light.position = (rotation * cgmath::Vector3::from(light.position)).into();
+        }
+        if self.use_sky {
+            let view_proj = self.projection.calc_matrix() * self.camera.calc_matrix();
+            self.sky_renderer.prepare(queue, &self.sky, view_proj);
+        }
+
+        let point_lights = &self.point_lights[..self.point_light_count];
+        if let Some(deferred) = self.deferred.as_mut() {
+            let view_proj = self.projection.calc_matrix() * self.camera.calc_matrix();
+            deferred.prepare(device, queue, view_proj, point_lights);
+        }
+        // Blended materials are shaded forward on both render paths
+        self.clustered_lights.prepare(
+            queue,
+            &self.camera,
+            &self.projection,
+            self.screen_size,
+            point_lights,
+        );
+
+        let view = self.camera.calc_matrix();
+        for emitter in &mut self.particle_emitters {
+            emitter.update(queue, dt.as_secs_f32(), view);
+        }
+
+        // Smooth the frame time so the displayed stats stay readable
+        self.frame_time = self.frame_time * 0.95 + dt.as_secs_f32() * 0.05;
+        let stats = format!(
+            "{:.0} FPS ({:.2} ms)\n{} instances\n{} terrain triangles",
+            1.0 / self.frame_time.max(f32::EPSILON),
+            self.frame_time * 1000.0,
+            self.instances.len(),
+            self.terrain.triangle_count()
+        );
+        self.text_renderer.queue_screen(
+            self.font,
+            &stats,
+            [10.0, 10.0],
+            &text::TextStyle::default(),
+        );
+
+        let position_style = text::TextStyle {
+            color: [1.0, 1.0, 1.0, 0.6],
+            align: text::HorizontalAlign::Right,
+            ..Default::default()
+        };
+        let position = format!(
+            "x: {:.1}\ny: {:.1}\nz: {:.1}",
+            self.camera.position.x, self.camera.position.y, self.camera.position.z
+        );
+        let (width, height) = self
+            .text_renderer
+            .measure(self.font, &position, &position_style);
+        self.text_renderer.queue_screen(
+            self.font,
+            &position,
+            [
+                self.screen_size.width as f32 - width - 10.0,
+                self.screen_size.height as f32 - height - 10.0,
+            ],
+            &position_style,
+        );
+
+        let light_position: cgmath::Point3<f32> = self.light_uniform.position.into();
+        self.text_renderer.queue_world(
+            self.font,
+            "Light",
+            light_position + cgmath::Vector3::unit_y() * 0.4,
+            0.01,
+            &text::TextStyle {
+                size: 32.0,
+                align: text::HorizontalAlign::Center,
+                color: [
+                    self.light_uniform.color[0],
+                    self.light_uniform.color[1],
+                    self.light_uniform.color[2],
+                    1.0,
+                ],
+                ..Default::default()
+            },
+        );
+        self.text_renderer
+            .prepare(device, queue, self.screen_size, self.camera.calc_matrix());
+    }
+
+    fn ui(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Debug").show(ctx, |ui| {
+            egui::CollapsingHeader::new("Light")
+                .default_open(true)
+                .show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Color");
+                        ui.color_edit_button_rgb(&mut self.light_uniform.color);
+                    });
+                    ui.add(
+                        egui::Slider::new(&mut self.light_orbit_speed, -360.0..=360.0)
+                            .text("Orbit speed (°/s)"),
+                    );
+                });
+
+            egui::CollapsingHeader::new("Camera")
+                .default_open(true)
+                .show(ui, |ui| {
+                    ui.add(
+                        egui::Slider::new(&mut self.camera_controller.speed, 0.0..=20.0)
+                            .text("Speed"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut self.camera_controller.sensitivity, 0.0..=2.0)
+                            .text("Sensitivity"),
+                    );
+                });
+
+            egui::CollapsingHeader::new("Materials")
+                .default_open(true)
+                .show(ui, |ui| {
+                    for material in &self.obj_model.materials {
+                        ui.label(&material.name);
+                    }
+                    ui.label(format!(
+                        "{:?} vertices, {} bytes each",
+                        self.obj_model.vertex_format,
+                        self.obj_model.vertex_format.size()
+                    ));
+                    ui.checkbox(
+                        &mut self.use_debug_material,
+                        format!("Override with {}", self.debug_material.name),
+                    );
+                });
+
+            egui::CollapsingHeader::new("Assets").show(ui, |ui| {
+                ui.label(self.assets.stats().to_string());
+                ui.label(format!("cube.obj: {}", self.obj_model.state()));
+            });
+
+            egui::CollapsingHeader::new("Instances").show(ui, |ui| {
+                ui.label(format!("{} cubes", self.instances.len()));
+                ui.horizontal(|ui| {
+                    if ui.button("Add cube").clicked() {
+                        // Spiral out above the grid
+                        let n = self.spawned_cubes.len() as f32;
+                        let (sin, cos) = (n * 0.8).sin_cos();
+                        let radius = 4.0 + n * 0.5;
+                        let id = self.instances.add(Instance {
+                            scale: cgmath::Vector3::new(0.5, 0.5, 0.5),
+                            color: [1.0, 0.9, 0.3, 1.0],
+                            ..Instance::new(
+                                cgmath::Vector3::new(cos * radius, 6.0, sin * radius),
+                                cgmath::Quaternion::from_angle_y(cgmath::Rad(n)),
+                            )
+                        });
+                        self.spawned_cubes.push(id);
+                    }
+                    if ui.button("Remove cube").clicked() {
+                        if let Some(id) = self.spawned_cubes.pop() {
+                            self.instances.remove(id);
+                        }
+                    }
+                });
+                if let Some(instance) = self
+                    .spawned_cubes
+                    .last()
+                    .and_then(|&id| self.instances.get(id))
+                {
+                    ui.label(format!(
+                        "Last added at ({:.1}, {:.1}, {:.1})",
+                        instance.position.x, instance.position.y, instance.position.z
+                    ));
+                }
+                ui.checkbox(&mut self.wave_cubes, "Wave the first row");
+            });
+
+            egui::CollapsingHeader::new("Animation").show(ui, |ui| {
+                if let Some(skeleton) = &self.tentacle_model.skeleton {
+                    ui.label(format!(
+                        "{} skinned instances, {} joints each",
+                        self.joint_palettes.count(),
+                        skeleton.joints().len()
+                    ));
+                }
+                if let Some(controller) = self.tentacle_controllers.first() {
+                    let base = &controller.layers()[0];
+                    ui.label(format!(
+                        "First tentacle: {} ({:.0}% faded in)",
+                        base.current_state().name,
+                        base.fade_progress() * 100.0
+                    ));
+                }
+                ui.label(format!(
+                    "Last event: {}",
+                    self.last_tentacle_event.as_deref().unwrap_or("none")
+                ));
+                ui.add(egui::Slider::new(&mut self.animation_speed, 0.0..=4.0).text("Speed"));
+                ui.add(
+                    egui::Slider::new(&mut self.tentacle_activity, 0.0..=1.0).text("Activity"),
+                );
+                ui.add(egui::Slider::new(&mut self.curl_weight, 0.0..=1.0).text("Curl layer"));
+                if ui.button("Stretch").clicked() {
+                    self.stretch_requested = true;
+                }
+                if let Some(interpolation) = self
+                    .tentacle_model
+                    .animations
+                    .first()
+                    .and_then(|clip| clip.channels.first())
+                    .map(|channel| channel.interpolation)
+                {
+                    let mut selected = interpolation;
+                    egui::ComboBox::from_label("Interpolation")
+                        .selected_text(format!("{:?}", selected))
+                        .show_ui(ui, |ui| {
+                            for option in [
+                                animation::Interpolation::Linear,
+                                animation::Interpolation::Step,
+                            ] {
+                                ui.selectable_value(&mut selected, option, format!("{:?}", option));
+                            }
+                        });
+                    if selected != interpolation {
+                        for clip in &mut self.tentacle_model.animations {
+                            for channel in &mut clip.channels {
+                                channel.interpolation = selected;
+                            }
+                        }
+                    }
+                }
+            });
+
+            if let Some((model, _)) = &self.blobs {
+                egui::CollapsingHeader::new("Morph targets").show(ui, |ui| {
+                    let names = model.meshes[0]
+                        .morph_targets
+                        .as_ref()
+                        .map_or(&[][..], |targets| targets.names());
+                    for (name, weight) in names.iter().zip(&mut self.blob_expression) {
+                        ui.add(egui::Slider::new(weight, 0.0..=1.0).text(name));
+                    }
+                });
+            }
+
+            egui::CollapsingHeader::new("Point lights").show(ui, |ui| {
+                ui.add(
+                    egui::Slider::new(&mut self.point_light_count, 0..=self.point_lights.len())
+                        .text("Count"),
+                );
+            });
+
+            egui::CollapsingHeader::new("Atmosphere").show(ui, |ui| {
+                let mode = &mut self.fog.mode;
+                egui::ComboBox::from_label("Fog")
+                    .selected_text(format!("{:?}", mode))
+                    .show_ui(ui, |ui| {
+                        for option in [
+                            atmosphere::FogMode::Off,
+                            atmosphere::FogMode::Linear {
+                                start: 40.0,
+                                end: 100.0,
+                            },
+                            atmosphere::FogMode::Exponential { density: 0.03 },
+                            atmosphere::FogMode::Height {
+                                density: 0.1,
+                                falloff: 0.3,
+                                base: -8.0,
+                            },
+                        ] {
+                            let label = format!("{:?}", option);
+                            ui.selectable_value(mode, option, label);
+                        }
+                    });
+                match mode {
+                    atmosphere::FogMode::Off => {}
+                    atmosphere::FogMode::Linear { start, end } => {
+                        ui.add(egui::Slider::new(start, 0.0..=100.0).text("Start"));
+                        ui.add(egui::Slider::new(end, 0.0..=100.0).text("End"));
+                    }
+                    atmosphere::FogMode::Exponential { density } => {
+                        ui.add(egui::Slider::new(density, 0.0..=0.2).text("Density"));
+                    }
+                    atmosphere::FogMode::Height {
+                        density,
+                        falloff,
+                        base,
+                    } => {
+                        ui.add(egui::Slider::new(density, 0.0..=0.5).text("Density"));
+                        ui.add(egui::Slider::new(falloff, 0.0..=2.0).text("Falloff"));
+                        ui.add(egui::Slider::new(base, -20.0..=20.0).text("Base height"));
+                    }
+                }
+                ui.add(
+                    egui::Slider::new(&mut self.fog.sun_scattering, 0.0..=1.0)
+                        .text("Sun scattering"),
+                );
+
+                ui.checkbox(&mut self.use_sky, "Sky");
+                if self.use_sky {
+                    use std::f32::consts::{FRAC_PI_2, PI};
+                    ui.add(
+                        egui::Slider::new(&mut self.sky.sun_elevation.0, -0.3..=FRAC_PI_2)
+                            .text("Sun elevation (rad)"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut self.sky.sun_azimuth.0, -PI..=PI)
+                            .text("Sun azimuth (rad)"),
+                    );
+                } else {
+                    ui.horizontal(|ui| {
+                        ui.label("Fog color");
+                        ui.color_edit_button_rgb(&mut self.fog.color);
+                    });
+                }
+            });
+
+            egui::CollapsingHeader::new("Terrain").show(ui, |ui| {
+                ui.add(
+                    egui::Slider::new(&mut self.terrain.lod_distance, 0.0..=100.0)
+                        .text("LOD distance"),
+                );
+            });
+
+            if !self.particle_emitters.is_empty() {
+                egui::CollapsingHeader::new("Particles").show(ui, |ui| {
+                    for (i, emitter) in self.particle_emitters.iter_mut().enumerate() {
+                        ui.label(format!("Emitter {} ({} particles)", i, emitter.capacity()));
+                        ui.add(
+                            egui::Slider::new(&mut emitter.config.rate, 0.0..=1000.0)
+                                .text("Rate (/s)"),
+                        );
+                        let shape = &mut emitter.config.shape;
+                        egui::ComboBox::from_id_source(i)
+                            .selected_text(format!("{:?}", shape))
+                            .show_ui(ui, |ui| {
+                                for option in [
+                                    particle::EmitterShape::Point,
+                                    particle::EmitterShape::Sphere { radius: 0.5 },
+                                    particle::EmitterShape::Cone { angle: 0.3 },
+                                    particle::EmitterShape::Box {
+                                        half_extents: (0.5, 0.1, 0.5).into(),
+                                    },
+                                ] {
+                                    let label = format!("{:?}", option);
+                                    ui.selectable_value(shape, option, label);
+                                }
+                            });
+                    }
+                });
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("Clear color");
+                ui.color_edit_button_rgb(&mut self.clear_color);
+            });
+        });
     }
 
     fn render(
@@ -286,46 +1160,158 @@ impl super::State for DefaultState {
         view: &wgpu::TextureView,
         encoder: &mut wgpu::CommandEncoder,
     ) -> Result<(), wgpu::SurfaceError> {
+        if let Some(particle_pipelines) = self.pipelines.get_particle_pipelines() {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Particle Simulate Pass"),
+            });
+            for emitter in &self.particle_emitters {
+                emitter.simulate(&mut compute_pass, particle_pipelines);
+            }
+        }
+
+        let clear_color = wgpu::Color {
+            r: self.clear_color[0] as f64,
+            g: self.clear_color[1] as f64,
+            b: self.clear_color[2] as f64,
+            a: 1.0,
+        };
+
+        let deferred = self
+            .deferred
+            .as_ref()
+            .zip(self.pipelines.get_deferred_pipelines());
+        if let Some((deferred, deferred_pipelines)) = deferred {
+            {
+                let mut render_pass =
+                    deferred.begin_geometry_pass(encoder, &self.depth_texture.view);
+                self.instance_buffer.bind(&mut render_pass);
+                render_pass.set_pipeline(
+                    deferred_pipelines.get_geometry_pipeline(self.obj_model.vertex_format),
+                );
+                self.draw_models(&mut render_pass, false);
+                self.terrain.render_geometry(
+                    &mut render_pass,
+                    deferred_pipelines,
+                    &self.camera_bind_group,
+                    &self.light_bind_group,
+                );
+            }
+            deferred.render_lighting(
+                encoder,
+                view,
+                clear_color,
+                deferred_pipelines,
+                &self.camera_bind_group,
+                &self.light_bind_group,
+            );
+        }
+
+        // The deferred path already shaded the opaque geometry, the rest is
+        // drawn over it using the depth of the geometry pass
+        let (color_load, depth_load) = match deferred {
+            Some(_) => (wgpu::LoadOp::Load, wgpu::LoadOp::Load),
+            None => (wgpu::LoadOp::Clear(clear_color), wgpu::LoadOp::Clear(1.0)),
+        };
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &view,
+                view,
                 resolve_target: None,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color {
-                        r: 0.1,
-                        g: 0.2,
-                        b: 0.3,
-                        a: 1.0,
-                    }),
+                    load: color_load,
                     store: true,
                 },
             })],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                 view: &self.depth_texture.view,
                 depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
+                    load: depth_load,
                     store: true,
                 }),
                 stencil_ops: None,
             }),
         });
 
-        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-        render_pass.set_pipeline(self.pipelines.get_light_pipeline());
+        self.instance_buffer.bind(&mut render_pass);
+        render_pass.set_pipeline(
+            self.pipelines
+                .get_light_pipeline(self.obj_model.vertex_format),
+        );
         render_pass.draw_light_model(
             &self.obj_model,
             &self.camera_bind_group,
             &self.light_bind_group,
         );
 
-        render_pass.set_pipeline(self.pipelines.get_render_pipeline());
-        render_pass.draw_model_instanced(
-            &self.obj_model,
-            0..self.instances.len() as u32,
-            &self.camera_bind_group,
-            &self.light_bind_group,
+        if deferred.is_none() {
+            render_pass.set_pipeline(
+                self.pipelines
+                    .get_render_pipeline(self.obj_model.vertex_format),
+            );
+            render_pass.set_bind_group(3, self.clustered_lights.bind_group(), &[]);
+            self.draw_models(&mut render_pass, false);
+
+            self.terrain.render(
+                &mut render_pass,
+                &self.pipelines,
+                &self.camera_bind_group,
+                &self.light_bind_group,
+                self.clustered_lights.bind_group(),
+            );
+        }
+
+        // Skinned models are lit like forward models on either path
+        render_pass.set_pipeline(self.pipelines.get_skinned_pipeline());
+        render_pass.set_vertex_buffer(1, self.tentacle_buffer.slice(..));
+        for i in 0..self.joint_palettes.count() {
+            self.joint_palettes.bind(&mut render_pass, i);
+            render_pass.draw_model_instanced(
+                &self.tentacle_model,
+                i as u32..i as u32 + 1,
+                &self.camera_bind_group,
+                &self.light_bind_group,
+            );
+        }
+
+        if let (Some((model, weights)), Some(morph_pipeline)) =
+            (&self.blobs, self.pipelines.get_morph_pipeline())
+        {
+            render_pass.set_pipeline(morph_pipeline.get_pipeline());
+            render_pass.set_vertex_buffer(1, self.blob_buffer.slice(..));
+            weights.bind(&mut render_pass);
+            render_pass.draw_model_instanced(
+                model,
+                0..weights.instance_count() as u32,
+                &self.camera_bind_group,
+                &self.light_bind_group,
+            );
+        }
+
+        if self.use_sky {
+            self.sky_renderer
+                .render(&mut render_pass, &self.pipelines, &self.camera_bind_group);
+        }
+
+        // Blended cubes go over every opaque model and the sky
+        render_pass.set_pipeline(
+            self.pipelines
+                .get_transparent_pipeline(self.obj_model.vertex_format),
         );
+        render_pass.set_bind_group(3, self.clustered_lights.bind_group(), &[]);
+        self.instance_buffer.bind(&mut render_pass);
+        self.draw_models(&mut render_pass, true);
+
+        // Transparent effects go after every opaque model
+        if let Some(particle_pipelines) = self.pipelines.get_particle_pipelines() {
+            for emitter in &self.particle_emitters {
+                emitter.render(&mut render_pass, particle_pipelines, &self.camera_bind_group);
+            }
+        }
+
+        self.sprite_batch.render(&mut render_pass, &self.pipelines);
+
+        self.text_renderer
+            .draw(&mut render_pass, &self.pipelines, &self.camera_bind_group);
 
         Ok(())
     }
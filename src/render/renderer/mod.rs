@@ -1,9 +1,22 @@
+mod blob;
 mod default_state;
+mod tentacle;
 pub use default_state::DefaultState;
 
 use wgpu::{CommandEncoder, Queue, TextureView};
 use winit::event::Event;
 
+/// How the scene is shaded, picked once at startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderPath {
+    /// Every object is lit while it is drawn
+    #[default]
+    Forward,
+    /// Objects are first drawn to a G-buffer, then lit once per pixel, which
+    /// scales to many small lights
+    Deferred,
+}
+
 pub trait State {
     fn resize(
         &mut self,
@@ -12,7 +25,9 @@ pub trait State {
         new_size: winit::dpi::PhysicalSize<u32>,
     );
     fn input(&mut self, event: &Event<()>) -> bool;
-    fn update(&mut self, queue: &Queue, dt: instant::Duration);
+    fn update(&mut self, device: &wgpu::Device, queue: &Queue, dt: instant::Duration);
+    /// Builds the state's debug panels, called once per frame after `update`
+    fn ui(&mut self, ctx: &egui::Context);
     fn render(
         &self,
         view: &TextureView,
@@ -0,0 +1,257 @@
+//! Procedural skinned model showing off skeletal animation until skinned
+//! models can be imported
+
+use std::f32::consts::TAU;
+
+use cgmath::{Deg, One, Quaternion, Rotation3, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::{animation, assets::Handle, model};
+
+const JOINTS: usize = 4;
+const SEGMENT_LENGTH: f32 = 1.0;
+const RINGS: usize = 24;
+const SIDES: usize = 12;
+
+/// Tapered tube standing on the origin, bent by a chain of joints, with
+/// "idle", "walk", "run" and "curl" animations
+pub fn create_tentacle(
+    device: &wgpu::Device,
+    material: model::Material,
+) -> anyhow::Result<model::Model> {
+    let height = JOINTS as f32 * SEGMENT_LENGTH;
+    let mut vertices = Vec::with_capacity((RINGS + 1) * (SIDES + 1));
+    for ring in 0..=RINGS {
+        let v = ring as f32 / RINGS as f32;
+        let y = v * height;
+        let radius = 0.35 * (1.0 - v) + 0.05;
+
+        // Blend between the two joints closest to the vertex
+        let segment = y / SEGMENT_LENGTH;
+        let joint = (segment.floor() as usize).min(JOINTS - 1);
+        let next = (joint + 1).min(JOINTS - 1);
+        let blend = if next == joint {
+            0.0
+        } else {
+            segment - joint as f32
+        };
+
+        for side in 0..=SIDES {
+            let u = side as f32 / SIDES as f32;
+            let (sin, cos) = (u * TAU).sin_cos();
+            vertices.push(model::SkinnedVertex {
+                position: [cos * radius, y, sin * radius],
+                tex_coords: [u * 2.0, (1.0 - v) * 4.0],
+                normal: [cos, 0.0, sin],
+                tangent: [-sin, 0.0, cos],
+                bitangent: [0.0, 1.0, 0.0],
+                joints: [joint as u16, next as u16, 0, 0],
+                weights: [1.0 - blend, blend, 0.0, 0.0],
+            });
+        }
+    }
+
+    let row = (SIDES + 1) as u32;
+    let mut indices = Vec::with_capacity(RINGS * SIDES * 6);
+    for ring in 0..RINGS as u32 {
+        for side in 0..SIDES as u32 {
+            let a = ring * row + side;
+            let b = a + 1;
+            let c = a + row;
+            let d = c + 1;
+            indices.extend([a, c, b, b, c, d]);
+        }
+    }
+
+    let skeleton = animation::Skeleton::from_rest_pose((0..JOINTS).map(|i| {
+        let offset = if i == 0 { 0.0 } else { SEGMENT_LENGTH };
+        (
+            format!("segment_{}", i),
+            i.checked_sub(1),
+            animation::Transform::from_translation(Vector3::new(0.0, offset, 0.0)),
+        )
+    }))?;
+
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Tentacle Vertex Buffer"),
+        contents: bytemuck::cast_slice(&vertices),
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Tentacle Index Buffer"),
+        contents: bytemuck::cast_slice(&indices),
+        usage: wgpu::BufferUsages::INDEX,
+    });
+
+    Ok(model::Model {
+        meshes: vec![model::Mesh {
+            name: "tentacle".to_string(),
+            vertex_buffer,
+            index_buffer,
+            index_format: wgpu::IndexFormat::Uint32,
+            num_elements: indices.len() as u32,
+            material: 0,
+            morph_targets: None,
+        }],
+        materials: vec![Handle::new(material)],
+        skeleton: Some(skeleton),
+        animations: vec![
+            sway("idle", 8.0, 3.0),
+            sway("walk", 20.0, 2.0),
+            sway("run", 35.0, 1.0),
+            curl(),
+            stretch(),
+        ],
+        vertex_format: model::VertexFormat::Full,
+        nodes: Vec::new(),
+    })
+}
+
+/// Every joint above the root bends back and forth, the tip lagging behind
+/// the base, while the whole tentacle bobs and breathes. Fires "left" and
+/// "right" at the end of each swing.
+fn sway(name: &str, angle: f32, period: f32) -> animation::AnimationClip {
+    let times: Vec<f32> = [0.0, 0.25, 0.5, 0.75, 1.0]
+        .iter()
+        .map(|t| t * period)
+        .collect();
+    let mut channels: Vec<_> = (1..JOINTS)
+        .map(|joint| {
+            let bend = |angle: f32| {
+                Quaternion::from_angle_z(Deg(angle))
+                    * Quaternion::from_angle_x(Deg(angle * 0.5 * joint as f32))
+            };
+            animation::Channel {
+                joint,
+                times: times.clone(),
+                keyframes: animation::Keyframes::Rotation(vec![
+                    bend(0.0),
+                    bend(angle),
+                    bend(0.0),
+                    bend(-angle),
+                    bend(0.0),
+                ]),
+                interpolation: animation::Interpolation::Linear,
+            }
+        })
+        .collect();
+    let bob = angle / 60.0;
+    channels.push(animation::Channel {
+        joint: 0,
+        times: vec![0.0, period * 0.5, period],
+        keyframes: animation::Keyframes::Translation(vec![
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, bob, 0.0),
+            Vector3::new(0.0, 0.0, 0.0),
+        ]),
+        interpolation: animation::Interpolation::Linear,
+    });
+    let (wide, short) = (1.0 + bob * 0.3, 1.0 - bob * 0.15);
+    channels.push(animation::Channel {
+        joint: 0,
+        times: times.clone(),
+        keyframes: animation::Keyframes::Scale(vec![
+            Vector3::new(1.0, 1.0, 1.0),
+            Vector3::new(wide, short, wide),
+            Vector3::new(1.0, 1.0, 1.0),
+            Vector3::new(wide, short, wide),
+            Vector3::new(1.0, 1.0, 1.0),
+        ]),
+        interpolation: animation::Interpolation::Linear,
+    });
+    animation::AnimationClip::new(name, channels)
+        .with_event(times[1], "left")
+        .with_event(times[3], "right")
+}
+
+/// Tip curling onto itself, meant to be layered over a sway
+fn curl() -> animation::AnimationClip {
+    let channels = (2..JOINTS)
+        .map(|joint| animation::Channel {
+            joint,
+            times: vec![0.0, 1.0, 2.0],
+            keyframes: animation::Keyframes::Rotation(vec![
+                Quaternion::from_angle_x(Deg(50.0)),
+                Quaternion::from_angle_x(Deg(70.0)),
+                Quaternion::from_angle_x(Deg(50.0)),
+            ]),
+            interpolation: animation::Interpolation::Linear,
+        })
+        .collect();
+    animation::AnimationClip::new("curl", channels)
+}
+
+/// One-off reach straight up, squashing the base on the way
+fn stretch() -> animation::AnimationClip {
+    let times = vec![0.0, 0.4, 1.0];
+    let mut channels: Vec<_> = (1..JOINTS)
+        .map(|joint| animation::Channel {
+            joint,
+            times: times.clone(),
+            keyframes: animation::Keyframes::Rotation(vec![Quaternion::one(); 3]),
+            interpolation: animation::Interpolation::Linear,
+        })
+        .collect();
+    channels.push(animation::Channel {
+        joint: 0,
+        times: times.clone(),
+        keyframes: animation::Keyframes::Scale(vec![
+            Vector3::new(1.0, 1.0, 1.0),
+            Vector3::new(0.85, 1.3, 0.85),
+            Vector3::new(1.0, 1.0, 1.0),
+        ]),
+        interpolation: animation::Interpolation::Linear,
+    });
+    animation::AnimationClip::new("stretch", channels).with_event(times[1], "stretch")
+}
+
+/// Controller playing the tentacle clips: the "activity" parameter moves the
+/// base layer between idle, walk and run, setting "stretch" above 0 plays a
+/// stretch before going back to idle, and a second layer curls the tip by
+/// the weight it is given
+pub fn create_tentacle_controller() -> anyhow::Result<animation::AnimationController> {
+    let state = |name: &str, clip| animation::AnimationState {
+        name: name.to_string(),
+        clip,
+        speed: 1.0,
+        looping: true,
+    };
+    let transition = |from, to, condition| animation::Transition {
+        from: Some(from),
+        to,
+        condition,
+        duration: 0.6,
+    };
+    let activity = || "activity".to_string();
+    let (idle, walk, run, stretch) = (0, 1, 2, 3);
+    let locomotion = animation::StateMachine::new(
+        vec![
+            state("idle", 0),
+            state("walk", 1),
+            state("run", 2),
+            animation::AnimationState {
+                looping: false,
+                ..state("stretch", 4)
+            },
+        ],
+        vec![
+            animation::Transition {
+                from: None,
+                to: stretch,
+                condition: animation::Condition::Greater("stretch".to_string(), 0.0),
+                duration: 0.2,
+            },
+            transition(stretch, idle, animation::Condition::Finished),
+            transition(idle, walk, animation::Condition::Greater(activity(), 0.3)),
+            transition(walk, idle, animation::Condition::Less(activity(), 0.3)),
+            transition(walk, run, animation::Condition::Greater(activity(), 0.7)),
+            transition(run, walk, animation::Condition::Less(activity(), 0.7)),
+        ],
+    )?;
+    let curl = animation::StateMachine::new(vec![state("curl", 3)], Vec::new())?;
+
+    Ok(animation::AnimationController::new(vec![
+        animation::AnimationLayer::new(locomotion, 1.0),
+        animation::AnimationLayer::new(curl, 0.0),
+    ]))
+}
@@ -1,9 +1,15 @@
-use std::io::{BufReader, Cursor};
+use std::{
+    collections::HashMap,
+    io::{BufReader, Cursor},
+    path::Path,
+    sync::Mutex,
+};
 
+use anyhow::{bail, Context};
 use cfg_if::cfg_if;
 use wgpu::util::DeviceExt;
 
-use crate::{model, texture};
+use crate::{cooked, gltf, mesh_optimizer, model, normals, tangents, terrain, texture};
 
 #[cfg(target_arch = "wasm32")]
 fn format_url(file_name: &str) -> reqwest::Url {
@@ -18,25 +24,6 @@ fn format_url(file_name: &str) -> reqwest::Url {
     base.join(file_name).unwrap()
 }
 
-pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
-    cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            let url = format_url(file_name);
-            let txt = reqwest::get(url)
-                .await?
-                .text()
-                .await?;
-        } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("res")
-                .join(file_name);
-            let txt = std::fs::read_to_string(path)?;
-        }
-    }
-
-    Ok(txt)
-}
-
 pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
     cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
@@ -67,13 +54,420 @@ pub async fn load_texture(
     texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
 }
 
-pub async fn load_model(
+/// Loads a heightmap from an image, or from square headerless 16-bit
+/// samples when the file ends in `.raw` or `.r16`
+pub async fn load_heightmap(file_name: &str) -> anyhow::Result<terrain::Heightmap> {
+    let data = load_binary(file_name).await?;
+    if file_name.ends_with(".raw") || file_name.ends_with(".r16") {
+        let size = ((data.len() / 2) as f64).sqrt() as u32;
+        terrain::Heightmap::from_raw_bytes(&data, size, size)
+    } else {
+        terrain::Heightmap::from_image_bytes(&data)
+    }
+}
+
+/// Texture coordinates given to meshes exported without any
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UvFallback {
+    /// Every vertex samples the corner of the textures
+    Zero,
+    /// Projected along the axis the mesh is the thinnest on, keeping its
+    /// proportions
+    #[default]
+    Planar,
+}
+
+/// How the meshes of a model are processed and uploaded
+#[derive(Debug, Clone, Copy)]
+pub struct LoadOptions {
+    pub vertex_format: model::VertexFormat,
+    /// Meshes are uploaded as exported when `None`
+    pub optimize: Option<mesh_optimizer::OptimizeOptions>,
+    /// Sharpest edge still smoothed when generating missing normals
+    pub smoothing_angle: cgmath::Deg<f32>,
+    pub uv_fallback: UvFallback,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            vertex_format: model::VertexFormat::default(),
+            optimize: None,
+            smoothing_angle: cgmath::Deg(60.0),
+            uv_fallback: UvFallback::default(),
+        }
+    }
+}
+
+/// Vertices and indices of an OBJ mesh, filling in missing normals and
+/// texture coordinates
+fn obj_mesh_vertices(
+    mesh: &tobj::Mesh,
+    options: &LoadOptions,
+) -> anyhow::Result<(Vec<model::ModelVertex>, Vec<u32>)> {
+    if !mesh.positions.len().is_multiple_of(3) {
+        bail!(
+            "{} position components do not make 3D points",
+            mesh.positions.len()
+        );
+    }
+    let vertex_count = mesh.positions.len() / 3;
+    if !mesh.indices.len().is_multiple_of(3) {
+        bail!("{} indices do not make triangles", mesh.indices.len());
+    }
+    if let Some(index) = mesh.indices.iter().find(|&&i| i as usize >= vertex_count) {
+        bail!(
+            "Index {} is out of bounds of the {} vertices",
+            index,
+            vertex_count
+        );
+    }
+    let has_tex_coords = !mesh.texcoords.is_empty();
+    if has_tex_coords && mesh.texcoords.len() != vertex_count * 2 {
+        bail!(
+            "{} texture coordinate components for {} vertices",
+            mesh.texcoords.len(),
+            vertex_count
+        );
+    }
+    let has_normals = !mesh.normals.is_empty();
+    if has_normals && mesh.normals.len() != vertex_count * 3 {
+        bail!(
+            "{} normal components for {} vertices",
+            mesh.normals.len(),
+            vertex_count
+        );
+    }
+
+    let mut vertices = (0..vertex_count)
+        .map(|i| model::ModelVertex {
+            position: [
+                mesh.positions[i * 3],
+                mesh.positions[i * 3 + 1],
+                mesh.positions[i * 3 + 2],
+            ],
+            tex_coords: if has_tex_coords {
+                [mesh.texcoords[i * 2], mesh.texcoords[i * 2 + 1]]
+            } else {
+                [0.0; 2]
+            },
+            normal: if has_normals {
+                [
+                    mesh.normals[i * 3],
+                    mesh.normals[i * 3 + 1],
+                    mesh.normals[i * 3 + 2],
+                ]
+            } else {
+                [0.0; 3]
+            },
+            // We'll calculate these later
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        })
+        .collect::<Vec<_>>();
+    let mut indices = mesh.indices.clone();
+
+    if !has_tex_coords && options.uv_fallback == UvFallback::Planar {
+        planar_tex_coords(&mut vertices);
+    }
+    if !has_normals {
+        (vertices, indices) =
+            normals::generate_normals(&vertices, &indices, options.smoothing_angle);
+    }
+    Ok((vertices, indices))
+}
+
+/// Projects the vertices on the plane of the two largest sides of their
+/// bounds, the largest one mapping to [0, 1]
+fn planar_tex_coords(vertices: &mut [model::ModelVertex]) {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for vertex in vertices.iter() {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(vertex.position[axis]);
+            max[axis] = max[axis].max(vertex.position[axis]);
+        }
+    }
+    let extent = [0, 1, 2].map(|axis| max[axis] - min[axis]);
+    let thinnest = (0..3)
+        .min_by(|&a, &b| extent[a].total_cmp(&extent[b]))
+        .unwrap_or(1);
+    let (u, v) = match thinnest {
+        0 => (2, 1),
+        1 => (0, 2),
+        _ => (0, 1),
+    };
+    let scale = 1.0 / extent[u].max(extent[v]).max(f32::EPSILON);
+    for vertex in vertices {
+        vertex.tex_coords = [
+            (vertex.position[u] - min[u]) * scale,
+            (vertex.position[v] - min[v]) * scale,
+        ];
+    }
+}
+
+/// Where the files of a model are read from
+#[derive(Debug, Clone, Copy)]
+pub enum AssetSource<'a> {
+    /// The resources bundled with the engine, see `load_binary`
+    Resources,
+    /// A resource folder on disk, for tools working on the sources
+    Directory(&'a Path),
+}
+
+impl AssetSource<'_> {
+    pub async fn read(self, file_name: &str) -> anyhow::Result<Vec<u8>> {
+        match self {
+            AssetSource::Resources => load_binary(file_name).await,
+            AssetSource::Directory(directory) => Ok(std::fs::read(directory.join(file_name))?),
+        }
+    }
+}
+
+/// Reads the files a model is made from, keeping their hashes so that the
+/// cooked model can tell when it is stale
+struct DependencyReader<'a> {
+    source: AssetSource<'a>,
+    dependencies: Mutex<Vec<cooked::Dependency>>,
+}
+
+impl<'a> DependencyReader<'a> {
+    fn new(source: AssetSource<'a>) -> Self {
+        Self {
+            source,
+            dependencies: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Reads a file, which is a dependency even when missing
+    async fn read(&self, file_name: &str) -> anyhow::Result<Vec<u8>> {
+        let data = self.source.read(file_name).await;
+        self.dependencies.lock().unwrap().push(cooked::Dependency {
+            path: file_name.to_string(),
+            hash: match &data {
+                Ok(data) => cooked::content_hash(data),
+                Err(_) => cooked::MISSING_HASH,
+            },
+        });
+        data.with_context(|| format!("Failed to read {}", file_name))
+    }
+
+    fn key(&self, options: &LoadOptions) -> cooked::CookKey {
+        cooked::CookKey {
+            options: cooked::options_hash(options),
+            dependencies: self.dependencies.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Whether a model cooked with `key` was made from the current sources with
+/// the same options
+pub async fn is_up_to_date(
+    key: &cooked::CookKey,
+    options: &LoadOptions,
+    source: AssetSource<'_>,
+) -> bool {
+    if key.options != cooked::options_hash(options) {
+        return false;
+    }
+    for dependency in &key.dependencies {
+        let hash = match source.read(&dependency.path).await {
+            Ok(data) => cooked::content_hash(&data),
+            Err(_) => cooked::MISSING_HASH,
+        };
+        if hash != dependency.hash {
+            return false;
+        }
+    }
+    true
+}
+
+/// `path` relative to the folder of `file_name`
+fn relative_path(file_name: &str, path: &str) -> String {
+    match file_name.rsplit_once('/') {
+        Some((directory, _)) => format!("{}/{}", directory, path),
+        None => path.to_string(),
+    }
+}
+
+fn obj_material_params(material: &tobj::Material) -> model::MaterialParams {
+    let alpha_mode = if material.dissolve < 1.0 {
+        model::AlphaMode::Blend
+    } else if !material.dissolve_texture.is_empty() {
+        // The alpha map is expected in the diffuse texture, as exporters
+        // usually write the same image for both
+        model::AlphaMode::Mask(0.5)
+    } else {
+        model::AlphaMode::Opaque
+    };
+    model::MaterialParams {
+        ambient: material.ambient,
+        diffuse: material.diffuse,
+        specular: material.specular,
+        shininess: material.shininess,
+        opacity: material.dissolve,
+        illumination: material.illumination_model.map_or(2, u32::from),
+        alpha_mode,
+    }
+}
+
+/// Material given to meshes without one
+fn default_material_data() -> cooked::MaterialData {
+    cooked::MaterialData {
+        name: "default".to_string(),
+        params: model::MaterialParams::default(),
+        diffuse_image: None,
+        normal_image: None,
+    }
+}
+
+/// Optimizes a mesh as set in `options`, generates its tangents unless it
+/// has some and lays it out for the GPU
+fn process_mesh(
     file_name: &str,
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    layout: &wgpu::BindGroupLayout,
-) -> anyhow::Result<model::Model> {
-    let obj_text = load_string(file_name).await?;
+    mesh_name: &str,
+    (mut vertices, mut indices): (Vec<model::ModelVertex>, Vec<u32>),
+    material: usize,
+    has_tangents: bool,
+    options: &LoadOptions,
+) -> cooked::MeshData {
+    let optimize = options.optimize;
+    let before =
+        mesh_optimizer::MeshStats::new(vertices.len(), &indices, wgpu::IndexFormat::Uint32);
+    // Before the tangents, so that welded vertices average them over
+    // all of their triangles
+    if optimize.is_some_and(|optimize| optimize.weld) {
+        vertices = mesh_optimizer::weld(&vertices, &mut indices);
+    }
+    if !has_tangents {
+        tangents::generate_tangents(&mut vertices, &mut indices);
+    }
+
+    if optimize.is_some_and(|optimize| optimize.reorder) {
+        indices = mesh_optimizer::optimize_vertex_cache(&indices, vertices.len());
+        vertices = mesh_optimizer::optimize_vertex_fetch(&vertices, &mut indices);
+    }
+    let small_indices = optimize.is_some_and(|optimize| optimize.small_indices);
+    let (index_data, index_format) =
+        mesh_optimizer::pack_indices(&indices, vertices.len(), small_indices);
+    if optimize.is_some() {
+        let after = mesh_optimizer::MeshStats::new(vertices.len(), &indices, index_format);
+        log::info!(
+            "Optimized {} ({}): {} -> {}",
+            file_name,
+            mesh_name,
+            before,
+            after
+        );
+    }
+
+    cooked::MeshData {
+        name: mesh_name.to_string(),
+        material,
+        index_format,
+        index_count: indices.len() as u32,
+        vertices: options.vertex_format.pack(&vertices).into(),
+        indices: index_data.into(),
+    }
+}
+
+/// Unit cube centered on the origin, one quad per face wound
+/// counter-clockwise seen from outside
+fn placeholder_cube() -> (Vec<model::ModelVertex>, Vec<u32>) {
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for axis in 0..3 {
+        for sign in [-1.0f32, 1.0] {
+            let mut normal = [0.0; 3];
+            normal[axis] = sign;
+            // u × v points along the normal, u flipping with it
+            let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+            let base = vertices.len() as u32;
+            for (u, v) in [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+                let mut position = [0.0; 3];
+                position[axis] = sign * 0.5;
+                position[u_axis] = (u - 0.5) * sign;
+                position[v_axis] = v - 0.5;
+                vertices.push(model::ModelVertex {
+                    position,
+                    tex_coords: [u, 1.0 - v],
+                    normal,
+                    tangent: [0.0; 3],
+                    bitangent: [0.0; 3],
+                });
+            }
+            indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+    }
+    (vertices, indices)
+}
+
+/// Mesh standing in for models that are still loading, laid out like the
+/// ones loaded with `options`
+pub fn placeholder_mesh(options: &LoadOptions) -> cooked::MeshData {
+    process_mesh(
+        "placeholder",
+        "placeholder",
+        placeholder_cube(),
+        0,
+        false,
+        options,
+    )
+}
+
+/// Cooked model of `file_name`, when it was made with the same options.
+/// Only with `check_sources` are the sources read to make sure it was made
+/// from the current ones, the cook tool keeping the shipped files up to date.
+pub async fn load_cooked_model(
+    file_name: &str,
+    options: &LoadOptions,
+    check_sources: bool,
+) -> Option<cooked::ModelData> {
+    let path = cooked::cooked_path(file_name);
+    let data = load_binary(&path).await.ok()?;
+    let model = match cooked::ModelData::from_bytes(data) {
+        Ok(model) => model,
+        Err(e) => {
+            log::debug!("Not using {}: {}", path, e);
+            return None;
+        }
+    };
+    let up_to_date = if check_sources {
+        is_up_to_date(&model.key, options, AssetSource::Resources).await
+    } else {
+        model.key.options == cooked::options_hash(options)
+    };
+    if !up_to_date {
+        log::info!("{} is out of date, loading {} instead", path, file_name);
+        return None;
+    }
+    Some(model)
+}
+
+/// Parses a model and processes its meshes, without touching the GPU
+pub async fn load_model_data(
+    file_name: &str,
+    source: AssetSource<'_>,
+    options: &LoadOptions,
+) -> anyhow::Result<cooked::ModelData> {
+    let reader = DependencyReader::new(source);
+    let extension = std::path::Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    if matches!(extension.as_deref(), Some("gltf" | "glb")) {
+        gltf_model_data(file_name, &reader, options).await
+    } else {
+        obj_model_data(file_name, &reader, options).await
+    }
+}
+
+async fn obj_model_data(
+    file_name: &str,
+    reader: &DependencyReader<'_>,
+    options: &LoadOptions,
+) -> anyhow::Result<cooked::ModelData> {
+    let obj_text = reader.read(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
 
@@ -85,136 +479,313 @@ pub async fn load_model(
             ..Default::default()
         },
         |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+            match reader.read(&p).await {
+                Ok(mat_text) => tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text))),
+                Err(e) => {
+                    log::warn!("{:#}", e);
+                    Err(tobj::LoadError::OpenFileFailed)
+                }
+            }
         },
     )
-    .await?;
-
-    let mut materials = Vec::new();
-    for m in obj_materials? {
-        let diffuse_texture = load_texture(&m.diffuse_texture, false, device, queue).await?;
-        let normal_texture = load_texture(&m.normal_texture, true, device, queue).await?;
+    .await
+    .with_context(|| format!("Failed to parse {}", file_name))?;
 
-        materials.push(model::Material::new(
-            device,
-            &m.name,
-            diffuse_texture,
-            normal_texture,
-            layout,
-        ));
+    let obj_materials = obj_materials.unwrap_or_else(|e| {
+        log::warn!("Failed to load the materials of {}: {}", file_name, e);
+        Vec::new()
+    });
+    // Maps used by several materials are only loaded once
+    let mut images = Vec::new();
+    let mut image_indices = HashMap::new();
+    let mut image = |path: &str| {
+        (!path.is_empty()).then(|| {
+            *image_indices.entry(path.to_string()).or_insert_with(|| {
+                images.push(cooked::ImageData::File(path.to_string()));
+                images.len() - 1
+            })
+        })
+    };
+    let mut materials = obj_materials
+        .iter()
+        .map(|m| cooked::MaterialData {
+            name: m.name.clone(),
+            params: obj_material_params(m),
+            diffuse_image: image(&m.diffuse_texture),
+            normal_image: image(&m.normal_texture),
+        })
+        .collect::<Vec<_>>();
+    let default_material = materials.len();
+    if models.iter().any(|m| {
+        m.mesh
+            .material_id
+            .is_none_or(|material| material >= default_material)
+    }) {
+        materials.push(default_material_data());
     }
 
     let meshes = models
         .into_iter()
+        .filter(|m| !m.mesh.indices.is_empty())
         .map(|m| {
-            let mut vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| model::ModelVertex {
-                    position: [
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ],
-                    tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
-                    // We'll calculate these later
-                    tangent: [0.0; 3],
-                    bitangent: [0.0; 3],
-                })
-                .collect::<Vec<_>>();
-
-            let indices = &m.mesh.indices;
-            let mut triangles_included = vec![0; vertices.len()];
-
-            // Calculate tangents and bitangets. We're going to
-            // use the triangles, so we need to loop through the
-            // indices in chunks of 3
-            for c in indices.chunks(3) {
-                let v0 = vertices[c[0] as usize];
-                let v1 = vertices[c[1] as usize];
-                let v2 = vertices[c[2] as usize];
-
-                let pos0: cgmath::Vector3<_> = v0.position.into();
-                let pos1: cgmath::Vector3<_> = v1.position.into();
-                let pos2: cgmath::Vector3<_> = v2.position.into();
-
-                let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
-                let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
-                let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
-
-                // Calculate the edges of the triangle
-                let delta_pos1 = pos1 - pos0;
-                let delta_pos2 = pos2 - pos0;
-
-                // This will give us a direction to calculate the
-                // tangent and bitangent
-                let delta_uv1 = uv1 - uv0;
-                let delta_uv2 = uv2 - uv0;
-
-                // Solving the following system of equations will
-                // give us the tangent and bitangent.
-                //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
-                //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
-                // Luckily, the place I found this equation provided
-                // the solution!
-                let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-                let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-                // We flip the bitangent to enable right-handed normal
-                // maps with wgpu texture coordinate system
-                let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
-
-                // We'll use the same tangent/bitangent for each vertex in the triangle
-                vertices[c[0] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
-                vertices[c[1] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
-                vertices[c[2] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
-                vertices[c[0] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
-                vertices[c[1] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
-                vertices[c[2] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
-
-                // Used to average the tangents/bitangents
-                triangles_included[c[0] as usize] += 1;
-                triangles_included[c[1] as usize] += 1;
-                triangles_included[c[2] as usize] += 1;
-            }
+            let mesh = obj_mesh_vertices(&m.mesh, options)
+                .with_context(|| format!("Invalid mesh {} in {}", m.name, file_name))?;
+            let material = m
+                .mesh
+                .material_id
+                .filter(|&material| material < default_material)
+                .unwrap_or(default_material);
+            Ok(process_mesh(
+                file_name, &m.name, mesh, material, false, options,
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
 
-            // Average the tangents/bitangents
-            for (i, n) in triangles_included.into_iter().enumerate() {
-                let denom = 1.0 / n as f32;
-                let mut v = &mut vertices[i];
-                v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
-                v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
-            }
+    Ok(cooked::ModelData {
+        key: reader.key(options),
+        vertex_format: options.vertex_format,
+        images,
+        materials,
+        nodes: Vec::new(),
+        meshes,
+    })
+}
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            model::Mesh {
-                name: file_name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
+async fn gltf_model_data(
+    file_name: &str,
+    reader: &DependencyReader<'_>,
+    options: &LoadOptions,
+) -> anyhow::Result<cooked::ModelData> {
+    let data = reader.read(file_name).await?;
+    let mut document =
+        gltf::Document::parse(&data).with_context(|| format!("Failed to parse {}", file_name))?;
+    let mut buffers = Vec::new();
+    for source in document.buffer_sources()? {
+        buffers.push(match source {
+            gltf::Source::Bytes(bytes) => bytes,
+            gltf::Source::File(path) => reader.read(&relative_path(file_name, &path)).await?,
+        });
+    }
+    let scene = document
+        .scene(&buffers)
+        .with_context(|| format!("Invalid scene in {}", file_name))?;
+
+    // Images used by several materials are only stored once
+    let mut images = Vec::new();
+    let mut image_indices = HashMap::new();
+    let mut image = |index: Option<usize>| {
+        let index = index?;
+        if let Some(&image) = image_indices.get(&index) {
+            return image;
+        }
+        let image = match document.image_source(index, &buffers) {
+            Ok(gltf::Source::Bytes(bytes)) => Some(cooked::ImageData::Bytes(bytes)),
+            Ok(gltf::Source::File(path)) => {
+                Some(cooked::ImageData::File(relative_path(file_name, &path)))
             }
+            Err(e) => {
+                log::warn!("Skipping image {} of {}: {}", index, file_name, e);
+                None
+            }
+        }
+        .map(|image| {
+            images.push(image);
+            images.len() - 1
+        });
+        image_indices.insert(index, image);
+        image
+    };
+    let mut materials = document
+        .materials()
+        .into_iter()
+        .map(|material| cooked::MaterialData {
+            diffuse_image: image(material.base_color_image),
+            normal_image: image(material.normal_image),
+            name: material.name,
+            params: material.params,
         })
         .collect::<Vec<_>>();
+    let default_material = materials.len();
+    if scene.primitives.iter().any(|p| {
+        p.material
+            .is_none_or(|material| material >= default_material)
+    }) {
+        materials.push(default_material_data());
+    }
+
+    let meshes = scene
+        .primitives
+        .into_iter()
+        .map(|mut primitive| {
+            if !primitive.has_tex_coords && options.uv_fallback == UvFallback::Planar {
+                planar_tex_coords(&mut primitive.vertices);
+            }
+            let mesh = if primitive.has_normals {
+                (primitive.vertices, primitive.indices)
+            } else {
+                // glTF asks for flat normals when there are none
+                normals::generate_normals(&primitive.vertices, &primitive.indices, cgmath::Deg(0.0))
+            };
+            let material = primitive
+                .material
+                .filter(|&material| material < default_material)
+                .unwrap_or(default_material);
+            process_mesh(
+                file_name,
+                &primitive.name,
+                mesh,
+                material,
+                primitive.has_tangents,
+                options,
+            )
+        })
+        .collect();
+
+    Ok(cooked::ModelData {
+        key: reader.key(options),
+        vertex_format: options.vertex_format,
+        images,
+        materials,
+        nodes: scene.nodes,
+        meshes,
+    })
+}
 
-    Ok(model::Model { meshes, materials })
+pub fn upload_mesh(file_name: &str, mesh: cooked::MeshData, device: &wgpu::Device) -> model::Mesh {
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Vertex Buffer", file_name)),
+        contents: &mesh.vertices,
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Index Buffer", file_name)),
+        contents: &mesh.indices,
+        usage: wgpu::BufferUsages::INDEX,
+    });
+    model::Mesh {
+        name: file_name.to_string(),
+        vertex_buffer,
+        index_buffer,
+        index_format: mesh.index_format,
+        num_elements: mesh.index_count,
+        material: mesh.material,
+        morph_targets: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+
+    use super::*;
+
+    fn quad() -> tobj::Mesh {
+        tobj::Mesh {
+            positions: vec![0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 2.0, 0.0, -1.0, 0.0, 0.0, -1.0],
+            indices: vec![0, 1, 2, 0, 2, 3],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn missing_attributes_are_generated() {
+        let (vertices, indices) = obj_mesh_vertices(&quad(), &LoadOptions::default()).unwrap();
+        assert_eq!(indices.len(), 6);
+        for vertex in &vertices {
+            assert_eq!(vertex.normal, [0.0, 1.0, 0.0]);
+        }
+        // Projected from above, the longest side spanning the texture
+        let corner = vertices.iter().find(|v| v.position == [2.0, 0.0, -1.0]);
+        assert_eq!(corner.unwrap().tex_coords, [1.0, 0.0]);
+    }
+
+    #[test]
+    fn dissolve_picks_the_alpha_mode() {
+        let opaque = obj_material_params(&tobj::Material::default());
+        assert_eq!(opaque.alpha_mode, model::AlphaMode::Opaque);
+        assert_eq!(opaque.illumination, 2);
+
+        let glass = obj_material_params(&tobj::Material {
+            dissolve: 0.25,
+            ..Default::default()
+        });
+        assert_eq!(glass.alpha_mode, model::AlphaMode::Blend);
+        assert_eq!(glass.opacity, 0.25);
+
+        let leaves = obj_material_params(&tobj::Material {
+            dissolve_texture: "leaves.png".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(leaves.alpha_mode, model::AlphaMode::Mask(0.5));
+    }
+
+    #[test]
+    fn cooked_models_know_their_sources() {
+        let res_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("res");
+        let source = AssetSource::Directory(&res_dir);
+        let options = LoadOptions::default();
+        let model = async_std::task::block_on(load_model_data("cube.obj", source, &options));
+        let model = model.unwrap();
+        let paths = model.key.dependencies.iter().map(|d| d.path.as_str());
+        assert_eq!(paths.collect::<Vec<_>>(), ["cube.obj", "cube.mtl"]);
+        assert!(async_std::task::block_on(is_up_to_date(
+            &model.key, &options, source
+        )));
+
+        let compact = LoadOptions {
+            vertex_format: model::VertexFormat::Compact,
+            ..options
+        };
+        assert!(!async_std::task::block_on(is_up_to_date(
+            &model.key, &compact, source
+        )));
+    }
+
+    #[test]
+    fn missing_sources_are_dependencies() {
+        let dir = std::env::temp_dir().join(format!("missing-sources-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let obj = "mtllib quad.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+        std::fs::write(dir.join("quad.obj"), obj).unwrap();
+        let source = AssetSource::Directory(&dir);
+        let options = LoadOptions::default();
+        let model = async_std::task::block_on(load_model_data("quad.obj", source, &options));
+        let key = model.unwrap().key;
+        assert!(key.dependencies.contains(&cooked::Dependency {
+            path: "quad.mtl".to_string(),
+            hash: cooked::MISSING_HASH,
+        }));
+        assert!(async_std::task::block_on(is_up_to_date(
+            &key, &options, source
+        )));
+
+        std::fs::write(dir.join("quad.mtl"), "newmtl quad\n").unwrap();
+        let up_to_date = async_std::task::block_on(is_up_to_date(&key, &options, source));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!up_to_date);
+    }
+
+    #[test]
+    fn placeholder_faces_point_outwards() {
+        let (vertices, indices) = placeholder_cube();
+        assert_eq!(indices.len(), 36);
+        for triangle in indices.chunks(3) {
+            let [a, b, c] =
+                [0, 1, 2].map(|i| cgmath::Vector3::from(vertices[triangle[i] as usize].position));
+            let normal = (b - a).cross(c - a);
+            let center = (a + b + c) / 3.0;
+            assert!(normal.dot(center) > 0.0, "{:?}", triangle);
+        }
+    }
+
+    #[test]
+    fn malformed_meshes_are_errors() {
+        let mut mesh = quad();
+        mesh.indices[5] = 4;
+        assert!(obj_mesh_vertices(&mesh, &LoadOptions::default()).is_err());
+
+        let mut mesh = quad();
+        mesh.normals = vec![0.0, 1.0, 0.0];
+        assert!(obj_mesh_vertices(&mesh, &LoadOptions::default()).is_err());
+    }
 }
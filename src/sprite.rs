@@ -0,0 +1,309 @@
+use std::ops::Range;
+
+use wgpu::util::DeviceExt;
+
+use crate::{assets::Handle, camera, model, render, texture, CameraUniform};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpriteTextureId(usize);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sprite {
+    pub texture: SpriteTextureId,
+    pub position: [f32; 2],
+    /// Counter-clockwise rotation around the pivot, in radians
+    pub rotation: f32,
+    pub size: [f32; 2],
+    /// Point the sprite is positioned and rotated around, from (0, 0) at the
+    /// bottom-left corner to (1, 1) at the top-right corner
+    pub pivot: [f32; 2],
+    pub tint: [f32; 4],
+    /// Region of the texture to show, as `[u_min, v_min, u_max, v_max]`
+    pub uv_rect: [f32; 4],
+    /// Sprites on higher layers are drawn over lower ones
+    pub layer: i32,
+}
+
+impl Sprite {
+    pub fn new(texture: SpriteTextureId, position: [f32; 2], size: [f32; 2]) -> Self {
+        Self {
+            texture,
+            position,
+            rotation: 0.0,
+            size,
+            pivot: [0.5, 0.5],
+            tint: [1.0, 1.0, 1.0, 1.0],
+            uv_rect: [0.0, 0.0, 1.0, 1.0],
+            layer: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SpriteInstance {
+    position: [f32; 2],
+    size: [f32; 2],
+    pivot: [f32; 2],
+    rotation: f32,
+    uv_rect: [f32; 4],
+    tint: [f32; 4],
+}
+
+impl From<&Sprite> for SpriteInstance {
+    fn from(sprite: &Sprite) -> Self {
+        Self {
+            position: sprite.position,
+            size: sprite.size,
+            pivot: sprite.pivot,
+            rotation: sprite.rotation,
+            uv_rect: sprite.uv_rect,
+            tint: sprite.tint,
+        }
+    }
+}
+
+impl model::Vertex for SpriteInstance {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<SpriteInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 7]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 11]>() as wgpu::BufferAddress,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Consecutive instances sharing a texture, drawn with a single call
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Batch {
+    texture: SpriteTextureId,
+    instances: Range<u32>,
+}
+
+/// Orders sprites by layer then texture and merges runs of the same texture
+fn build_batches(sprites: &mut [Sprite]) -> (Vec<SpriteInstance>, Vec<Batch>) {
+    // The sort is stable so sprites submitted in the same layer with the
+    // same texture keep their submission order.
+    sprites.sort_by_key(|s| (s.layer, s.texture));
+
+    let instances = sprites.iter().map(SpriteInstance::from).collect();
+    let mut batches: Vec<Batch> = Vec::new();
+    for (i, sprite) in sprites.iter().enumerate() {
+        let i = i as u32;
+        match batches.last_mut() {
+            Some(batch) if batch.texture == sprite.texture => batch.instances.end = i + 1,
+            _ => batches.push(Batch {
+                texture: sprite.texture,
+                instances: i..i + 1,
+            }),
+        }
+    }
+
+    (instances, batches)
+}
+
+/// Collects the sprites drawn during a frame and renders them in as few
+/// draw calls as possible.
+pub struct SpriteBatch {
+    textures: Vec<(Handle<texture::Texture>, wgpu::BindGroup)>,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+    sprites: Vec<Sprite>,
+    batches: Vec<Batch>,
+}
+
+impl SpriteBatch {
+    const INITIAL_CAPACITY: usize = 256;
+
+    pub fn new(device: &wgpu::Device, global_bind_layout: &render::GlobalBindLayout) -> Self {
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sprite Camera Buffer"),
+            contents: bytemuck::cast_slice(&[CameraUniform::new()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: global_bind_layout.get_camera_bind_layout(),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+            label: Some("sprite_camera_bind_group"),
+        });
+
+        Self {
+            textures: Vec::new(),
+            camera_buffer,
+            camera_bind_group,
+            instance_buffer: Self::create_instance_buffer(device, Self::INITIAL_CAPACITY),
+            instance_capacity: Self::INITIAL_CAPACITY,
+            sprites: Vec::new(),
+            batches: Vec::new(),
+        }
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sprite Instance Buffer"),
+            size: (capacity * std::mem::size_of::<SpriteInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn add_texture(
+        &mut self,
+        device: &wgpu::Device,
+        global_bind_layout: &render::GlobalBindLayout,
+        texture: Handle<texture::Texture>,
+    ) -> SpriteTextureId {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: global_bind_layout.get_simple_texture_bind_layout(),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("sprite_texture_bind_group"),
+        });
+        self.textures.push((texture, bind_group));
+        SpriteTextureId(self.textures.len() - 1)
+    }
+
+    /// Queues a sprite for this frame
+    pub fn draw(&mut self, sprite: Sprite) {
+        self.sprites.push(sprite);
+    }
+
+    /// Sorts and uploads the queued sprites. The queue is emptied, sprites
+    /// have to be drawn again every frame.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &camera::OrthographicCamera,
+    ) {
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.view_proj = camera.calc_matrix().into();
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[camera_uniform]),
+        );
+
+        let (instances, batches) = build_batches(&mut self.sprites);
+        self.sprites.clear();
+        self.batches = batches;
+
+        if instances.len() > self.instance_capacity {
+            self.instance_capacity = instances.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.instance_capacity);
+        }
+        if !instances.is_empty() {
+            queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instances));
+        }
+    }
+
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a render::Pipelines,
+    ) {
+        if self.batches.is_empty() {
+            return;
+        }
+
+        render_pass.set_pipeline(pipelines.get_sprite_pipeline());
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        for batch in &self.batches {
+            render_pass.set_bind_group(1, &self.textures[batch.texture.0].1, &[]);
+            render_pass.draw(0..6, batch.instances.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_by_layer_then_texture() {
+        let a = SpriteTextureId(0);
+        let b = SpriteTextureId(1);
+        let sprite = |texture, layer| Sprite {
+            layer,
+            ..Sprite::new(texture, [0.0, 0.0], [1.0, 1.0])
+        };
+        let mut sprites = vec![
+            sprite(b, 1),
+            sprite(a, 0),
+            sprite(b, 0),
+            sprite(a, 0),
+            sprite(a, 1),
+        ];
+
+        let (instances, batches) = build_batches(&mut sprites);
+
+        assert_eq!(instances.len(), 5);
+        assert_eq!(
+            batches,
+            vec![
+                Batch {
+                    texture: a,
+                    instances: 0..2
+                },
+                Batch {
+                    texture: b,
+                    instances: 2..3
+                },
+                Batch {
+                    texture: a,
+                    instances: 3..4
+                },
+                Batch {
+                    texture: b,
+                    instances: 4..5
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! Tangent frames following MikkTSpace, the convention normal maps are baked
+//! with, so that they shade without seams
+
+use cgmath::{InnerSpace, Vector2, Vector3, Zero};
+
+use crate::model::ModelVertex;
+
+/// Fills the tangent and bitangent of every vertex from the positions,
+/// normals and texture coordinates of the triangles using it.
+///
+/// As in MikkTSpace, face tangents are projected on the plane of the vertex
+/// normal and weighted by the angle of the triangle at the vertex, and vertices
+/// shared by triangles of opposite handedness, where the UVs are mirrored, are
+/// split. Vertices only used by triangles with degenerate UVs get an arbitrary
+/// tangent perpendicular to their normal.
+///
+/// The bitangent points towards decreasing v, as wgpu puts the origin of
+/// texture coordinates at the top left.
+pub fn generate_tangents(vertices: &mut Vec<ModelVertex>, indices: &mut [u32]) {
+    // Tangents summed over the triangles keeping and mirroring the UVs
+    let mut sums = vec![[Vector3::zero(); 2]; vertices.len()];
+    let mut used = vec![[false; 2]; vertices.len()];
+    let mut handedness = Vec::with_capacity(indices.len() / 3);
+
+    for corners in indices.chunks_exact(3) {
+        let vertex = |i: usize| &vertices[corners[i] as usize];
+        let position = |i: usize| Vector3::from(vertex(i).position);
+        let uv = |i: usize| Vector2::from(vertex(i).tex_coords);
+
+        let (edge1, edge2) = (position(1) - position(0), position(2) - position(0));
+        let (delta_uv1, delta_uv2) = (uv(1) - uv(0), uv(2) - uv(0));
+        let determinant = delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x;
+        // Direction of increasing u, the determinant only scales it
+        let tangent = (edge1 * delta_uv2.y - edge2 * delta_uv1.y) * determinant.signum();
+        if determinant.abs() < f32::MIN_POSITIVE || !tangent_is_usable(tangent) {
+            handedness.push(None);
+            continue;
+        }
+        let side = usize::from(determinant < 0.0);
+        handedness.push(Some(side));
+
+        for i in 0..3 {
+            let normal = Vector3::from(vertex(i).normal);
+            let Some(tangent) = project(tangent, normal) else {
+                continue;
+            };
+            let angle = match (
+                project(position((i + 1) % 3) - position(i), normal),
+                project(position((i + 2) % 3) - position(i), normal),
+            ) {
+                (Some(a), Some(b)) => a.dot(b).clamp(-1.0, 1.0).acos(),
+                _ => 0.0,
+            };
+            sums[corners[i] as usize][side] += tangent * angle;
+            used[corners[i] as usize][side] = true;
+        }
+    }
+
+    // Mirrored triangles get their own copy of vertices shared with others
+    let mut mirrored_copies = vec![None; vertices.len()];
+    for (corners, side) in indices.chunks_exact_mut(3).zip(&handedness) {
+        if *side != Some(1) {
+            continue;
+        }
+        for index in corners {
+            if used[*index as usize][0] {
+                *index = *mirrored_copies[*index as usize].get_or_insert_with(|| {
+                    vertices.push(vertices[*index as usize]);
+                    vertices.len() as u32 - 1
+                });
+            }
+        }
+    }
+
+    for (index, copy) in mirrored_copies.into_iter().enumerate() {
+        let side = usize::from(!used[index][0] && used[index][1]);
+        set_tangent(&mut vertices[index], sums[index][side], side);
+        if let Some(copy) = copy {
+            set_tangent(&mut vertices[copy as usize], sums[index][1], 1);
+        }
+    }
+}
+
+fn tangent_is_usable(tangent: Vector3<f32>) -> bool {
+    let length = tangent.magnitude2();
+    length.is_finite() && length > 0.0
+}
+
+/// Normalized part of `v` perpendicular to `normal`
+fn project(v: Vector3<f32>, normal: Vector3<f32>) -> Option<Vector3<f32>> {
+    let projected = v - normal * normal.dot(v);
+    tangent_is_usable(projected).then(|| projected.normalize())
+}
+
+fn set_tangent(vertex: &mut ModelVertex, sum: Vector3<f32>, side: usize) {
+    let normal = Vector3::from(vertex.normal);
+    let tangent = project(sum, normal).unwrap_or_else(|| {
+        let axis = if normal.x.abs() < 0.9 {
+            Vector3::unit_x()
+        } else {
+            Vector3::unit_y()
+        };
+        project(axis, normal).unwrap_or(axis)
+    });
+    let sign = if side == 0 { 1.0 } else { -1.0 };
+    vertex.tangent = tangent.into();
+    vertex.bitangent = (normal.cross(tangent) * -sign).into();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(position: [f32; 3], tex_coords: [f32; 2]) -> ModelVertex {
+        ModelVertex {
+            position,
+            tex_coords,
+            normal: [0.0, 0.0, 1.0],
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        }
+    }
+
+    fn assert_near(a: [f32; 3], b: [f32; 3]) {
+        assert!(
+            (Vector3::from(a) - Vector3::from(b)).magnitude() < 1e-5,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn tangents_follow_the_uvs() {
+        let mut vertices = vec![
+            vertex([0.0, 0.0, 0.0], [0.0, 0.0]),
+            vertex([1.0, 0.0, 0.0], [1.0, 0.0]),
+            vertex([0.0, 1.0, 0.0], [0.0, 1.0]),
+        ];
+        let mut indices = vec![0, 1, 2];
+        generate_tangents(&mut vertices, &mut indices);
+        for vertex in &vertices {
+            assert_near(vertex.tangent, [1.0, 0.0, 0.0]);
+            assert_near(vertex.bitangent, [0.0, -1.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn degenerate_uvs_give_perpendicular_tangents() {
+        let mut vertices = vec![
+            vertex([0.0, 0.0, 0.0], [0.5, 0.5]),
+            vertex([1.0, 0.0, 0.0], [0.5, 0.5]),
+            vertex([0.0, 1.0, 0.0], [0.5, 0.5]),
+        ];
+        let mut indices = vec![0, 1, 2];
+        generate_tangents(&mut vertices, &mut indices);
+        for vertex in &vertices {
+            let tangent = Vector3::from(vertex.tangent);
+            assert!((tangent.magnitude() - 1.0).abs() < 1e-5);
+            assert!(tangent.dot(vertex.normal.into()).abs() < 1e-5);
+            assert!(vertex.bitangent.iter().all(|x| x.is_finite()));
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_split_shared_vertices() {
+        // Two triangles sharing the edge x = 0, the right one mirrored in u
+        let mut vertices = vec![
+            vertex([0.0, 0.0, 0.0], [1.0, 0.0]),
+            vertex([0.0, 1.0, 0.0], [1.0, 1.0]),
+            vertex([-1.0, 0.0, 0.0], [0.0, 0.0]),
+            vertex([1.0, 0.0, 0.0], [0.0, 0.0]),
+        ];
+        let mut indices = vec![2, 0, 1, 0, 3, 1];
+        generate_tangents(&mut vertices, &mut indices);
+
+        assert_eq!(vertices.len(), 6);
+        assert_eq!(indices, vec![2, 0, 1, 4, 3, 5]);
+        assert_near(vertices[0].tangent, [1.0, 0.0, 0.0]);
+        assert_near(vertices[4].tangent, [-1.0, 0.0, 0.0]);
+        // Both halves agree on the direction of v
+        assert_near(vertices[0].bitangent, vertices[4].bitangent);
+    }
+}
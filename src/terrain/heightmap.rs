@@ -0,0 +1,73 @@
+use anyhow::{bail, Context};
+
+/// Grid of 16-bit height samples, `width` along X and `depth` along Z
+#[derive(Debug, Clone)]
+pub struct Heightmap {
+    width: u32,
+    depth: u32,
+    samples: Vec<u16>,
+}
+
+impl Heightmap {
+    pub fn new(width: u32, depth: u32, samples: Vec<u16>) -> anyhow::Result<Self> {
+        if width < 2 || depth < 2 {
+            bail!(
+                "Heightmap must be at least 2x2 samples, got {}x{}",
+                width,
+                depth
+            );
+        }
+        if samples.len() != (width * depth) as usize {
+            bail!(
+                "Heightmap of {}x{} needs {} samples, got {}",
+                width,
+                depth,
+                width * depth,
+                samples.len()
+            );
+        }
+
+        Ok(Self {
+            width,
+            depth,
+            samples,
+        })
+    }
+
+    /// Decodes an image, ideally a 16-bit grayscale PNG. 8-bit images are
+    /// accepted but give visible terraces.
+    pub fn from_image_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let image = image::load_from_memory(bytes).context("Invalid heightmap image")?;
+        let image = image.to_luma16();
+        let (width, depth) = image.dimensions();
+        Self::new(width, depth, image.into_raw())
+    }
+
+    /// Reads headerless little-endian 16-bit samples, row by row
+    pub fn from_raw_bytes(bytes: &[u8], width: u32, depth: u32) -> anyhow::Result<Self> {
+        if !bytes.len().is_multiple_of(2) {
+            bail!("Raw heightmap has an odd number of bytes");
+        }
+        let samples = bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        Self::new(width, depth, samples)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// Height between 0 and 1. Coordinates outside of the map are clamped to
+    /// its edges.
+    pub fn get(&self, x: i64, z: i64) -> f32 {
+        let x = x.clamp(0, self.width as i64 - 1) as usize;
+        let z = z.clamp(0, self.depth as i64 - 1) as usize;
+        self.samples[z * self.width as usize + x] as f32 / u16::MAX as f32
+    }
+}
@@ -0,0 +1,174 @@
+use cgmath::{MetricSpace, Point3};
+
+/// Edges of a chunk, in the order used by `chunk_indices`
+pub const EDGE_NEGATIVE_Z: usize = 0;
+pub const EDGE_POSITIVE_X: usize = 1;
+pub const EDGE_POSITIVE_Z: usize = 2;
+pub const EDGE_NEGATIVE_X: usize = 3;
+
+/// Level of detail of a chunk whose bounds are `min`..`max`. Level 0 is
+/// used within `lod_distance` of the camera, each further level covers twice
+/// the distance of the previous one and halves the resolution.
+pub fn select_lod(
+    camera: Point3<f32>,
+    min: Point3<f32>,
+    max: Point3<f32>,
+    lod_distance: f32,
+    max_lod: u32,
+) -> u32 {
+    let closest = Point3::new(
+        camera.x.clamp(min.x, max.x),
+        camera.y.clamp(min.y, max.y),
+        camera.z.clamp(min.z, max.z),
+    );
+    let distance = camera.distance(closest);
+    if distance < lod_distance || lod_distance <= 0.0 {
+        return 0;
+    }
+    ((distance / lod_distance).log2().floor() as u32 + 1).min(max_lod)
+}
+
+/// Lowers levels of detail until neighbouring chunks are at most one level
+/// apart, which `chunk_indices` needs to stitch them. `lods` is row major,
+/// `width` chunks per row.
+pub fn limit_lod_steps(lods: &mut [u32], width: usize) {
+    let depth = lods.len() / width;
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for z in 0..depth {
+            for x in 0..width {
+                let neighbours = [
+                    (x > 0).then(|| z * width + x - 1),
+                    (x + 1 < width).then(|| z * width + x + 1),
+                    (z > 0).then(|| (z - 1) * width + x),
+                    (z + 1 < depth).then(|| (z + 1) * width + x),
+                ];
+                let limit = neighbours.iter().flatten().map(|&i| lods[i] + 1).min();
+                let lod = &mut lods[z * width + x];
+                if let Some(limit) = limit.filter(|&limit| *lod > limit) {
+                    *lod = limit;
+                    changed = true;
+                }
+            }
+        }
+    }
+}
+
+/// Triangle list over a chunk of `size` by `size` quads, `size + 1` vertices
+/// per row, skipping vertices to draw at the given level of detail.
+///
+/// `edge_lods` holds the level of the neighbour across each edge, at most one
+/// level coarser than the chunk. Vertices on
+/// an edge shared with a coarser neighbour are snapped onto its grid, so both
+/// chunks agree on the edge and no cracks appear between them. Triangles
+/// flattened by the snapping are left out.
+pub fn chunk_indices(size: u32, lod: u32, edge_lods: [u32; 4]) -> Vec<u32> {
+    let step = 1 << lod;
+    let row = size + 1;
+    let edge_step = edge_lods.map(|edge_lod| 1u32 << edge_lod.max(lod));
+    let snap = |x: u32, z: u32| {
+        let mut x = x;
+        let mut z = z;
+        if z == 0 {
+            x = x / edge_step[EDGE_NEGATIVE_Z] * edge_step[EDGE_NEGATIVE_Z];
+        } else if z == size {
+            x = x / edge_step[EDGE_POSITIVE_Z] * edge_step[EDGE_POSITIVE_Z];
+        }
+        if x == 0 {
+            z = z / edge_step[EDGE_NEGATIVE_X] * edge_step[EDGE_NEGATIVE_X];
+        } else if x == size {
+            z = z / edge_step[EDGE_POSITIVE_X] * edge_step[EDGE_POSITIVE_X];
+        }
+        (x as i64, z as i64)
+    };
+
+    let mut indices = Vec::new();
+    for z in (0..size).step_by(step as usize) {
+        for x in (0..size).step_by(step as usize) {
+            let a = snap(x, z);
+            let b = snap(x, z + step);
+            let c = snap(x + step, z);
+            let d = snap(x + step, z + step);
+            for [p0, p1, p2] in [[a, b, c], [c, b, d]] {
+                // Twice the area seen from above, zero when flattened
+                let area = (p1.1 - p0.1) * (p2.0 - p0.0) - (p1.0 - p0.0) * (p2.1 - p0.1);
+                if area != 0 {
+                    indices.extend([p0, p1, p2].map(|(x, z)| z as u32 * row + x as u32));
+                }
+            }
+        }
+    }
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Twice the signed area of each triangle seen from above, positive
+    /// when it faces up
+    fn areas(size: u32, indices: &[u32]) -> Vec<i64> {
+        let row = size as i64 + 1;
+        indices
+            .chunks_exact(3)
+            .map(|t| {
+                let p: Vec<_> = t
+                    .iter()
+                    .map(|&i| (i as i64 % row, i as i64 / row))
+                    .collect();
+                let (ax, az) = (p[1].0 - p[0].0, p[1].1 - p[0].1);
+                let (bx, bz) = (p[2].0 - p[0].0, p[2].1 - p[0].1);
+                az * bx - ax * bz
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stitched_chunks_cover_the_whole_chunk() {
+        let size = 8;
+        for lod in 0..=3 {
+            for mask in 0..16u32 {
+                let edge_lods = [0, 1, 2, 3].map(|edge| (lod + ((mask >> edge) & 1)).min(3));
+                let areas = areas(size, &chunk_indices(size, lod, edge_lods));
+                assert!(areas.iter().all(|&area| area > 0), "{lod} {edge_lods:?}");
+                assert_eq!(areas.iter().sum::<i64>(), 2 * (size * size) as i64);
+            }
+        }
+    }
+
+    #[test]
+    fn edges_match_coarser_neighbour() {
+        let size = 8;
+        let row = size + 1;
+        let fine = chunk_indices(size, 1, [2, 1, 1, 1]);
+        let coarse = chunk_indices(size, 2, [2, 2, 2, 2]);
+        let edge_vertices = |indices: &[u32]| {
+            let mut vertices: Vec<_> = indices.iter().filter(|&&i| i < row).copied().collect();
+            vertices.sort_unstable();
+            vertices.dedup();
+            vertices
+        };
+        assert_eq!(edge_vertices(&fine), vec![0, 4, 8]);
+        assert_eq!(edge_vertices(&fine), edge_vertices(&coarse));
+    }
+
+    #[test]
+    fn neighbours_differ_by_one_level_at_most() {
+        let mut lods = vec![0, 3, 3, 3, 3, 3];
+        limit_lod_steps(&mut lods, 3);
+        assert_eq!(lods, vec![0, 1, 2, 1, 2, 3]);
+    }
+
+    #[test]
+    fn lod_grows_with_distance() {
+        let min = Point3::new(0.0, 0.0, 0.0);
+        let max = Point3::new(10.0, 1.0, 10.0);
+        let lod = |x| select_lod(Point3::new(x, 0.0, 5.0), min, max, 10.0, 3);
+        assert_eq!(lod(5.0), 0);
+        assert_eq!(lod(19.0), 0);
+        assert_eq!(lod(21.0), 1);
+        assert_eq!(lod(41.0), 2);
+        assert_eq!(lod(1000.0), 3);
+    }
+}
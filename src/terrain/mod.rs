@@ -0,0 +1,384 @@
+mod heightmap;
+mod lod;
+
+pub use heightmap::Heightmap;
+
+use std::collections::HashMap;
+
+use anyhow::bail;
+use cgmath::{InnerSpace, Point3, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::{model, render, texture};
+
+#[derive(Debug, Clone, Copy)]
+pub struct TerrainConfig {
+    /// World position of the first heightmap sample
+    pub origin: Point3<f32>,
+    /// World distance between two neighbouring samples
+    pub spacing: f32,
+    /// World height of the highest possible sample
+    pub height_scale: f32,
+    /// Quads along each side of a chunk, must be a power of two
+    pub chunk_size: u32,
+    /// Distance from the camera where chunks start losing detail
+    pub lod_distance: f32,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            origin: Point3::new(0.0, 0.0, 0.0),
+            spacing: 1.0,
+            height_scale: 10.0,
+            chunk_size: 32,
+            lod_distance: 32.0,
+        }
+    }
+}
+
+/// A material blended over the terrain, following one channel of the splat
+/// map: red for the first layer, green for the second and so on.
+pub struct TerrainLayer<'a> {
+    pub material: &'a model::Material,
+    /// World units covered by one repetition of the material textures
+    pub scale: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LayersUniform {
+    scale: [f32; 4],
+    count: u32,
+    _padding: [u32; 3],
+}
+
+struct Chunk {
+    /// Position in chunks from the first one
+    coords: (u32, u32),
+    min: Point3<f32>,
+    max: Point3<f32>,
+    vertex_buffer: wgpu::Buffer,
+    lod: u32,
+}
+
+/// Level of detail of a chunk and of its neighbours, identifying the index
+/// buffer it is drawn with
+type IndexKey = (u32, [u32; 4]);
+
+/// Heightmap terrain split into chunks drawn at a level of detail depending
+/// on their distance to the camera.
+pub struct Terrain {
+    pub lod_distance: f32,
+    chunk_size: u32,
+    chunks_x: u32,
+    chunks_z: u32,
+    chunks: Vec<Chunk>,
+    /// Index buffers shared by every chunk, built the first time a
+    /// combination of levels is needed
+    index_buffers: HashMap<IndexKey, (wgpu::Buffer, u32)>,
+    // Kept alive for the bind group
+    _layers_buffer: wgpu::Buffer,
+    layers_bind_group: wgpu::BindGroup,
+}
+
+impl Terrain {
+    /// Builds the chunks covering the heightmap. Samples past its last row
+    /// and column repeat the edge when the size isn't a multiple of the
+    /// chunk size plus one.
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &render::Pipelines,
+        heightmap: &Heightmap,
+        splat_map: &texture::Texture,
+        layers: &[TerrainLayer],
+        config: TerrainConfig,
+    ) -> anyhow::Result<Self> {
+        if !config.chunk_size.is_power_of_two() {
+            bail!(
+                "Terrain chunk size {} is not a power of two",
+                config.chunk_size
+            );
+        }
+        if layers.is_empty() || layers.len() > render::MAX_TERRAIN_LAYERS {
+            bail!(
+                "Terrain needs between 1 and {} layers, got {}",
+                render::MAX_TERRAIN_LAYERS,
+                layers.len()
+            );
+        }
+
+        let chunk_size = config.chunk_size;
+        let chunks_x = (heightmap.width() - 1).div_ceil(chunk_size);
+        let chunks_z = (heightmap.depth() - 1).div_ceil(chunk_size);
+        let mut chunks = Vec::new();
+        for cz in 0..chunks_z {
+            for cx in 0..chunks_x {
+                chunks.push(Self::build_chunk(device, heightmap, &config, (cx, cz)));
+            }
+        }
+
+        let (layers_buffer, layers_bind_group) =
+            Self::create_layers_bind_group(device, pipelines, splat_map, layers);
+
+        Ok(Self {
+            lod_distance: config.lod_distance,
+            chunk_size,
+            chunks_x,
+            chunks_z,
+            chunks,
+            index_buffers: HashMap::new(),
+            _layers_buffer: layers_buffer,
+            layers_bind_group,
+        })
+    }
+
+    fn build_chunk(
+        device: &wgpu::Device,
+        heightmap: &Heightmap,
+        config: &TerrainConfig,
+        coords: (u32, u32),
+    ) -> Chunk {
+        let size = config.chunk_size;
+        let height = |x: i64, z: i64| heightmap.get(x, z) * config.height_scale;
+        // Gradients use the samples around each vertex, even across chunks,
+        // so shading is continuous over the seams
+        let slope = |a: f32, b: f32| (b - a) / (2.0 * config.spacing);
+
+        let mut vertices = Vec::with_capacity(((size + 1) * (size + 1)) as usize);
+        let mut min = Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Point3::new(f32::MIN, f32::MIN, f32::MIN);
+        for z in 0..=size {
+            for x in 0..=size {
+                let gx = (coords.0 * size + x) as i64;
+                let gz = (coords.1 * size + z) as i64;
+                let position = config.origin
+                    + Vector3::new(
+                        gx as f32 * config.spacing,
+                        height(gx, gz),
+                        gz as f32 * config.spacing,
+                    );
+
+                let dx = slope(height(gx - 1, gz), height(gx + 1, gz));
+                let dz = slope(height(gx, gz - 1), height(gx, gz + 1));
+                let normal = Vector3::new(-dx, 1.0, -dz).normalize();
+                let tangent = Vector3::new(1.0, dx, 0.0).normalize();
+                let bitangent = Vector3::new(0.0, dz, 1.0).normalize();
+
+                vertices.push(model::ModelVertex {
+                    position: position.into(),
+                    tex_coords: [
+                        gx as f32 / (heightmap.width() - 1) as f32,
+                        gz as f32 / (heightmap.depth() - 1) as f32,
+                    ],
+                    normal: normal.into(),
+                    tangent: tangent.into(),
+                    bitangent: bitangent.into(),
+                });
+
+                min = Point3::new(
+                    min.x.min(position.x),
+                    min.y.min(position.y),
+                    min.z.min(position.z),
+                );
+                max = Point3::new(
+                    max.x.max(position.x),
+                    max.y.max(position.y),
+                    max.z.max(position.z),
+                );
+            }
+        }
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("Terrain Chunk {:?} Vertex Buffer", coords)),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        Chunk {
+            coords,
+            min,
+            max,
+            vertex_buffer,
+            lod: 0,
+        }
+    }
+
+    fn create_layers_bind_group(
+        device: &wgpu::Device,
+        pipelines: &render::Pipelines,
+        splat_map: &texture::Texture,
+        layers: &[TerrainLayer],
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        // Unused slots repeat the first layer, the shader gives them no weight
+        let layer = |i: usize| layers.get(i).unwrap_or(&layers[0]);
+
+        let uniform = LayersUniform {
+            scale: [0, 1, 2, 3].map(|i| layer(i).scale),
+            count: layers.len() as u32,
+            _padding: [0; 3],
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Terrain Layers Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        // Material samplers clamp, layers need to repeat over the terrain
+        let layer_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&splat_map.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(&splat_map.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&layer_sampler),
+            },
+        ];
+        for i in 0..render::MAX_TERRAIN_LAYERS {
+            let material = layer(i).material;
+            entries.push(wgpu::BindGroupEntry {
+                binding: 4 + i as u32,
+                resource: wgpu::BindingResource::TextureView(&material.diffuse_texture.view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: (4 + render::MAX_TERRAIN_LAYERS + i) as u32,
+                resource: wgpu::BindingResource::TextureView(&material.normal_texture.view),
+            });
+        }
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipelines.get_terrain_layers_bind_layout(),
+            entries: &entries,
+            label: Some("terrain_layers_bind_group"),
+        });
+        (buffer, bind_group)
+    }
+
+    /// Picks the level of detail of every chunk and prepares the index
+    /// buffers they need
+    pub fn update(&mut self, device: &wgpu::Device, camera_position: Point3<f32>) {
+        let max_lod = self.chunk_size.trailing_zeros();
+        let mut lods: Vec<_> = self
+            .chunks
+            .iter()
+            .map(|chunk| {
+                lod::select_lod(
+                    camera_position,
+                    chunk.min,
+                    chunk.max,
+                    self.lod_distance,
+                    max_lod,
+                )
+            })
+            .collect();
+        lod::limit_lod_steps(&mut lods, self.chunks_x as usize);
+        for (chunk, lod) in self.chunks.iter_mut().zip(lods) {
+            chunk.lod = lod;
+        }
+
+        for i in 0..self.chunks.len() {
+            let key = self.index_key(&self.chunks[i]);
+            if !self.index_buffers.contains_key(&key) {
+                let indices = lod::chunk_indices(self.chunk_size, key.0, key.1);
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("Terrain {:?} Index Buffer", key)),
+                    contents: bytemuck::cast_slice(&indices),
+                    usage: wgpu::BufferUsages::INDEX,
+                });
+                self.index_buffers
+                    .insert(key, (buffer, indices.len() as u32));
+            }
+        }
+    }
+
+    fn index_key(&self, chunk: &Chunk) -> IndexKey {
+        let (cx, cz) = chunk.coords;
+        let neighbour_lod = |x: Option<u32>, z: Option<u32>| match (x, z) {
+            (Some(x), Some(z)) if x < self.chunks_x && z < self.chunks_z => self.chunks
+                [(z * self.chunks_x + x) as usize]
+                .lod
+                .max(chunk.lod),
+            // Terrain borders have nothing to stitch to
+            _ => chunk.lod,
+        };
+
+        let mut edge_lods = [0; 4];
+        edge_lods[lod::EDGE_NEGATIVE_Z] = neighbour_lod(Some(cx), cz.checked_sub(1));
+        edge_lods[lod::EDGE_POSITIVE_X] = neighbour_lod(Some(cx + 1), Some(cz));
+        edge_lods[lod::EDGE_POSITIVE_Z] = neighbour_lod(Some(cx), Some(cz + 1));
+        edge_lods[lod::EDGE_NEGATIVE_X] = neighbour_lod(cx.checked_sub(1), Some(cz));
+        (chunk.lod, edge_lods)
+    }
+
+    /// Triangles drawn with the current levels of detail
+    pub fn triangle_count(&self) -> u32 {
+        self.chunks
+            .iter()
+            .filter_map(|chunk| self.index_buffers.get(&self.index_key(chunk)))
+            .map(|(_, count)| count / 3)
+            .sum()
+    }
+
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a render::Pipelines,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        cluster_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(pipelines.get_terrain_pipeline());
+        render_pass.set_bind_group(3, cluster_bind_group, &[]);
+        self.draw(render_pass, camera_bind_group, light_bind_group);
+    }
+
+    /// Draws the terrain into the G-buffer of the deferred renderer
+    pub fn render_geometry<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a render::DeferredPipelines,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(pipelines.get_terrain_geometry_pipeline());
+        self.draw(render_pass, camera_bind_group, light_bind_group);
+    }
+
+    fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, light_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.layers_bind_group, &[]);
+        for chunk in &self.chunks {
+            // Chunks are only drawn once `update` prepared their indices
+            if let Some((index_buffer, count)) = self.index_buffers.get(&self.index_key(chunk)) {
+                render_pass.set_vertex_buffer(0, chunk.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..*count, 0, 0..1);
+            }
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use std::{collections::HashMap, num::NonZeroU32};
+
+use rusttype::{point, Font, GlyphId, Scale};
+
+use crate::texture;
+
+use super::FontId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GlyphKey {
+    pub font: FontId,
+    pub glyph: GlyphId,
+    /// Size in quarter pixels, so close sizes share their rasterisation
+    pub size: u32,
+}
+
+impl GlyphKey {
+    pub fn new(font: FontId, glyph: GlyphId, size: f32) -> Self {
+        Self {
+            font,
+            glyph,
+            size: (size * 4.0).round() as u32,
+        }
+    }
+
+    fn scale(&self) -> Scale {
+        Scale::uniform(self.size as f32 / 4.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AtlasEntry {
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+    /// Top-left corner of the glyph bitmap relative to the pen position
+    pub offset: [f32; 2],
+    pub size: [f32; 2],
+}
+
+#[derive(Debug)]
+pub struct AtlasFull;
+
+struct Shelf {
+    y: u32,
+    height: u32,
+    x: u32,
+}
+
+/// Single channel texture holding rasterised glyphs, packed on shelves as
+/// they are first requested.
+pub struct GlyphAtlas {
+    texture: texture::Texture,
+    size: u32,
+    entries: HashMap<GlyphKey, Option<AtlasEntry>>,
+    shelves: Vec<Shelf>,
+}
+
+impl GlyphAtlas {
+    const PADDING: u32 = 1;
+
+    pub fn new(device: &wgpu::Device, size: u32) -> Self {
+        let format = wgpu::TextureFormat::R8Unorm;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Glyph Atlas"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[format],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture: texture::Texture {
+                texture,
+                view,
+                sampler,
+            },
+            size,
+            entries: HashMap::new(),
+            shelves: Vec::new(),
+        }
+    }
+
+    pub fn texture(&self) -> &texture::Texture {
+        &self.texture
+    }
+
+    /// Forgets every glyph, they will be rasterised again when next requested
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.shelves.clear();
+    }
+
+    /// Returns where the glyph is stored, rasterising it first if needed.
+    /// Glyphs without any pixels, like spaces, have no entry.
+    pub fn get_or_insert(
+        &mut self,
+        queue: &wgpu::Queue,
+        font: &Font,
+        key: GlyphKey,
+    ) -> Result<Option<AtlasEntry>, AtlasFull> {
+        if let Some(entry) = self.entries.get(&key) {
+            return Ok(*entry);
+        }
+
+        let glyph = font
+            .glyph(key.glyph)
+            .scaled(key.scale())
+            .positioned(point(0.0, 0.0));
+        let bounds = match glyph.pixel_bounding_box() {
+            Some(bounds) => bounds,
+            None => {
+                self.entries.insert(key, None);
+                return Ok(None);
+            }
+        };
+
+        let width = bounds.width() as u32;
+        let height = bounds.height() as u32;
+        let (x, y) = self.allocate(width, height).ok_or(AtlasFull)?;
+
+        let mut pixels = vec![0u8; (width * height) as usize];
+        glyph.draw(|gx, gy, coverage| {
+            pixels[(gy * width + gx) as usize] = (coverage * 255.0).round() as u8;
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+            },
+            &pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(width),
+                rows_per_image: NonZeroU32::new(height),
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        let texel = 1.0 / self.size as f32;
+        let entry = AtlasEntry {
+            uv_min: [x as f32 * texel, y as f32 * texel],
+            uv_max: [(x + width) as f32 * texel, (y + height) as f32 * texel],
+            offset: [bounds.min.x as f32, bounds.min.y as f32],
+            size: [width as f32, height as f32],
+        };
+        self.entries.insert(key, Some(entry));
+        Ok(Some(entry))
+    }
+
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let padded_width = width + Self::PADDING;
+        let padded_height = height + Self::PADDING;
+        if padded_width > self.size {
+            return None;
+        }
+
+        // Prefer the shelf wasting the least height among those with room left
+        let size = self.size;
+        let shelf = self
+            .shelves
+            .iter_mut()
+            .filter(|s| s.height >= padded_height && s.x + padded_width <= size)
+            .min_by_key(|s| s.height - padded_height);
+        if let Some(shelf) = shelf {
+            let position = (shelf.x, shelf.y);
+            shelf.x += padded_width;
+            return Some(position);
+        }
+
+        let y = self.shelves.last().map_or(0, |s| s.y + s.height);
+        if y + padded_height > self.size {
+            return None;
+        }
+        self.shelves.push(Shelf {
+            y,
+            height: padded_height,
+            x: padded_width,
+        });
+        Some((0, y))
+    }
+}
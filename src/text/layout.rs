@@ -0,0 +1,192 @@
+use rusttype::{Font, GlyphId, Scale};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HorizontalAlign {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TextStyle {
+    /// Height of a line in pixels
+    pub size: f32,
+    pub color: [f32; 4],
+    /// Lines longer than this are wrapped at the last space that fits
+    pub max_width: Option<f32>,
+    pub align: HorizontalAlign,
+    /// Multiplier applied to the font's line height
+    pub line_spacing: f32,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            size: 16.0,
+            color: [1.0, 1.0, 1.0, 1.0],
+            max_width: None,
+            align: HorizontalAlign::Left,
+            line_spacing: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LaidOutGlyph {
+    pub id: GlyphId,
+    /// Pen position on the baseline, relative to the top-left corner of the text
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Debug, Default)]
+pub struct TextLayout {
+    pub glyphs: Vec<LaidOutGlyph>,
+    pub width: f32,
+    pub height: f32,
+}
+
+struct Word {
+    glyphs: Vec<(GlyphId, f32)>,
+    width: f32,
+}
+
+#[derive(Default)]
+struct Line {
+    glyphs: Vec<(GlyphId, f32)>,
+    width: f32,
+}
+
+fn measure_word(font: &Font, scale: Scale, word: &str) -> Word {
+    let mut glyphs = Vec::with_capacity(word.len());
+    let mut x = 0.0;
+    let mut previous = None;
+    for c in word.chars() {
+        let glyph = font.glyph(c).scaled(scale);
+        let id = glyph.id();
+        if let Some(previous) = previous {
+            x += font.pair_kerning(scale, previous, id);
+        }
+        glyphs.push((id, x));
+        x += glyph.h_metrics().advance_width;
+        previous = Some(id);
+    }
+
+    Word { glyphs, width: x }
+}
+
+pub fn layout(font: &Font, text: &str, style: &TextStyle) -> TextLayout {
+    let scale = Scale::uniform(style.size);
+    let v_metrics = font.v_metrics(scale);
+    let line_height =
+        (v_metrics.ascent - v_metrics.descent + v_metrics.line_gap) * style.line_spacing;
+    let space_width = font.glyph(' ').scaled(scale).h_metrics().advance_width;
+
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line = Line::default();
+        for (i, word) in paragraph.split(' ').enumerate() {
+            let word = measure_word(font, scale, word);
+            let start = if i == 0 {
+                0.0
+            } else {
+                line.width + space_width
+            };
+            let overflows = style
+                .max_width
+                .is_some_and(|max_width| start + word.width > max_width);
+
+            // A word wider than the whole line is left overflowing on its own line
+            if overflows && !line.glyphs.is_empty() {
+                lines.push(std::mem::replace(
+                    &mut line,
+                    Line {
+                        glyphs: word.glyphs,
+                        width: word.width,
+                    },
+                ));
+            } else {
+                line.glyphs
+                    .extend(word.glyphs.into_iter().map(|(id, x)| (id, start + x)));
+                line.width = start + word.width;
+            }
+        }
+        lines.push(line);
+    }
+
+    let widest = lines.iter().map(|l| l.width).fold(0.0, f32::max);
+    let width = style.max_width.unwrap_or(widest);
+
+    let mut glyphs = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let offset = match style.align {
+            HorizontalAlign::Left => 0.0,
+            HorizontalAlign::Center => (width - line.width) * 0.5,
+            HorizontalAlign::Right => width - line.width,
+        };
+        let baseline = v_metrics.ascent + line_height * i as f32;
+        glyphs.extend(line.glyphs.iter().map(|(id, x)| LaidOutGlyph {
+            id: *id,
+            x: offset + x,
+            y: baseline,
+        }));
+    }
+
+    TextLayout {
+        glyphs,
+        width,
+        height: line_height * lines.len() as f32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn font() -> Font<'static> {
+        Font::try_from_bytes(include_bytes!("../../res/DejaVuSans.ttf")).unwrap()
+    }
+
+    #[test]
+    fn wraps_at_spaces() {
+        let font = font();
+        let style = TextStyle {
+            max_width: Some(layout(&font, "hello", &TextStyle::default()).width + 1.0),
+            ..Default::default()
+        };
+
+        let text = layout(&font, "hello hello hello", &style);
+
+        let baselines = text
+            .glyphs
+            .iter()
+            .map(|g| g.y)
+            .fold(Vec::<f32>::new(), |mut lines, y| {
+                if lines.last() != Some(&y) {
+                    lines.push(y);
+                }
+                lines
+            });
+        assert_eq!(baselines.len(), 3);
+        assert_eq!(text.glyphs.iter().filter(|g| g.x == 0.0).count(), 3);
+    }
+
+    #[test]
+    fn aligns_lines_within_max_width() {
+        let font = font();
+        let style = TextStyle {
+            max_width: Some(200.0),
+            align: HorizontalAlign::Right,
+            ..Default::default()
+        };
+
+        let text = layout(&font, "right", &style);
+        let natural = layout(&font, "right", &TextStyle::default());
+
+        assert_eq!(text.width, 200.0);
+        let shift = 200.0 - natural.width;
+        for (aligned, natural) in text.glyphs.iter().zip(natural.glyphs.iter()) {
+            assert!((aligned.x - natural.x - shift).abs() < 1e-3);
+        }
+    }
+}
@@ -0,0 +1,359 @@
+mod atlas;
+mod layout;
+
+pub use layout::{layout, HorizontalAlign, TextStyle};
+
+use std::ops::Range;
+
+use cgmath::{InnerSpace, Matrix4, Point3, Vector3};
+use rusttype::Font;
+use wgpu::util::DeviceExt;
+
+use crate::{camera, model, render, CameraUniform};
+use atlas::{AtlasFull, GlyphAtlas, GlyphKey};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FontId(usize);
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TextVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl model::Vertex for TextVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<TextVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+enum Placement {
+    /// Top-left corner in pixels, from the top-left corner of the window
+    Screen([f32; 2]),
+    /// Billboard centered above `position`, `pixel_size` world units per pixel
+    World {
+        position: Point3<f32>,
+        pixel_size: f32,
+    },
+}
+
+struct Section {
+    font: FontId,
+    text: String,
+    style: TextStyle,
+    placement: Placement,
+}
+
+/// Draws text queued during the frame, in screen space on top of the scene
+/// or as camera-facing labels in the world.
+pub struct TextRenderer {
+    fonts: Vec<Font<'static>>,
+    atlas: GlyphAtlas,
+    atlas_bind_group: wgpu::BindGroup,
+    screen_buffer: wgpu::Buffer,
+    screen_bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    vertex_capacity: usize,
+    sections: Vec<Section>,
+    screen_vertices: Range<u32>,
+    world_vertices: Range<u32>,
+}
+
+impl TextRenderer {
+    const ATLAS_SIZE: u32 = 1024;
+    const INITIAL_VERTEX_CAPACITY: usize = 6 * 256;
+
+    pub fn new(device: &wgpu::Device, global_bind_layout: &render::GlobalBindLayout) -> Self {
+        let atlas = GlyphAtlas::new(device, Self::ATLAS_SIZE);
+        let atlas_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: global_bind_layout.get_simple_texture_bind_layout(),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&atlas.texture().view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&atlas.texture().sampler),
+                },
+            ],
+            label: Some("glyph_atlas_bind_group"),
+        });
+
+        let screen_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Text Screen Buffer"),
+            contents: bytemuck::cast_slice(&[CameraUniform::new()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let screen_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: global_bind_layout.get_camera_bind_layout(),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: screen_buffer.as_entire_binding(),
+            }],
+            label: Some("text_screen_bind_group"),
+        });
+
+        Self {
+            fonts: Vec::new(),
+            atlas,
+            atlas_bind_group,
+            screen_buffer,
+            screen_bind_group,
+            vertex_buffer: Self::create_vertex_buffer(device, Self::INITIAL_VERTEX_CAPACITY),
+            vertex_capacity: Self::INITIAL_VERTEX_CAPACITY,
+            sections: Vec::new(),
+            screen_vertices: 0..0,
+            world_vertices: 0..0,
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Text Vertex Buffer"),
+            size: (capacity * std::mem::size_of::<TextVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Loads a TTF or OTF font
+    pub fn add_font(&mut self, bytes: Vec<u8>) -> anyhow::Result<FontId> {
+        let font = Font::try_from_vec(bytes).ok_or_else(|| anyhow::anyhow!("Invalid font data"))?;
+        self.fonts.push(font);
+        Ok(FontId(self.fonts.len() - 1))
+    }
+
+    pub fn measure(&self, font: FontId, text: &str, style: &TextStyle) -> (f32, f32) {
+        let text = layout(&self.fonts[font.0], text, style);
+        (text.width, text.height)
+    }
+
+    pub fn queue_screen(
+        &mut self,
+        font: FontId,
+        text: &str,
+        position: [f32; 2],
+        style: &TextStyle,
+    ) {
+        self.sections.push(Section {
+            font,
+            text: text.to_string(),
+            style: *style,
+            placement: Placement::Screen(position),
+        });
+    }
+
+    pub fn queue_world(
+        &mut self,
+        font: FontId,
+        text: &str,
+        position: Point3<f32>,
+        pixel_size: f32,
+        style: &TextStyle,
+    ) {
+        self.sections.push(Section {
+            font,
+            text: text.to_string(),
+            style: *style,
+            placement: Placement::World {
+                position,
+                pixel_size,
+            },
+        });
+    }
+
+    /// Lays out the queued sections and uploads their glyphs and vertices.
+    /// The queue is emptied, sections have to be queued again every frame.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        screen_size: winit::dpi::PhysicalSize<u32>,
+        view: Matrix4<f32>,
+    ) {
+        let projection = camera::OPENGL_TO_WGPU_MATRIX
+            * cgmath::ortho(
+                0.0,
+                screen_size.width as f32,
+                screen_size.height as f32,
+                0.0,
+                -1.0,
+                1.0,
+            );
+        let mut screen_uniform = CameraUniform::new();
+        screen_uniform.view_proj = projection.into();
+        queue.write_buffer(
+            &self.screen_buffer,
+            0,
+            bytemuck::cast_slice(&[screen_uniform]),
+        );
+
+        // Screen sections go first so both kinds can be drawn as one range each
+        let sections = std::mem::take(&mut self.sections);
+        let (screen, world): (Vec<_>, Vec<_>) = sections
+            .iter()
+            .partition(|s| matches!(s.placement, Placement::Screen(_)));
+
+        let mut vertices = Vec::new();
+        let mut screen_count = 0;
+        let mut result = Ok(());
+        for _ in 0..2 {
+            vertices.clear();
+            result = screen
+                .iter()
+                .try_for_each(|section| self.build_section(queue, section, view, &mut vertices))
+                .and_then(|_| {
+                    screen_count = vertices.len() as u32;
+                    world.iter().try_for_each(|section| {
+                        self.build_section(queue, section, view, &mut vertices)
+                    })
+                });
+            match result {
+                Ok(_) => break,
+                // Start over with an empty atlas holding only this frame's glyphs
+                Err(AtlasFull) => self.atlas.clear(),
+            }
+        }
+        if result.is_err() {
+            log::warn!("Too many glyphs to fit in the atlas, text is skipped this frame");
+            vertices.clear();
+            screen_count = 0;
+        }
+
+        self.screen_vertices = 0..screen_count;
+        self.world_vertices = screen_count..vertices.len() as u32;
+
+        if vertices.len() > self.vertex_capacity {
+            self.vertex_capacity = vertices.len().next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(device, self.vertex_capacity);
+        }
+        if !vertices.is_empty() {
+            queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+        }
+    }
+
+    fn build_section(
+        &mut self,
+        queue: &wgpu::Queue,
+        section: &Section,
+        view: Matrix4<f32>,
+        vertices: &mut Vec<TextVertex>,
+    ) -> Result<(), AtlasFull> {
+        let font = &self.fonts[section.font.0];
+        let text = layout(font, &section.text, &section.style);
+        let color = section.style.color;
+
+        for glyph in &text.glyphs {
+            let key = GlyphKey::new(section.font, glyph.id, section.style.size);
+            let entry = match self.atlas.get_or_insert(queue, font, key)? {
+                Some(entry) => entry,
+                None => continue,
+            };
+
+            // Corners of the quad in pixels, relative to the top-left of the text
+            let left = glyph.x + entry.offset[0];
+            let top = glyph.y + entry.offset[1];
+            let corners = match section.placement {
+                Placement::Screen(origin) => {
+                    // Snap to whole pixels to keep small text crisp
+                    let left = (origin[0] + left).round();
+                    let top = (origin[1] + top).round();
+                    let right = left + entry.size[0];
+                    let bottom = top + entry.size[1];
+                    [
+                        Vector3::new(left, top, 0.0),
+                        Vector3::new(right, top, 0.0),
+                        Vector3::new(right, bottom, 0.0),
+                        Vector3::new(left, bottom, 0.0),
+                    ]
+                }
+                Placement::World {
+                    position,
+                    pixel_size,
+                } => {
+                    // The rows of the view matrix are the camera axes in world space
+                    let right = Vector3::new(view.x.x, view.y.x, view.z.x).normalize();
+                    let up = Vector3::new(view.x.y, view.y.y, view.z.y).normalize();
+                    let origin = Vector3::new(position.x, position.y, position.z);
+                    let to_world = |x: f32, y: f32| {
+                        origin
+                            + right * (x - text.width * 0.5) * pixel_size
+                            + up * (text.height - y) * pixel_size
+                    };
+                    [
+                        to_world(left, top),
+                        to_world(left + entry.size[0], top),
+                        to_world(left + entry.size[0], top + entry.size[1]),
+                        to_world(left, top + entry.size[1]),
+                    ]
+                }
+            };
+
+            let uv = [
+                [entry.uv_min[0], entry.uv_min[1]],
+                [entry.uv_max[0], entry.uv_min[1]],
+                [entry.uv_max[0], entry.uv_max[1]],
+                [entry.uv_min[0], entry.uv_max[1]],
+            ];
+            for i in [0, 3, 2, 0, 2, 1] {
+                vertices.push(TextVertex {
+                    position: corners[i].into(),
+                    tex_coords: uv[i],
+                    color,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a render::Pipelines,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.screen_vertices.is_empty() && self.world_vertices.is_empty() {
+            return;
+        }
+
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_bind_group(1, &self.atlas_bind_group, &[]);
+
+        if !self.world_vertices.is_empty() {
+            render_pass.set_pipeline(pipelines.get_text_world_pipeline());
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.draw(self.world_vertices.clone(), 0..1);
+        }
+
+        if !self.screen_vertices.is_empty() {
+            render_pass.set_pipeline(pipelines.get_text_screen_pipeline());
+            render_pass.set_bind_group(0, &self.screen_bind_group, &[]);
+            render_pass.draw(self.screen_vertices.clone(), 0..1);
+        }
+    }
+}
@@ -2,6 +2,8 @@ use anyhow::*;
 use image::GenericImageView;
 use std::num::NonZeroU32;
 
+use crate::assets::Handle;
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
@@ -29,7 +31,7 @@ impl Texture {
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-            view_formats: &[Self::DEPTH_FORMAT]
+            view_formats: &[Self::DEPTH_FORMAT],
         };
         let texture = device.create_texture(&desc);
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
@@ -51,6 +53,18 @@ impl Texture {
         }
     }
 
+    /// Single texel texture of `color`
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+        is_normal_map: bool,
+    ) -> Result<Self> {
+        let img = image::RgbaImage::from_pixel(1, 1, image::Rgba(color));
+        Self::from_image(device, queue, &img.into(), Some(label), is_normal_map)
+    }
+
     #[allow(dead_code)]
     pub fn from_bytes(
         device: &wgpu::Device,
@@ -71,8 +85,6 @@ impl Texture {
         is_normal_map: bool,
     ) -> Result<Self> {
         let dimensions = img.dimensions();
-        let rgba = img.to_rgba8();
-
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
@@ -91,17 +103,46 @@ impl Texture {
             dimension: wgpu::TextureDimension::D2,
             format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[format]
+            view_formats: &[format],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
         });
 
+        let texture = Self {
+            texture,
+            view,
+            sampler,
+        };
+        texture.write_image(queue, img);
+        Ok(texture)
+    }
+
+    /// Replaces the texels with those of `img`, keeping the bind groups
+    /// using the texture valid. Returns false, leaving the texture as is,
+    /// when `img` isn't the size of the texture.
+    pub fn write_image(&self, queue: &wgpu::Queue, img: &image::DynamicImage) -> bool {
+        let dimensions = img.dimensions();
+        let size = self.texture.size();
+        if (size.width, size.height) != dimensions {
+            return false;
+        }
         queue.write_texture(
             wgpu::ImageCopyTexture {
                 aspect: wgpu::TextureAspect::All,
-                texture: &texture,
+                texture: &self.texture,
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            &rgba,
+            &img.to_rgba8(),
             wgpu::ImageDataLayout {
                 offset: 0,
                 bytes_per_row: NonZeroU32::new(4 * dimensions.0),
@@ -109,22 +150,51 @@ impl Texture {
             },
             size,
         );
+        true
+    }
+}
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+/// Textures standing in for the maps a material doesn't have, shared by all
+/// the materials missing them
+pub struct DefaultTextures {
+    pub white: Handle<Texture>,
+    /// Normal map leaving the surface normal as is
+    pub flat_normal: Handle<Texture>,
+    /// Stands out on assets that are still loading
+    pub checker: Handle<Texture>,
+}
 
+impl DefaultTextures {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Self> {
         Ok(Self {
-            texture,
-            view,
-            sampler,
+            white: Handle::new(Texture::from_color(
+                device,
+                queue,
+                [255; 4],
+                "default_white_texture",
+                false,
+            )?),
+            flat_normal: Handle::new(Texture::from_color(
+                device,
+                queue,
+                [128, 128, 255, 255],
+                "default_normal_texture",
+                true,
+            )?),
+            checker: Handle::new(Texture::from_image(
+                device,
+                queue,
+                &image::RgbaImage::from_fn(64, 64, |x, y| {
+                    if (x / 8 + y / 8) % 2 == 0 {
+                        image::Rgba([255, 0, 255, 255])
+                    } else {
+                        image::Rgba([32, 32, 32, 255])
+                    }
+                })
+                .into(),
+                Some("default_checker_texture"),
+                false,
+            )?),
         })
     }
 }
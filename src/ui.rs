@@ -0,0 +1,112 @@
+use winit::{event::WindowEvent, event_loop::EventLoopWindowTarget, window::Window};
+
+/// Immediate-mode UI drawn with egui on top of the rendered scene.
+pub struct Ui {
+    context: egui::Context,
+    state: egui_winit::State,
+    renderer: egui_wgpu::Renderer,
+    paint_jobs: Vec<egui::ClippedPrimitive>,
+    textures_delta: egui::TexturesDelta,
+    /// Textures released by the last frame, freed once it has been drawn
+    textures_to_free: Vec<egui::TextureId>,
+    screen_descriptor: egui_wgpu::renderer::ScreenDescriptor,
+}
+
+impl Ui {
+    pub fn new<T>(
+        event_loop: &EventLoopWindowTarget<T>,
+        window: &Window,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let mut state = egui_winit::State::new(event_loop);
+        state.set_pixels_per_point(window.scale_factor() as f32);
+        state.set_max_texture_side(device.limits().max_texture_dimension_2d as usize);
+
+        Self {
+            context: egui::Context::default(),
+            state,
+            renderer: egui_wgpu::Renderer::new(device, config.format, None, 1),
+            paint_jobs: Vec::new(),
+            textures_delta: egui::TexturesDelta::default(),
+            textures_to_free: Vec::new(),
+            screen_descriptor: egui_wgpu::renderer::ScreenDescriptor {
+                size_in_pixels: [config.width, config.height],
+                pixels_per_point: window.scale_factor() as f32,
+            },
+        }
+    }
+
+    /// Forwards a window event to the UI. Returns true when the UI used it,
+    /// in which case it must not reach the rest of the application.
+    pub fn on_event(&mut self, event: &WindowEvent) -> bool {
+        self.state.on_event(&self.context, event).consumed
+    }
+
+    /// Builds the UI for this frame
+    pub fn run(&mut self, window: &Window, build: impl FnOnce(&egui::Context)) {
+        let input = self.state.take_egui_input(window);
+        let output = self.context.run(input, build);
+        self.state
+            .handle_platform_output(window, &self.context, output.platform_output);
+
+        self.paint_jobs = self.context.tessellate(output.shapes);
+        self.textures_delta.append(output.textures_delta);
+    }
+
+    /// Uploads the textures and geometry of the last built UI
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+    ) {
+        self.screen_descriptor = egui_wgpu::renderer::ScreenDescriptor {
+            size_in_pixels: [config.width, config.height],
+            pixels_per_point: self.state.pixels_per_point(),
+        };
+
+        for id in self.textures_to_free.drain(..) {
+            self.renderer.free_texture(&id);
+        }
+        for (id, image_delta) in &self.textures_delta.set {
+            self.renderer
+                .update_texture(device, queue, *id, image_delta);
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("UI Upload Encoder"),
+        });
+        let mut command_buffers = self.renderer.update_buffers(
+            device,
+            queue,
+            &mut encoder,
+            &self.paint_jobs,
+            &self.screen_descriptor,
+        );
+        command_buffers.push(encoder.finish());
+        queue.submit(command_buffers);
+
+        self.textures_to_free = std::mem::take(&mut self.textures_delta.free);
+        self.textures_delta.set.clear();
+    }
+
+    /// Draws the UI over the content of `view`
+    pub fn render(&self, view: &wgpu::TextureView, encoder: &mut wgpu::CommandEncoder) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("UI Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        self.renderer
+            .render(&mut render_pass, &self.paint_jobs, &self.screen_descriptor);
+    }
+}